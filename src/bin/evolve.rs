@@ -0,0 +1,81 @@
+extern crate spellcraft;
+extern crate rand;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+use std::rc::Rc;
+use rand::{Isaac64Rng, SeedableRng};
+use spellcraft::evolve::{self, Settings, Fitness, History, DamagePerMana, FunVariance, ClosestToPower};
+use spellcraft::profile::Profile;
+
+// Evolves spells headless and writes the best, with their lineage, to a file.
+//
+//   evolve [--fitness damage_per_mana | fun_variance | power=<score>]
+//          [--profile <file>] [--seed <n>] [--population <n>]
+//          [--generations <n>] [--best <n>] [--out <file>]
+
+const USAGE: &str = "usage: evolve [--fitness damage_per_mana | fun_variance | power=<score>] \
+[--profile <file>] [--seed <n>] [--population <n>] [--generations <n>] [--best <n>] [--out <file>]";
+
+fn main() {
+    let mut settings = Settings::new(Profile::standard());
+    let mut fitness = "damage_per_mana".to_string();
+    let mut best = 5;
+    let mut out = "evolved.sexp".to_string();
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().unwrap_or_else(|| fail(&format!("`{}` needs a value", flag)));
+        match &flag[..] {
+            "--fitness" => fitness = value,
+            "--profile" => settings.profile = Rc::new(load_profile(&value)),
+            "--seed" => settings.seed = number(&flag, &value),
+            "--population" => settings.population = number(&flag, &value),
+            "--generations" => settings.generations = number(&flag, &value),
+            "--best" => best = number(&flag, &value),
+            "--out" => out = value,
+            _ => fail(&format!("unknown flag `{}`", flag)),
+        }
+    }
+    if settings.population == 0 {
+        fail("the population can't be empty");
+    }
+    let history = match &fitness[..] {
+        "damage_per_mana" => run(&settings, &DamagePerMana),
+        "fun_variance" => run(&settings, &FunVariance),
+        f if f.starts_with("power=") => run(&settings, &ClosestToPower(number("--fitness", &f["power=".len()..]))),
+        f => fail(&format!("unknown fitness `{}`", f)),
+    };
+    if let Err(e) = File::create(&out).and_then(|mut f| f.write_all(history.report(best).as_bytes())) {
+        fail(&format!("couldn't write {}: {}", out, e));
+    }
+    eprintln!("wrote the best {} to {}", best.min(history.population.len()), out);
+}
+
+fn run<F: Fitness>(settings: &Settings, fitness: &F) -> History {
+    let mut rng = Isaac64Rng::from_seed(&[settings.seed]);
+    evolve::evolve(settings, fitness, &mut rng, |h| {
+        let scores: Vec<f32> = h.population.iter().map(|&id| h.individuals[id].fitness).collect();
+        let mean = scores.iter().sum::<f32>() / scores.len() as f32;
+        eprintln!("generation {}: best {:.3}, mean {:.3}, {} spells so far",
+            h.generation, scores[0], mean, h.individuals.len());
+    })
+}
+
+fn load_profile(path: &str) -> Profile {
+    let mut src = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+        fail(&format!("couldn't read {}: {}", path, e));
+    }
+    Profile::parse(&src).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+}
+
+fn number<T: ::std::str::FromStr>(flag: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| fail(&format!("`{}` is not a valid number for {}", value, flag)))
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}\n{}", msg, USAGE);
+    process::exit(1)
+}
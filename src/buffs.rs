@@ -1,4 +1,15 @@
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+use std::fmt;
+use std::error::Error;
+use std::rc::Rc;
+
+use magic::*;
+use sexp::{Sexp, ParseError};
+use spell_text::Text;
+
+// tiers run from 1 up to this
+pub const MAX_TIER: u8 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Buff {
     // ASSORTED
     Swarm,      // surrounded by biting insects. taking damage over time
@@ -53,9 +64,39 @@ pub enum Buff {
     Energized, // cancels tired. 
 }
 
+impl Buff {
+    // declaration order, so that `buff as usize` indexes it
+    pub const ALL: [Buff; 36] = {
+        use self::Buff::*;
+        [
+            Swarm, Mute, Wet, Stealth,
+            Wary, Wise, Unpredictable, Resolute, Calm, Steady, Panicked,
+            Dizzy, Tired, Confused, Drained, Weary,
+            Bleeding, Hemorrhage, Bruised, Limping, Delicate,
+            Tough,
+            Hot, Burning, Scalded, Warm,
+            Cold, Shivering, Chilled, Cool,
+            Electrified, Toxified, Poisoned, Envenomed,
+            Refreshed, Energized,
+        ]
+    };
 
+    #[inline]
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    // whether bearing it is bad for you, for guessing what a spell is worth
+    pub fn harmful(self) -> bool {
+        use self::Buff::*;
+        !matches!(self,
+            Stealth | Wary | Wise | Unpredictable | Resolute | Calm | Steady | Panicked
+            | Tough | Warm | Cool | Refreshed | Energized)
+    }
+}
 
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum StackingBehaviour {
     Max, // duration is max(new_dur, old_dur)
     Min, // duration is min(new_dur, old_dur)
@@ -64,13 +105,245 @@ pub enum StackingBehaviour {
     Replace, // replace duration and stacks entirely.
 }
 
-pub fn stacking_method(buff: Buff) -> StackingBehaviour {
-    use self::StackingBehaviour::*;
-    use self::Buff::*;
-    match buff {
-        Toxified => Min,
-        Envenomed => IfMax,
-        Electrified => Replace,
-        _ =>  Max
+impl StackingBehaviour {
+    pub const ALL: [StackingBehaviour; 4] = {
+        use self::StackingBehaviour::*;
+        [Max, Min, IfMax, Replace]
+    };
+}
+
+// What a buff does, as data. The events are magic, run by the engine with
+// ent0:bearer, loc0:bearer's position, d0:stacks, d1:tier, for on_tick
+// d2:1 if the bearer is moving or else 0, and for on_excess d2:excess
+// (see assets/buffs.sexp)
+#[derive(Clone, PartialEq, Debug)]
+pub struct BuffDef {
+    pub duration: f32, // seconds a fresh application lasts
+    pub stacking: StackingBehaviour,
+    pub period: f32, // seconds between runs of on_tick
+    pub max_stacks: u8, // stacks past this are EXCESS. see ideas/buffs.md
+    pub on_apply: Vec<Instruction>,
+    pub on_tick: Vec<Instruction>,
+    pub on_remove: Vec<Instruction>,
+    pub on_excess: Vec<Instruction>, // after on_apply, when an application went past max_stacks
+}
+
+impl Default for BuffDef {
+    fn default() -> Self {
+        BuffDef {
+            duration: 10.0,
+            stacking: StackingBehaviour::Max,
+            period: 1.0,
+            max_stacks: 5,
+            on_apply: vec![],
+            on_tick: vec![],
+            on_remove: vec![],
+            on_excess: vec![],
+        }
     }
-}
\ No newline at end of file
+}
+
+// a BuffDef the engine can't run
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum InvalidBuffDef {
+    Duration,  // not positive
+    Period,    // negative
+    MaxStacks, // 0
+}
+
+impl fmt::Display for InvalidBuffDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::InvalidBuffDef::*;
+        match *self {
+            Duration => write!(f, "duration must be positive"),
+            Period => write!(f, "period can't be negative"),
+            MaxStacks => write!(f, "max_stacks must be positive"),
+        }
+    }
+}
+
+impl Error for InvalidBuffDef {}
+
+impl BuffDef {
+    pub fn check(&self) -> Result<(), InvalidBuffDef> {
+        use self::InvalidBuffDef::*;
+        // comparisons are false for NaN, so it's caught too
+        match (self.duration > 0., self.period >= 0., self.max_stacks > 0) {
+            (false, _, _) => Err(Duration),
+            (_, false, _) => Err(Period),
+            (_, _, false) => Err(MaxStacks),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn unbound_loads(&self) -> Vec<UnboundLoad> {
+        let bound = [Slot::E(ESlot(0)), Slot::L(LSlot(0)), Slot::D(DSlot(0)), Slot::D(DSlot(1))];
+        let mut found = unbound_loads_in("on_apply", &bound, &self.on_apply);
+        found.extend(unbound_loads_in("on_remove", &bound, &self.on_remove));
+        let bound = [Slot::E(ESlot(0)), Slot::L(LSlot(0)), Slot::D(DSlot(0)), Slot::D(DSlot(1)), Slot::D(DSlot(2))];
+        found.extend(unbound_loads_in("on_tick", &bound, &self.on_tick));
+        found.extend(unbound_loads_in("on_excess", &bound, &self.on_excess));
+        found
+    }
+}
+
+// one BuffDef for every Buff
+#[derive(Clone, PartialEq, Debug)]
+pub struct BuffTable {
+    defs: Vec<BuffDef>, // in the order of Buff::ALL
+}
+
+const STANDARD_BUFFS: &str = include_str!("../assets/buffs.sexp");
+
+thread_local! {
+    static STANDARD: Rc<BuffTable> = Rc::new(
+        BuffTable::parse(STANDARD_BUFFS).expect("assets/buffs.sexp is valid")
+    );
+}
+
+impl BuffTable {
+    // every buff at BuffDef::default()
+    pub fn empty() -> BuffTable {
+        BuffTable { defs: Buff::ALL.iter().map(|_| BuffDef::default()).collect() }
+    }
+
+    // the definitions in assets/buffs.sexp, as they were when compiled
+    pub fn standard() -> Rc<BuffTable> {
+        STANDARD.with(|t| t.clone())
+    }
+
+    pub fn get(&self, buff: Buff) -> &BuffDef {
+        &self.defs[buff.index()]
+    }
+
+    pub fn set(&mut self, buff: Buff, def: BuffDef) -> Result<(), InvalidBuffDef> {
+        def.check()?;
+        self.defs[buff.index()] = def;
+        Ok(())
+    }
+
+    // buffs that aren't mentioned keep BuffDef::default()
+    pub fn parse(src: &str) -> Result<BuffTable, ParseError> {
+        BuffTable::from_sexp(&Sexp::parse_all(src)?)
+    }
+
+    pub fn from_sexp(entries: &[Sexp]) -> Result<BuffTable, ParseError> {
+        let mut table = BuffTable::empty();
+        let mut seen = vec![false; Buff::ALL.len()];
+        for x in entries {
+            let (name, fields) = x.as_call()?;
+            let buff = Buff::from_sexp(&x.as_list()?[0])?;
+            if seen[buff.index()] {
+                return Err(ParseError::new(x.pos(), format!("`{}` is defined twice", name)));
+            }
+            seen[buff.index()] = true;
+            let mut def = BuffDef::default();
+            for f in fields {
+                let (field, args) = f.as_call()?;
+                let one = || if args.len() == 1 {
+                    Ok(&args[0])
+                } else {
+                    Err(ParseError::new(f.pos(), format!("`{}` takes one value", field)))
+                };
+                match field {
+                    "duration" => def.duration = F32::from_sexp(one()?)?.0,
+                    "period" => def.period = F32::from_sexp(one()?)?.0,
+                    "max_stacks" => def.max_stacks = u8::from_sexp(one()?)?,
+                    "stacking" => {
+                        let s = one()?;
+                        def.stacking = *StackingBehaviour::ALL.iter()
+                            .find(|b| format!("{:?}", b) == s.as_atom().unwrap_or(""))
+                            .ok_or_else(|| ParseError::new(s.pos(), format!("expected Max, Min, IfMax or Replace, found {}", s.describe())))?;
+                    },
+                    "on_apply" => def.on_apply = args.iter().map(Instruction::from_sexp).collect::<Result<_, _>>()?,
+                    "on_tick" => def.on_tick = args.iter().map(Instruction::from_sexp).collect::<Result<_, _>>()?,
+                    "on_remove" => def.on_remove = args.iter().map(Instruction::from_sexp).collect::<Result<_, _>>()?,
+                    "on_excess" => def.on_excess = args.iter().map(Instruction::from_sexp).collect::<Result<_, _>>()?,
+                    _ => return Err(ParseError::new(f.pos(), format!("`{}` has no field `{}`", name, field))),
+                }
+            }
+            if let Some(u) = def.unbound_loads().first() {
+                return Err(ParseError::new(x.pos(), format!("`{}` loads {:?} in {} before defining it", name, u.slot, u.field)));
+            }
+            table.set(buff, def).map_err(|e| ParseError::new(x.pos(), format!("`{}`: {}", name, e)))?;
+        }
+        Ok(table)
+    }
+
+    // the buffs that differ from BuffDef::default(), in the syntax of parse
+    pub fn to_sexp(&self) -> Vec<Sexp> {
+        let default = BuffDef::default();
+        let mut ret = vec![];
+        for (&buff, def) in Buff::ALL.iter().zip(self.defs.iter()) {
+            if *def == default {
+                continue;
+            }
+            let mut fields = vec![buff.to_sexp()];
+            let field = |name: &str, mut x: Vec<Sexp>| {
+                x.insert(0, Sexp::atom(name));
+                Sexp::list(x)
+            };
+            fields.push(field("duration", vec![F32(def.duration).to_sexp()]));
+            fields.push(field("stacking", vec![Sexp::atom(format!("{:?}", def.stacking))]));
+            fields.push(field("period", vec![F32(def.period).to_sexp()]));
+            fields.push(field("max_stacks", vec![def.max_stacks.to_sexp()]));
+            fields.push(field("on_apply", def.on_apply.iter().map(Text::to_sexp).collect()));
+            fields.push(field("on_tick", def.on_tick.iter().map(Text::to_sexp).collect()));
+            fields.push(field("on_remove", def.on_remove.iter().map(Text::to_sexp).collect()));
+            fields.push(field("on_excess", def.on_excess.iter().map(Text::to_sexp).collect()));
+            ret.push(Sexp::list(fields));
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use space::{Space, Player, UPDATES_PER_SEC};
+    use movement_2d::Point;
+    use super::*;
+
+    #[test]
+    fn set_rejects_what_the_engine_cant_run() {
+        let mut table = BuffTable::empty();
+        let bad = |f: fn(&mut BuffDef)| {
+            let mut def = BuffDef::default();
+            f(&mut def);
+            def
+        };
+        assert_eq!(table.set(Buff::Swarm, bad(|d| d.duration = 0.)), Err(InvalidBuffDef::Duration));
+        assert_eq!(table.set(Buff::Swarm, bad(|d| d.duration = f32::NAN)), Err(InvalidBuffDef::Duration));
+        assert_eq!(table.set(Buff::Swarm, bad(|d| d.period = -1.)), Err(InvalidBuffDef::Period));
+        assert_eq!(table.set(Buff::Swarm, bad(|d| d.max_stacks = 0)), Err(InvalidBuffDef::MaxStacks));
+        assert_eq!(*table.get(Buff::Swarm), BuffDef::default());
+        assert_eq!(table.set(Buff::Swarm, bad(|d| d.duration = 3.)), Ok(()));
+        assert_eq!(table.get(Buff::Swarm).duration, 3.);
+    }
+
+    #[test]
+    fn parse_rejects_zero_duration() {
+        assert!(BuffTable::parse("(Swarm (duration 2.0))").is_ok());
+        assert!(BuffTable::parse("(Swarm (duration 0.0))").is_err());
+    }
+
+    // health lost to a stack of Hemorrhage over 4 seconds
+    fn hemorrhage(walking: bool) -> i32 {
+        let mut space = Space::with_seed(1);
+        let tok = space.player_enter(Point(0., 0.), Player::new(1000, 100));
+        space.apply_buff(tok, Buff::Hemorrhage, 1, 1);
+        for _ in 0..4 * UPDATES_PER_SEC {
+            if walking {
+                space.walk(tok, 0., 4.);
+            }
+            space.tick();
+        }
+        1000 - space.health_of(tok).unwrap()
+    }
+
+    #[test]
+    fn hemorrhage_hurts_more_on_the_move() {
+        let (still, walking) = (hemorrhage(false), hemorrhage(true));
+        assert!(still > 0);
+        assert_eq!(walking - still, 4);
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use magic::*;
+use spell_binary;
+
+// CANONICAL FORM
+// One representative for spells that differ only in ways that don't matter
+// for telling them apart:
+//
+//   - operands of commutative operations (And, Or, Nand, Sum, Mult, Max,
+//     Min, Midpoint, Choose and the set operations) are sorted, as are the
+//     two sides of Equals and Equal. MoreThan becomes LessThan
+//   - slots are renumbered in order of first definition, field by field.
+//     the slots the engine binds (eg. ent0:caster) keep their numbers
+//   - no projectile blueprint is shared
+//   - -0.0 becomes 0.0
+//
+// Sorting changes the order operands are evaluated in, and so which of them
+// draws from the rng first. The canonical form is a key to compare and hash
+// spells by, not a spell to cast in their place. Nothing is flattened or
+// folded: simplify first for that.
+
+pub fn spell(s: &Spell) -> Spell {
+    let caster_cursor = [Slot::E(ESlot(0)), Slot::L(LSlot(0))];
+    Spell {
+        on_cast: Numbering::new(&caster_cursor).instructions(&s.on_cast),
+        requires: Box::new(Numbering::new(&caster_cursor).condition(&s.requires)),
+        on_cooldown: Numbering::new(&[Slot::E(ESlot(0))]).instructions(&s.on_cooldown),
+        consumes: {
+            let mut n = Numbering::new(&caster_cursor);
+            s.consumes.iter().map(|r| n.resource(r)).collect()
+        },
+        cast_time: Numbering::new(&caster_cursor).discrete(&s.cast_time),
+        on_cast_start: Numbering::new(&caster_cursor).instructions(&s.on_cast_start),
+        on_interrupt: Numbering::new(&caster_cursor).instructions(&s.on_interrupt),
+    }
+}
+
+pub fn blueprint(bp: &ProjectileBlueprint) -> ProjectileBlueprint {
+    let me = [Slot::E(ESlot(0)), Slot::E(ESlot(1)), Slot::L(LSlot(0))];
+    let hit = [Slot::E(ESlot(0)), Slot::E(ESlot(1)), Slot::E(ESlot(2)), Slot::L(LSlot(0))];
+    ProjectileBlueprint {
+        on_create: Numbering::new(&me).instructions(&bp.on_create),
+        on_collision: Numbering::new(&hit).instructions(&bp.on_collision),
+        collides_with: Numbering::new(&me).entity_set(&bp.collides_with),
+        on_destroy: Numbering::new(&me).instructions(&bp.on_destroy),
+        lifetime: Numbering::new(&me).discrete(&bp.lifetime),
+    }
+}
+
+// the same for spells with the same canonical form, between builds too, so
+// it can be saved. the binary header is left out, so a new version only
+// changes it if the encoding of the spell does
+pub fn hash(s: &Spell) -> u64 {
+    fnv(&spell_binary::value_to_bytes(&spell(s)))
+}
+
+// the first of every group of spells with the same canonical form, in order
+pub fn dedup(spells: &mut Vec<Spell>) {
+    let mut seen: HashMap<u64, Vec<Spell>> = HashMap::new();
+    spells.retain(|s| {
+        let c = spell(s);
+        let same_hash = seen.entry(fnv(&spell_binary::value_to_bytes(&c))).or_default();
+        if same_hash.contains(&c) {
+            false
+        } else {
+            same_hash.push(c);
+            true
+        }
+    });
+}
+
+// FNV-1a, as in Space::checksum
+fn fnv(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+/////////////////////////////////////////////////////////////////////////
+
+// old slot number -> new, per kind of slot. every slot is numbered the first
+// time it's met, in the order the engine meets it. for spells without
+// unbound loads that's when it's first defined
+struct Numbering {
+    e: HashMap<u8, u8>,
+    eset: HashMap<u8, u8>,
+    d: HashMap<u8, u8>,
+    l: HashMap<u8, u8>,
+}
+
+fn renumber(map: &mut HashMap<u8, u8>, x: u8) -> u8 {
+    let next = map.len() as u8;
+    *map.entry(x).or_insert(next)
+}
+
+// sorts by the binary form, which is the same only for equal parts
+fn sort<T: spell_binary::Binary>(v: &mut [T]) {
+    v.sort_by_cached_key(spell_binary::value_to_bytes);
+}
+
+fn ordered<T: spell_binary::Binary>(a: T, b: T) -> (T, T) {
+    if spell_binary::value_to_bytes(&a) <= spell_binary::value_to_bytes(&b) { (a, b) } else { (b, a) }
+}
+
+// -0.0 and 0.0 act alike, but encode differently
+fn f32(F32(x): F32) -> F32 {
+    F32(if x == 0. { 0. } else { x })
+}
+
+impl Numbering {
+    // `bound` are numbered from 0 up in each kind, so they keep their numbers
+    fn new(bound: &[Slot]) -> Self {
+        let mut n = Numbering { e: HashMap::new(), eset: HashMap::new(), d: HashMap::new(), l: HashMap::new() };
+        for &slot in bound {
+            n.slot(slot);
+        }
+        n
+    }
+
+    fn slot(&mut self, slot: Slot) -> Slot {
+        match slot {
+            Slot::E(ESlot(x)) => Slot::E(ESlot(renumber(&mut self.e, x))),
+            Slot::ESet(ESetSlot(x)) => Slot::ESet(ESetSlot(renumber(&mut self.eset, x))),
+            Slot::D(DSlot(x)) => Slot::D(DSlot(renumber(&mut self.d, x))),
+            Slot::L(LSlot(x)) => Slot::L(LSlot(renumber(&mut self.l, x))),
+        }
+    }
+
+    fn e(&mut self, ESlot(x): ESlot) -> ESlot {
+        ESlot(renumber(&mut self.e, x))
+    }
+
+    fn instructions(&mut self, block: &[Instruction]) -> Vec<Instruction> {
+        block.iter().map(|i| self.instruction(i)).collect()
+    }
+
+    fn instruction(&mut self, ins: &Instruction) -> Instruction {
+        use magic::Instruction::*;
+        match *ins {
+            Define(ref def) => Define(self.definition(def)),
+            ITE(ref cond, ref then, ref els) => {
+                let cond = self.condition(cond);
+                let then = self.instructions(then);
+                ITE(cond, then, self.instructions(els))
+            },
+            CallWith(ref def, ref body) => {
+                let def = self.definition(def);
+                CallWith(def, self.instructions(body))
+            },
+            ForEachAs(slot, ref set, ref body) => {
+                let set = self.entity_set(set);
+                let slot = self.e(slot);
+                ForEachAs(slot, set, self.instructions(body))
+            },
+            DestroyWithoutEvent(ref ent) => DestroyWithoutEvent(self.entity(ent)),
+            Destroy(ref ent) => Destroy(self.entity(ent)),
+            MoveEntity(ref ent, ref loc) => {
+                let ent = self.entity(ent);
+                MoveEntity(ent, self.location(loc))
+            },
+            AddResource(ref ent, ref res) => {
+                let ent = self.entity(ent);
+                AddResource(ent, self.resource(res))
+            },
+            AddVelocity(ref ent, ref dir, ref disc) => {
+                let ent = self.entity(ent);
+                let dir = self.direction(dir);
+                AddVelocity(ent, dir, self.discrete(disc))
+            },
+            SpawnProjectileAt(ref bp, ref loc) => SpawnProjectileAt(Rc::new(blueprint(bp)), self.location(loc)),
+            Nothing => Nothing,
+        }
+    }
+
+    // the value is evaluated before the slot is bound
+    fn definition(&mut self, def: &Definition) -> Definition {
+        use magic::Definition::*;
+        match *def {
+            ESet(ESetSlot(s), ref x) => {
+                let x = self.entity_set(x);
+                ESet(ESetSlot(renumber(&mut self.eset, s)), x)
+            },
+            E(s, ref x) => {
+                let x = self.entity(x);
+                E(self.e(s), x)
+            },
+            D(DSlot(s), ref x) => {
+                let x = self.discrete(x);
+                D(DSlot(renumber(&mut self.d, s)), x)
+            },
+            L(LSlot(s), ref x) => {
+                let x = self.location(x);
+                L(LSlot(renumber(&mut self.l, s)), x)
+            },
+        }
+    }
+
+    fn condition(&mut self, cond: &Condition) -> Condition {
+        use magic::Condition::*;
+        match *cond {
+            Nand(ref v) | And(ref v) | Or(ref v) => {
+                let mut v: Vec<_> = v.iter().map(|c| self.condition(c)).collect();
+                sort(&mut v);
+                match *cond {
+                    Nand(_) => Nand(v),
+                    And(_) => And(v),
+                    _ => Or(v),
+                }
+            },
+            Top => Top,
+            Bottom => Bottom,
+            Equals(ref a, ref b) => {
+                let a = self.discrete(a);
+                let (a, b) = ordered(a, self.discrete(b));
+                Equals(a, b)
+            },
+            LessThan(ref a, ref b) => {
+                let a = self.discrete(a);
+                LessThan(a, self.discrete(b))
+            },
+            MoreThan(ref a, ref b) => {
+                let a = self.discrete(a);
+                LessThan(self.discrete(b), a)
+            },
+            EntitySetCmp(ref cmp) => EntitySetCmp(self.entity_set_cmp(cmp)),
+        }
+    }
+
+    fn entity_set_cmp(&mut self, cmp: &EntitySetCmp) -> EntitySetCmp {
+        use magic::EntitySetCmp::*;
+        match *cmp {
+            Nand(ref v) | And(ref v) | Or(ref v) => {
+                let mut v: Vec<_> = v.iter().map(|c| self.entity_set_cmp(c)).collect();
+                sort(&mut v);
+                match *cmp {
+                    Nand(_) => Nand(v),
+                    And(_) => And(v),
+                    _ => Or(v),
+                }
+            },
+            Subset(ref a, ref b) => {
+                let a = self.entity_set(a);
+                Subset(a, self.entity_set(b))
+            },
+            Superset(ref a, ref b) => {
+                let a = self.entity_set(a);
+                Superset(a, self.entity_set(b))
+            },
+            Equal(ref a, ref b) => {
+                let a = self.entity_set(a);
+                let (a, b) = ordered(a, self.entity_set(b));
+                Equal(a, b)
+            },
+            Contains(ref set, ref ent) => {
+                let set = self.entity_set(set);
+                Contains(set, self.entity(ent))
+            },
+        }
+    }
+
+    fn entity_set(&mut self, set: &EntitySet) -> EntitySet {
+        use magic::EntitySet::*;
+        match *set {
+            None(ref v) | And(ref v) | Or(ref v) => {
+                let mut v: Vec<_> = v.iter().map(|s| self.entity_set(s)).collect();
+                sort(&mut v);
+                match *set {
+                    None(_) => None(v),
+                    And(_) => And(v),
+                    _ => Or(v),
+                }
+            },
+            Only(ref ent) => Only(self.entity(ent)),
+            IsInSlot(ESetSlot(s)) => IsInSlot(ESetSlot(renumber(&mut self.eset, s))),
+            WithinRangeOf(ref ent, ref disc) => {
+                let ent = self.entity(ent);
+                WithinRangeOf(ent, self.discrete(disc))
+            },
+            HasMinResource(ref res) => HasMinResource(self.resource(res)),
+            EnemiesOf(ref ent) => EnemiesOf(self.entity(ent)),
+            AllBut(ref ent) => AllBut(self.entity(ent)),
+            IsHuman => IsHuman,
+            IsProjectile => IsProjectile,
+            Empty => Empty,
+            Universe => Universe,
+        }
+    }
+
+    fn entity(&mut self, ent: &Entity) -> Entity {
+        use magic::Entity::*;
+        match *ent {
+            LoadEntity(s) => LoadEntity(self.e(s)),
+            FirstOf(ref set) => FirstOf(Box::new(self.entity_set(set))),
+            Choose(ref set) => Choose(Box::new(self.entity_set(set))),
+            ClosestFrom(ref set, ref loc) => {
+                let set = self.entity_set(set);
+                ClosestFrom(Box::new(set), Box::new(self.location(loc)))
+            },
+        }
+    }
+
+    fn location(&mut self, loc: &Location) -> Location {
+        use magic::Location::*;
+        match *loc {
+            AtEntity(ref ent) => AtEntity(self.entity(ent)),
+            Midpoint(ref v) | Choose(ref v) => {
+                let mut v: Vec<_> = v.iter().map(|l| self.location(l)).collect();
+                sort(&mut v);
+                match loc {
+                    &Midpoint(_) => Midpoint(v),
+                    _ => Choose(v),
+                }
+            },
+            LoadLocation(LSlot(s)) => LoadLocation(LSlot(renumber(&mut self.l, s))),
+        }
+    }
+
+    fn direction(&mut self, dir: &Direction) -> Direction {
+        use magic::Direction::*;
+        match *dir {
+            TowardLocation(ref a, ref b) => {
+                let a = self.location(a);
+                TowardLocation(a, self.location(b))
+            },
+            ConstRad(x) => ConstRad(f32(x)),
+            BetweenRad(x, y) => BetweenRad(f32(x), f32(y)),
+            Choose(ref v) => {
+                let mut v: Vec<_> = v.iter().map(|d| self.direction(d)).collect();
+                sort(&mut v);
+                Choose(v)
+            },
+            ChooseWithinRadOf(ref d, x) => ChooseWithinRadOf(Box::new(self.direction(d)), f32(x)),
+        }
+    }
+
+    fn resource(&mut self, res: &Resource) -> Resource {
+        use magic::Resource::*;
+        match *res {
+            Mana(ref d) => Mana(self.discrete(d)),
+            Health(ref d) => Health(self.discrete(d)),
+            BuffStacks(buff, ref tier, ref d) => {
+                let tier = self.buff_tier(tier);
+                BuffStacks(buff, tier, self.discrete(d))
+            },
+        }
+    }
+
+    fn buff_tier(&mut self, tier: &BuffTier) -> BuffTier {
+        match *tier {
+            BuffTier::Tier(ref d) => BuffTier::Tier(Box::new(self.discrete(d))),
+            BuffTier::AnyTier => BuffTier::AnyTier,
+        }
+    }
+
+    fn discrete(&mut self, d: &Discrete) -> Discrete {
+        use magic::Discrete::*;
+        match *d {
+            Const(x) => Const(x),
+            Range(x, y) => Range(x, y),
+            WithinPercent(x, y) => WithinPercent(x, f32(y)),
+            Div(ref a, ref b) => {
+                let a = self.discrete(a);
+                Div(Box::new(a), Box::new(self.discrete(b)))
+            },
+            Neg(ref a) => Neg(Box::new(self.discrete(a))),
+            Sum(ref v) | Mult(ref v) | Max(ref v) | Min(ref v) | Choose(ref v) => {
+                let mut v: Vec<_> = v.iter().map(|x| self.discrete(x)).collect();
+                sort(&mut v);
+                match *d {
+                    Sum(_) => Sum(v),
+                    Mult(_) => Mult(v),
+                    Max(_) => Max(v),
+                    Min(_) => Min(v),
+                    _ => Choose(v),
+                }
+            },
+            CountStacks(buff, ref tier, ref ent) => {
+                let tier = self.buff_tier(tier);
+                CountStacks(buff, tier, self.entity(ent))
+            },
+            CountDur(buff, ref tier, ref ent) => {
+                let tier = self.buff_tier(tier);
+                CountDur(buff, tier, self.entity(ent))
+            },
+            Cardinality(ref set) => Cardinality(Box::new(self.entity_set(set))),
+            LoadFrom(DSlot(s)) => LoadFrom(DSlot(renumber(&mut self.d, s))),
+            CooldownLeft(i, ref ent) => CooldownLeft(i, self.entity(ent)),
+            Charges(i, ref ent) => Charges(i, self.entity(ent)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use generate;
+    use spell_text::Text;
+    use super::*;
+
+    fn parse(src: &str) -> Spell {
+        Spell::from_text(src).unwrap()
+    }
+
+    #[test]
+    fn idempotent() {
+        for s in generate::samples(20) {
+            let c = spell(&s);
+            assert_eq!(spell(&c), c);
+            assert_eq!(hash(&c), hash(&s));
+        }
+    }
+
+    #[test]
+    fn renamed_and_reordered_hash_alike() {
+        let a = parse("(Spell
+            (on_cast
+                (Define (D_3 (Range 1 10)))
+                (Define (D_5 (Sum (LoadFrom D_3) (Const 2))))
+                (AddResource (LoadEntity E_0) (Health (Max (LoadFrom D_5) (Const 4))))
+                (AddVelocity (LoadEntity E_0) (ConstRad 0.0) (Const 1)))
+            (requires (And (MoreThan (Const 3) (Const 1)) Top))
+            (on_cooldown) (consumes) (cast_time (Const 0)) (on_cast_start) (on_interrupt))");
+        let b = parse("(Spell
+            (on_cast
+                (Define (D_1 (Range 1 10)))
+                (Define (D_2 (Sum (Const 2) (LoadFrom D_1))))
+                (AddResource (LoadEntity E_0) (Health (Max (Const 4) (LoadFrom D_2))))
+                (AddVelocity (LoadEntity E_0) (ConstRad -0.0) (Const 1)))
+            (requires (And Top (LessThan (Const 1) (Const 3))))
+            (on_cooldown) (consumes) (cast_time (Const 0)) (on_cast_start) (on_interrupt))");
+        assert!(a != b);
+        // the text keeps the sign of -0.0, so it's the canonical form that drops it
+        assert!(b.to_text().contains("-0"));
+        assert_eq!(spell(&a), spell(&b));
+        assert_eq!(hash(&a), hash(&b));
+        // while a spell that does something else doesn't
+        let mut c = b.clone();
+        c.cast_time = Discrete::Const(1);
+        assert!(hash(&c) != hash(&b));
+    }
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+use std::error::Error;
+use magic::*;
+use space::Token;
+
+// Things that can go wrong while making magic concrete. None of these are bugs
+// in the engine; they arise from ordinary play, eg. a target dying mid-spell.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EvalError {
+    UnboundESlot(ESlot),
+    UnboundESetSlot(ESetSlot),
+    UnboundDSlot(DSlot),
+    UnboundLSlot(LSlot),
+    NullToken,
+    DeadToken(Token),
+    DivisionByZero,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::EvalError::*;
+        match *self {
+            UnboundESlot(s) => write!(f, "nothing defined in slot {:?}", s),
+            UnboundESetSlot(s) => write!(f, "nothing defined in slot {:?}", s),
+            UnboundDSlot(s) => write!(f, "nothing defined in slot {:?}", s),
+            UnboundLSlot(s) => write!(f, "nothing defined in slot {:?}", s),
+            NullToken => write!(f, "entity is NULL"),
+            DeadToken(t) => write!(f, "entity {:?} no longer exists", t),
+            DivisionByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+// What an event does when its magic fails to evaluate.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Recovery {
+    AbortEvent,        // the rest of the event's instructions are not executed
+    SkipInstruction,   // the failing instruction is skipped. its siblings still run
+    SubstituteDefault, // failing expressions evaluate to NULL, 0, false, (0,0) or {}
+}
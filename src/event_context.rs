@@ -1,6 +1,6 @@
 use magic::*;
 use std::collections::HashMap;
-use play::{Token, TokenSet};
+use space::{Token, TokenSet};
 use movement_2d::*;
 
 #[derive(Clone, Debug)]
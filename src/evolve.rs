@@ -0,0 +1,446 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::rc::Rc;
+use rand::{Rng, Isaac64Rng, SeedableRng};
+use magic::{Spell, Condition, Instruction};
+use space::{Space, Player};
+use movement_2d::Point;
+use profile::Profile;
+use sexp::Sexp;
+use spell_text::Text;
+use {generate, mutate, simplify, canonical, power};
+
+// Growing spells rather than taking random ones. A population of generated
+// spells is scored by a Fitness, from duels played out headless, and each
+// generation the fittest few stay as they are while the rest of it is bred
+// from tournament winners: one of the mutate.rs operators, or a crossover
+// with a second winner. Children are simplified, and one the run has seen
+// before (by canonical::hash) is thrown away and bred again, so the
+// population doesn't fill up with copies. Every spell remembers its parents
+// and what was done to them, so the best can be traced back to where they
+// came from.
+//
+// Duels are against a target that stands still and doesn't fight back. They
+// are the same for every spell in a run, so scores can be compared.
+
+const ATTEMPTS: u32 = 20; // tries at breeding a new child before generating one
+const HEALTH: i32 = 100;
+const MANA: i32 = 100;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Settings {
+    pub profile: Rc<Profile>,
+    pub seed: u64,           // of the duels
+    pub population: usize,
+    pub generations: u32,
+    pub elites: usize,       // fittest kept as they are each generation
+    pub tournament: usize,   // spells drawn to pick each parent from
+    pub crossover: f32,      // chance a child is bred from two parents
+    pub duels: u32,          // per spell
+    pub duel_ticks: u32,
+}
+
+impl Settings {
+    pub fn new(profile: Rc<Profile>) -> Settings {
+        Settings {
+            profile,
+            seed: 0,
+            population: 50,
+            generations: 20,
+            elites: 3,
+            tournament: 3,
+            crossover: 0.3,
+            duels: 4,
+            duel_ticks: 300,
+        }
+    }
+}
+
+// how a duel went, from the caster's side
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Duel {
+    pub damage: i32,      // health the target lost, all of it if it's gone
+    pub self_damage: i32, // health the caster lost, less if it was healed
+    pub mana_spent: i32,  // mana the caster lost, whatever it got back
+}
+
+// what a run selects for. higher is fitter
+pub trait Fitness {
+    fn name(&self) -> String; // for the report, eg. "damage_per_mana"
+    fn score(&self, spell: &Spell, duels: &[Duel]) -> f32;
+}
+
+// damage done to the target for each point of mana spent, less half of what
+// the caster took. random spells often destroy their caster along with
+// everything else, and that half keeps them above spells doing nothing while
+// their children learn not to. healing the caster doesn't count
+pub struct DamagePerMana;
+
+impl Fitness for DamagePerMana {
+    fn name(&self) -> String { "damage_per_mana".to_string() }
+    fn score(&self, _: &Spell, duels: &[Duel]) -> f32 {
+        let damage: i32 = duels.iter().map(|d| d.damage).sum();
+        let self_damage: i32 = duels.iter().map(|d| d.self_damage.max(0)).sum();
+        let mana: i32 = duels.iter().map(|d| d.mana_spent).sum();
+        (damage as f32 - 0.5 * self_damage as f32) / mana.max(1) as f32
+    }
+}
+
+// spells that play out differently from one duel to the next: the standard
+// deviation of the damage they do
+pub struct FunVariance;
+
+impl Fitness for FunVariance {
+    fn name(&self) -> String { "fun_variance".to_string() }
+    fn score(&self, _: &Spell, duels: &[Duel]) -> f32 {
+        if duels.is_empty() {
+            return 0.;
+        }
+        let n = duels.len() as f32;
+        let mean = duels.iter().map(|d| d.damage as f32).sum::<f32>() / n;
+        let var = duels.iter().map(|d| (d.damage as f32 - mean).powi(2)).sum::<f32>() / n;
+        var.sqrt()
+    }
+}
+
+// spells whose power::Power::score is near the given one. duels don't matter
+pub struct ClosestToPower(pub f32);
+
+impl Fitness for ClosestToPower {
+    fn name(&self) -> String { format!("closest_to_power {}", self.0) }
+    fn score(&self, spell: &Spell, _: &[Duel]) -> f32 {
+        -(power::estimate(spell).score() - self.0).abs()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Individual {
+    pub id: usize,                       // index into History::individuals
+    pub spell: Spell,
+    pub generation: u32,                 // it was born in
+    pub parents: Vec<usize>,             // none if it was generated
+    pub change: Option<mutate::Change>,  // what was done to the first parent
+    pub fitness: f32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct History {
+    pub fitness: String,              // Fitness::name
+    pub individuals: Vec<Individual>, // every spell the run scored, by id
+    pub population: Vec<usize>,       // the latest generation, fittest first
+    pub generation: u32,
+}
+
+impl History {
+    pub fn best(&self) -> Option<&Individual> {
+        self.population.first().map(|&id| &self.individuals[id])
+    }
+
+    // the given ones and all their ancestors, oldest first
+    pub fn lineage(&self, ids: &[usize]) -> Vec<&Individual> {
+        let mut found: HashSet<usize> = HashSet::new();
+        let mut todo = ids.to_vec();
+        while let Some(id) = todo.pop() {
+            if found.insert(id) {
+                todo.extend(self.individuals[id].parents.iter().cloned());
+            }
+        }
+        let mut found: Vec<_> = found.into_iter().collect();
+        found.sort();
+        found.into_iter().map(|id| &self.individuals[id]).collect()
+    }
+
+    // the `best` fittest spells and their lineage, as an S-expression with
+    // the changes in comments:
+    //
+    //   (Evolution
+    //     (fitness damage_per_mana)
+    //     (best 41 38)
+    //     ; on_cast: nudged (Const 3) to (Const 5)
+    //     (Individual (id 41) (generation 5) (fitness 3.2) (parents 12)
+    //       (Spell ...))
+    //     ...)
+    pub fn report(&self, best: usize) -> String {
+        let best = &self.population[..best.min(self.population.len())];
+        let mut out = String::new();
+        writeln!(out, "(Evolution").unwrap();
+        writeln!(out, "  (fitness {})", self.fitness).unwrap();
+        let ids: Vec<String> = best.iter().map(|id| id.to_string()).collect();
+        writeln!(out, "  (best {})", ids.join(" ")).unwrap();
+        for i in self.lineage(best) {
+            if let Some(ref change) = i.change {
+                writeln!(out, "  ; {}", change).unwrap();
+            }
+            let field = |name: &str, values: Vec<String>| {
+                Sexp::list(Some(Sexp::atom(name)).into_iter().chain(values.into_iter().map(Sexp::atom)).collect())
+            };
+            let individual = Sexp::list(vec![
+                Sexp::atom("Individual"),
+                field("id", vec![i.id.to_string()]),
+                field("generation", vec![i.generation.to_string()]),
+                field("fitness", vec![format!("{:?}", i.fitness)]),
+                field("parents", i.parents.iter().map(|p| p.to_string()).collect()),
+                i.spell.to_sexp(),
+            ]);
+            writeln!(out, "  {}", individual.pretty(78).replace('\n', "\n  ")).unwrap();
+        }
+        out.push(')');
+        out
+    }
+}
+
+// the spell cast at a target over `ticks`, as often as it can be.
+// where the target stands depends on the seed
+pub fn duel(spell: &Spell, seed: u64, ticks: u32) -> Duel {
+    let mut space = Space::with_seed(seed);
+    let mut rng = Isaac64Rng::from_seed(&[seed]);
+    let mut me = Player::new(HEALTH as u32, MANA as u32);
+    me.add_spell(spell.clone());
+    let caster = space.player_enter(Point(0., 0.), me);
+    let (angle, dist) = (rng.gen_range(0., 2. * ::std::f32::consts::PI), rng.gen_range(40., 200.));
+    let target = space.player_enter(Point(angle.cos() * dist, angle.sin() * dist), Player::new(HEALTH as u32, MANA as u32));
+    let mut duel = Duel::default();
+    let mut mana = MANA;
+    for _ in 0..ticks {
+        if let Some(pt) = space.pt_of_player(target) {
+            space.player_cast(caster, 0, pt);
+        }
+        space.tick();
+        let now = space.player(caster).map(|p| p.mana()).unwrap_or(mana);
+        duel.mana_spent += (mana - now).max(0);
+        mana = now;
+    }
+    // health may go below 0, but there's only so much to lose
+    duel.damage = HEALTH - space.health_of(target).unwrap_or(0).max(0);
+    duel.self_damage = HEALTH - space.health_of(caster).unwrap_or(0).max(0);
+    duel
+}
+
+// runs every generation, calling `progress` after each with the history so far
+pub fn evolve<F, R, P>(settings: &Settings, fitness: &F, rng: &mut R, mut progress: P) -> History
+where F: Fitness, R: Rng, P: FnMut(&History) {
+    assert!(settings.population > 0 && settings.tournament > 0);
+    let mut history = History {
+        fitness: fitness.name(),
+        individuals: vec![],
+        population: vec![],
+        generation: 0,
+    };
+    let mut seen = HashSet::new();
+    let mut next = vec![];
+    while next.len() < settings.population {
+        next.push(generated(settings, rng, &mut seen));
+    }
+    loop {
+        for mut i in next.drain(..) {
+            i.id = history.individuals.len();
+            i.generation = history.generation;
+            i.fitness = score(settings, fitness, &i.spell);
+            history.population.push(i.id);
+            history.individuals.push(i);
+        }
+        {
+            let individuals = &history.individuals;
+            history.population.sort_by(|&a, &b| {
+                individuals[b].fitness.partial_cmp(&individuals[a].fitness).unwrap_or(::std::cmp::Ordering::Equal)
+            });
+        }
+        progress(&history);
+        if history.generation + 1 >= settings.generations {
+            return history;
+        }
+        history.generation += 1;
+        let parents = history.population.clone();
+        history.population.truncate(settings.elites.min(settings.population));
+        while history.population.len() + next.len() < settings.population {
+            let child = breed(settings, &history, &parents, rng, &mut seen)
+                .unwrap_or_else(|| generated(settings, rng, &mut seen));
+            next.push(child);
+        }
+    }
+}
+
+// NaN, eg. from a broken Fitness, is as unfit as can be. spells that can't
+// do anything aren't worth their duels, and score 0 at best
+fn score<F: Fitness>(settings: &Settings, fitness: &F, spell: &Spell) -> f32 {
+    if !acts(spell) {
+        let s = fitness.score(spell, &[]);
+        return if s.is_nan() { f32::MIN } else { s.min(0.) };
+    }
+    let duels: Vec<Duel> = (0..settings.duels)
+        .map(|i| duel(spell, settings.seed.wrapping_add(i as u64), settings.duel_ticks))
+        .collect();
+    let s = fitness.score(spell, &duels);
+    if s.is_nan() { f32::MIN } else { s }
+}
+
+// whether casting the spell can change anything. it can't when it's never
+// cast, or when all it does is define slots
+fn acts(spell: &Spell) -> bool {
+    *spell.requires != Condition::Bottom && [
+        &spell.on_cast, &spell.on_cooldown, &spell.on_cast_start, &spell.on_interrupt,
+    ].iter().any(|block| block_acts(block))
+}
+
+fn block_acts(block: &[Instruction]) -> bool {
+    use magic::Instruction::*;
+    block.iter().any(|ins| match *ins {
+        Define(_) | Nothing => false,
+        ITE(_, ref then, ref els) => block_acts(then) || block_acts(els),
+        CallWith(_, ref body) | ForEachAs(_, _, ref body) => block_acts(body),
+        Destroy(_) | DestroyWithoutEvent(_) | MoveEntity(_, _) | AddResource(_, _)
+        | AddVelocity(_, _, _) | SpawnProjectileAt(_, _) => true,
+    })
+}
+
+fn unscored(spell: Spell, parents: Vec<usize>, change: Option<mutate::Change>) -> Individual {
+    Individual { id: 0, spell, generation: 0, parents, change, fitness: 0. }
+}
+
+// a new spell, unlike any seen if that turns up within ATTEMPTS
+fn generated<R: Rng>(settings: &Settings, rng: &mut R, seen: &mut HashSet<u64>) -> Individual {
+    let mut spell = generate::spell(&settings.profile, rng).0;
+    for _ in 1..ATTEMPTS {
+        if !seen.contains(&canonical::hash(&spell)) {
+            break;
+        }
+        spell = generate::spell(&settings.profile, rng).0;
+    }
+    seen.insert(canonical::hash(&spell));
+    unscored(spell, vec![], None)
+}
+
+// a child of tournament winners among `parents`, unlike any seen
+fn breed<R: Rng>(settings: &Settings, history: &History, parents: &[usize], rng: &mut R, seen: &mut HashSet<u64>) -> Option<Individual> {
+    if parents.is_empty() {
+        return None;
+    }
+    for _ in 0..ATTEMPTS {
+        let a = tournament(settings, history, parents, rng);
+        let mut spell = history.individuals[a].spell.clone();
+        let (from, change) = if rng.gen::<f32>() < settings.crossover {
+            let b = tournament(settings, history, parents, rng);
+            (vec![a, b], mutate::crossover(&mut spell, &history.individuals[b].spell, rng))
+        } else {
+            let p = &settings.profile;
+            (vec![a], match rng.gen_range(0, 5) {
+                0 => mutate::reroll(&mut spell, p, rng),
+                1 => mutate::nudge(&mut spell, rng),
+                2 => mutate::swap_buff(&mut spell, p, rng),
+                3 => mutate::add_instruction(&mut spell, p, rng),
+                _ => mutate::remove_instruction(&mut spell, rng),
+            })
+        };
+        let change = match change {
+            Some(c) => c,
+            None => continue,
+        };
+        simplify::spell(&mut spell);
+        if seen.insert(canonical::hash(&spell)) {
+            return Some(unscored(spell, from, Some(change)));
+        }
+    }
+    None
+}
+
+// the fittest of settings.tournament drawn from `among`
+fn tournament<R: Rng>(settings: &Settings, history: &History, among: &[usize], rng: &mut R) -> usize {
+    (0..settings.tournament)
+        .map(|_| among[rng.gen_range(0, among.len())])
+        .max_by(|&a, &b| {
+            history.individuals[a].fitness.partial_cmp(&history.individuals[b].fitness).unwrap_or(::std::cmp::Ordering::Equal)
+        })
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Isaac64Rng, SeedableRng};
+    use generate;
+    use magic::*;
+    use profile::Profile;
+    use sexp::Sexp;
+    use spell_text::Text;
+    use super::*;
+
+    fn settings() -> Settings {
+        let mut s = Settings::new(Profile::standard());
+        s.population = 8;
+        s.generations = 4;
+        s.duels = 2;
+        s.duel_ticks = 60;
+        s
+    }
+
+    fn run(seed: u64) -> History {
+        let mut rng = Isaac64Rng::from_seed(&[seed]);
+        evolve(&settings(), &DamagePerMana, &mut rng, |_| ())
+    }
+
+    fn individual(id: usize, parents: Vec<usize>) -> Individual {
+        let mut i = unscored(generate::samples(1)[0].clone(), parents, None);
+        i.id = id;
+        i
+    }
+
+    #[test]
+    fn seeded_runs_repeat() {
+        assert_eq!(run(5), run(5));
+        assert!(run(5) != run(6));
+    }
+
+    #[test]
+    fn lineage_is_every_ancestor_once() {
+        let history = History {
+            fitness: "test".to_string(),
+            individuals: vec![
+                individual(0, vec![]),
+                individual(1, vec![]),
+                individual(2, vec![0]),
+                individual(3, vec![2, 1]),
+                individual(4, vec![0]),
+                individual(5, vec![3, 2]),
+            ],
+            population: vec![5, 4],
+            generation: 3,
+        };
+        let ids = |found: Vec<&Individual>| found.iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids(history.lineage(&[5])), vec![0, 1, 2, 3, 5]);
+        assert_eq!(ids(history.lineage(&[4, 2])), vec![0, 2, 4]);
+        assert_eq!(ids(history.lineage(&[])), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn report_holds_the_lineage() {
+        let history = run(7);
+        let report = history.report(2);
+        let sexp = Sexp::parse(&report).unwrap();
+        let (head, args) = sexp.as_call().unwrap();
+        assert_eq!(head, "Evolution");
+        assert_eq!(args[0].to_string(), format!("(fitness {})", history.fitness));
+        assert_eq!(args[1].to_string(), format!("(best {} {})", history.population[0], history.population[1]));
+        let lineage = history.lineage(&history.population[..2]);
+        assert_eq!(args.len(), 2 + lineage.len());
+        for (x, i) in args[2..].iter().zip(lineage) {
+            let fields = x.as_list().unwrap();
+            assert_eq!(fields[1].to_string(), format!("(id {})", i.id));
+            assert_eq!(Spell::from_sexp(&fields[5]).unwrap(), i.spell);
+        }
+    }
+
+    #[test]
+    fn doing_nothing_scores_zero() {
+        let never = Spell::from_text("(Spell
+            (on_cast (AddResource (LoadEntity E_0) (Health (Const 1))))
+            (requires Bottom) (on_cooldown) (consumes) (cast_time (Const 0)) (on_cast_start) (on_interrupt))").unwrap();
+        let defines = Spell::from_text("(Spell
+            (on_cast (CallWith (D_1 (Range 1 5))) (Define (E_1 (Choose Universe))))
+            (requires Top) (on_cooldown) (consumes) (cast_time (Const 0)) (on_cast_start) (on_interrupt))").unwrap();
+        for s in [never, defines].iter() {
+            assert!(!acts(s));
+            assert_eq!(score(&settings(), &DamagePerMana, s), 0.);
+            assert_eq!(score(&settings(), &FunVariance, s), 0.);
+            assert!(score(&settings(), &ClosestToPower(10.), s) <= 0.);
+        }
+    }
+}
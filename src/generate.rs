@@ -2,21 +2,191 @@ use magic::*;
 use rand::{Rng};
 use buffs::*;
 use std::rc::Rc;
+use power::{self, Target};
+use profile::{Profile, Aim};
+use mutate::Node;
+use simplify;
 
+const DIVISOR_ATTEMPTS: u32 = 100; // at a divisor that isn't 0, before settling for 1
 
-pub fn spell<R: Rng>(max_depth: u16, rng: &mut R) -> (Spell, u32) {
+// `profile` weighs what goes into the spell. see profile.rs
+pub fn spell<R: Rng>(profile: &Profile, rng: &mut R) -> (Spell, u32) {
+    let max_depth = profile.max_depth;
     assert!(max_depth > 0);
     let mut slots = SlotsTaken {ent:1,ent_set:0,loc:1,disc:0};
     let mut counter = Counter{ counter: 0 };
-    let s = Spell {
-        on_cast: vec_instruction(rng, &mut counter,  max_depth as i16-1, &mut slots.clone()),
-        on_cooldown: vec_instruction(rng, &mut counter,  max_depth as i16-1, &mut SlotsTaken {ent:1,ent_set:0,loc:0,disc:0}),
-        requires: Box::new(condition(rng, &mut counter,  max_depth as i16-1, &mut slots.clone())),
-        consumes: vec_resource(rng, &mut counter,  max_depth as i16-1, &mut slots),
+    let mut s = Spell {
+        on_cast: vec_instruction(rng, profile, &mut counter,  max_depth as i16-1, &mut slots.clone()),
+        on_cooldown: vec_instruction(rng, profile, &mut counter,  max_depth as i16-1, &mut SlotsTaken {ent:1,ent_set:0,loc:0,disc:0}),
+        requires: Box::new(condition(rng, profile, &mut counter,  max_depth as i16-1, &mut slots.clone())),
+        consumes: vec_resource(rng, profile, &mut counter,  max_depth as i16-1, &mut slots.clone()),
+        cast_time: cast_time(rng, profile, &mut counter),
+        on_cast_start: vec![],
+        on_interrupt: vec![],
     };
+    // only spells that take time have a start worth reacting to
+    if s.cast_time.estimate() > 0. {
+        s.on_cast_start = vec_instruction(rng, profile, &mut counter,  max_depth as i16-1, &mut slots.clone());
+        s.on_interrupt = vec_instruction(rng, profile, &mut counter,  max_depth as i16-1, &mut slots);
+    }
+    simplify::spell(&mut s);
     (s, counter.counter)
 }
 
+// what a spell asked of spell_matching must be like. for content that needs
+// spells for a specific role
+#[derive(Clone, PartialEq, Debug)]
+pub struct Spec {
+    pub profile: Rc<Profile>,
+    pub complexity: (u32, u32), // inclusive
+    pub constraints: Vec<Constraint>,
+    pub attempts: u32, // spells generated before giving up
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Constraint {
+    SpawnsProjectile,
+    ConsumesMana(i32, i32), // estimated mana consumed, inclusive
+    AppliesBuff(Buff),      // adds stacks of it to someone
+    TargetsEnemiesOnly,     // some effect lands on the caster's enemies, and none on anyone but them or its own projectiles
+    NoSelfDamage,           // nothing takes health from the caster or gives it harmful buffs
+    Power(f32, f32),        // power::Power::score, inclusive
+}
+
+impl Spec {
+    pub const DEFAULT_ATTEMPTS: u32 = 10000;
+
+    pub fn new(profile: Rc<Profile>) -> Spec {
+        Spec {
+            profile,
+            complexity: (0, u32::MAX),
+            constraints: vec![],
+            attempts: Self::DEFAULT_ATTEMPTS,
+        }
+    }
+}
+
+// a spell without unbound loads that meets the spec, and its complexity.
+// None if none turned up within spec.attempts
+pub fn spell_matching<R: Rng>(spec: &Spec, rng: &mut R) -> Option<(Spell, u32)> {
+    for _ in 0..spec.attempts {
+        let (mut s, complexity) = spell(&spec.profile, rng);
+        if complexity < spec.complexity.0 || spec.complexity.1 < complexity {
+            continue;
+        }
+        // some constraints are far cheaper to fix up than to wait for
+        for c in spec.constraints.iter() {
+            match *c {
+                Constraint::ConsumesMana(lo, hi) if lo <= hi && !c.holds(&s) => {
+                    s.consumes.retain(|r| !matches!(r, &Resource::Mana(_)));
+                    s.consumes.push(Resource::Mana(Discrete::Const(rng.gen_range(lo, hi + 1))));
+                },
+                Constraint::TargetsEnemiesOnly if !c.holds(&s) => {
+                    enemies_only(&mut s.on_cast_start, false);
+                    enemies_only(&mut s.on_cast, false);
+                    enemies_only(&mut s.on_cooldown, false);
+                    enemies_only(&mut s.on_interrupt, false);
+                },
+                _ => (),
+            }
+        }
+        if s.unbound_loads().is_empty() && spec.constraints.iter().all(|c| c.holds(&s)) {
+            return Some((s, complexity));
+        }
+    }
+    None
+}
+
+impl Constraint {
+    pub fn holds(&self, s: &Spell) -> bool {
+        use self::Constraint::*;
+        use magic::Instruction::*;
+        let effects = power::effects(s);
+        let happen = || effects.iter().filter(|e| e.times > 0.0);
+        match *self {
+            SpawnsProjectile => happen().any(|e| matches!(e.instruction, &SpawnProjectileAt(..))),
+            ConsumesMana(lo, hi) => {
+                let mana: f32 = s.consumes.iter().map(|r| match *r {
+                    Resource::Mana(ref d) => d.estimate(),
+                    _ => 0.0,
+                }).sum();
+                lo as f32 <= mana && mana <= hi as f32
+            },
+            AppliesBuff(buff) => happen().any(|e| match e.instruction {
+                &AddResource(_, Resource::BuffStacks(b, _, ref d)) => b == buff && d.estimate() > 0.0,
+                _ => false,
+            }),
+            TargetsEnemiesOnly => {
+                happen().any(|e| e.target == Target::Enemy)
+                && happen().all(|e| e.target == Target::Enemy || e.target == Target::Projectile)
+            },
+            NoSelfDamage => {
+                let pays_health = s.consumes.iter().any(|r| match *r {
+                    Resource::Health(ref d) => d.estimate() > 0.0,
+                    _ => false,
+                });
+                !pays_health && !happen().any(|e| e.target == Target::Caster && match e.instruction {
+                    &DestroyWithoutEvent(_) | &Destroy(_) => true,
+                    &AddResource(_, Resource::Health(ref d)) => d.estimate() < 0.0,
+                    &AddResource(_, Resource::BuffStacks(b, _, ref d)) => b.harmful() && d.estimate() > 0.0,
+                    _ => false,
+                })
+            },
+            Power(lo, hi) => {
+                let score = power::estimate(s).score();
+                lo <= score && score <= hi
+            },
+        }
+    }
+}
+
+// narrows the target of every effect down to the caster's enemies, leaving
+// alone what a projectile does to itself. an effect on anyone else is
+// skipped, and effects on the caster are dropped
+fn enemies_only(block: &mut [Instruction], in_projectile: bool) {
+    use magic::Instruction::*;
+    for ins in block.iter_mut() {
+        let on_caster = match ins {
+            &mut DestroyWithoutEvent(ref e) | &mut Destroy(ref e) | &mut MoveEntity(ref e, _)
+            | &mut AddResource(ref e, _) | &mut AddVelocity(ref e, _, _) => *e == Entity::LoadEntity(ESlot(0)),
+            _ => false,
+        };
+        if on_caster {
+            *ins = Nothing;
+            continue;
+        }
+        match ins {
+            &mut ITE(_, ref mut t, ref mut e) => {
+                enemies_only(t, in_projectile);
+                enemies_only(e, in_projectile);
+            },
+            &mut CallWith(_, ref mut body) | &mut ForEachAs(_, _, ref mut body) => enemies_only(body, in_projectile),
+            &mut SpawnProjectileAt(ref mut bp, _) => {
+                let bp = Rc::make_mut(bp);
+                enemies_only(&mut bp.on_create, true);
+                enemies_only(&mut bp.on_collision, true);
+                enemies_only(&mut bp.on_destroy, true);
+            },
+            &mut DestroyWithoutEvent(ref mut e) | &mut Destroy(ref mut e) | &mut MoveEntity(ref mut e, _)
+            | &mut AddResource(ref mut e, _) | &mut AddVelocity(ref mut e, _, _)
+            if !(in_projectile && *e == Entity::LoadEntity(ESlot(1))) => {
+                let target = e.clone();
+                *e = Entity::FirstOf(Box::new(EntitySet::And(vec![
+                    EntitySet::EnemiesOf(Entity::LoadEntity(ESlot(0))),
+                    EntitySet::Only(target),
+                ])));
+            },
+            _ => (),
+        }
+    }
+}
+
+// most spells are instant
+fn cast_time<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter) -> Discrete {
+    counter.increment();
+    Discrete::Const(p.cast_time.any(rng))
+}
+
 struct Counter {
     counter: u32,
 }
@@ -26,25 +196,45 @@ impl Counter {
     }
 }
 
-#[derive(Clone, Copy)]
-struct SlotsTaken {
-    ent: u8,
-    ent_set: u8,
-    loc: u8,
-    disc: u8,
+// slots 0 up to these are bound, and parts may load from them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SlotsTaken {
+    pub ent: u8,
+    pub ent_set: u8,
+    pub loc: u8,
+    pub disc: u8,
+}
+
+// a new part of the same kind as `like`, for rerolling part of a spell. an
+// instruction is never a Define, so nothing after it loses a slot
+pub fn part<R: Rng>(profile: &Profile, rng: &mut R, like: &Node, depth_left: i16, slots: SlotsTaken) -> Node {
+    let p = profile;
+    let counter = &mut Counter { counter: 0 };
+    let slots = &mut slots.clone();
+    match *like {
+        Node::Instruction(_) => Node::Instruction(nondef_instruction(rng, p, counter, depth_left, slots)),
+        Node::Condition(_) => Node::Condition(condition(rng, p, counter, depth_left, slots)),
+        Node::Discrete(_) => Node::Discrete(discrete(rng, p, counter, depth_left, slots, DiscreteContext::Other)),
+        Node::Entity(_) => Node::Entity(entity(rng, p, counter, depth_left, slots)),
+        Node::EntitySet(_) => Node::EntitySet(entity_set(rng, p, counter, depth_left, slots)),
+        Node::EntitySetCmp(_) => Node::EntitySetCmp(entity_set_cmp(rng, p, counter, depth_left, slots)),
+        Node::Location(_) => Node::Location(location(rng, p, counter, depth_left, slots)),
+        Node::Direction(_) => Node::Direction(direction(rng, p, counter, depth_left, slots)),
+        Node::Resource(_) => Node::Resource(resource(rng, p, counter, depth_left, slots)),
+    }
 }
 
-fn vec_instruction<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Instruction> {
+fn vec_instruction<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Instruction> {
     counter.increment();
     let mut v = vec![];
 
     let stored = slots.clone();
     while rng.gen_weighted_bool(3) && depth_left > 1 {
-        v.push(Instruction::Define(definition(rng, counter, depth_left-1, slots)));
+        v.push(Instruction::Define(definition(rng, p, counter, depth_left-1, slots)));
     }
     let mut non_defs = 0;
     while rng.gen_weighted_bool(2) || (v.len() > 0 && non_defs == 0) {
-        let i = nondef_instruction(rng, counter, depth_left-1, slots);
+        let i = nondef_instruction(rng, p, counter, depth_left-1, slots);
         if i != Instruction::Nothing {
             v.push(i);
             non_defs += 1;
@@ -54,375 +244,356 @@ fn vec_instruction<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16,
     v
 }
 
-fn condition<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Condition {
+fn condition<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Condition {
     counter.increment();
     let stop = depth_left <= 1 || rng.gen_weighted_bool(depth_left as u32 + 1);
-    let mut ret = if stop {
-        if rng.gen::<bool>() {
-            Condition::Top
-        } else {
-            Condition::Bottom
+    // comparisons are as often between small numbers as between big ones
+    let ctx = if rng.gen() { DiscreteContext::Threeish } else { DiscreteContext::Twentyish };
+    if stop {
+        match p.leaf_condition.any(rng) {
+            "Top" => Condition::Top,
+            _ => Condition::Bottom,
         }
     } else {
-        match rng.gen::<u8>() % 70 {
-            n if n < 10 => Condition::Nand(vec_condition(rng, counter,  depth_left-1, slots)),
-            n if n < 25 => Condition::And(vec_condition(rng, counter,  depth_left-1, slots)),
-            n if n < 45 => Condition::Or(vec_condition(rng, counter,  depth_left-1, slots)),
-            n if n < 50 => Condition::Equals(
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish),
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish),
-            ),
-            n if n < 55 => Condition::Equals(
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
-            ),
-            n if n < 60 => Condition::LessThan(
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish),
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish),
+        match p.condition.any(rng) {
+            "Nand" => Condition::Nand(vec_condition(rng, p, counter,  depth_left-1, slots)),
+            "And" => Condition::And(vec_condition(rng, p, counter,  depth_left-1, slots)),
+            "Or" => Condition::Or(vec_condition(rng, p, counter,  depth_left-1, slots)),
+            "Equals" => Condition::Equals(
+                discrete(rng, p, counter,  depth_left-1, slots, ctx),
+                discrete(rng, p, counter,  depth_left-1, slots, ctx),
             ),
-            n if n < 63 => Condition::LessThan(
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
+            "LessThan" => Condition::LessThan(
+                discrete(rng, p, counter,  depth_left-1, slots, ctx),
+                discrete(rng, p, counter,  depth_left-1, slots, ctx),
             ),
-            n if n < 65 => Condition::MoreThan(
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish),
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish),
+            "MoreThan" => Condition::MoreThan(
+                discrete(rng, p, counter,  depth_left-1, slots, ctx),
+                discrete(rng, p, counter,  depth_left-1, slots, ctx),
             ),
-            n if n < 67 => Condition::MoreThan(
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
-            ),
-            _ => Condition::EntitySetCmp(entity_set_cmp(rng, counter,  depth_left-1, slots)),
+            _ => Condition::EntitySetCmp(entity_set_cmp(rng, p, counter,  depth_left-1, slots)),
         }
-    };
-    rewrite_condition(&mut ret);
-    ret        
+    }
 }
 
+#[derive(Clone, Copy)]
 enum DiscreteContext {
     Threeish, Twentyish, Other,
 }
 
-fn discrete<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken, ctx: DiscreteContext) -> Discrete {
+fn discrete<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken, ctx: DiscreteContext) -> Discrete {
     counter.increment();
     let stop = depth_left <= 1 || rng.gen_weighted_bool(depth_left as u32 + 1);
     use magic::Discrete::*;
     let mut loops = 0;
     loop {
-        let mut ret = if stop {
-            match rng.gen::<u8>() % 35 {
-                x if x < 20 && slots.disc > 0 => {
-                    LoadFrom(DSlot(rng.gen::<u8>() % slots.disc))
-                },
-                x if x < 20 => Const(rng.gen::<i32>() % 50),
-                x if x < 27 => {
-                    let a = rng.gen::<i32>() % 50;
-                    let b = rng.gen::<i32>() % 50;
+        let ret = if stop {
+            match p.leaf_discrete.pick(rng, |x| x != "LoadFrom" || slots.disc > 0) {
+                "LoadFrom" => LoadFrom(DSlot(rng.gen::<u8>() % slots.disc)),
+                "Const" => Const(p.constant(rng)),
+                "Range" => {
+                    let a = p.constant(rng);
+                    let b = p.constant(rng);
                     if a < b {Range(a,b)}
                     else {Range(b,a)}
                 },
-                _ => WithinPercent(rng.gen::<i32>() % 50, F32(rng.gen::<f32>())),
+                _ => WithinPercent(p.constant(rng), F32(rng.gen::<f32>())),
             }
         } else {
-            match rng.gen::<u8>() % 53 {
-                x if x < 5 => Div(
-
-                    Box::new(discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish)),
-                    Box::new({
-                        let mut z = discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish);
-                        let mut est = 0.;
-                        loop {
-                            z = discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish);
-                            est = z.estimate();
-                            if est != 0. {break}
-                        }
-                        z
-                    }),
+            match p.discrete.any(rng) {
+                "Div" => Div(
+
+                    Box::new(discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Twentyish)),
+                    // a profile may only make divisors of 0, eg. with (constant 0 0)
+                    Box::new(
+                        (0..DIVISOR_ATTEMPTS)
+                            .map(|_| discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Threeish))
+                            .find(|z| z.estimate() != 0.)
+                            .unwrap_or(Const(1))
+                    ),
                 ),
-                x if x < 20 => Sum(vec_discrete(rng, counter,  depth_left-1, slots)),
-                x if x < 28 => Neg(Box::new(discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Other))),
-                x if x < 32 => Mult(vec_discrete(rng, counter,  depth_left-1, slots)),
-                x if x < 38 => Max(vec_discrete(rng, counter,  depth_left-1, slots)),
-                x if x < 44 => Min(vec_discrete(rng, counter,  depth_left-1, slots)),
-                x if x < 48 => CountStacks(buff(rng, counter), entity(rng, counter,  depth_left-1, slots)),
-                x if x < 48 => CountDur(buff(rng, counter), entity(rng, counter,  depth_left-1, slots)),
-                x if x < 51 => Choose(vec_discrete(rng, counter,  depth_left-1, slots)),
-                _ => Cardinality(Box::new(entity_set(rng, counter,  depth_left, slots))),
+                "Sum" => Sum(vec_discrete(rng, p, counter,  depth_left-1, slots)),
+                "Neg" => Neg(Box::new(discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Other))),
+                "Mult" => Mult(vec_discrete(rng, p, counter,  depth_left-1, slots)),
+                "Max" => Max(vec_discrete(rng, p, counter,  depth_left-1, slots)),
+                "Min" => Min(vec_discrete(rng, p, counter,  depth_left-1, slots)),
+                "CountStacks" => CountStacks(buff(rng, p, counter), tier(rng, p, counter, true), entity(rng, p, counter,  depth_left-1, slots)),
+                "CountDur" => CountDur(buff(rng, p, counter), tier(rng, p, counter, true), entity(rng, p, counter,  depth_left-1, slots)),
+                "Choose" => Choose(vec_discrete(rng, p, counter,  depth_left-1, slots)),
+                _ => Cardinality(Box::new(entity_set(rng, p, counter,  depth_left, slots))),
             }
         };
-        rewrite_discrete(&mut ret);
         loops += 1;
         if loops >= 5 { return ret; }
-        match ctx {
-            DiscreteContext::Threeish => {
-                let est = ret.estimate();
-                if 0. <= est && est <= 5. {return ret}
-                else if -3. <= est && est <= 10. && rng.gen_weighted_bool(3) {return ret}
-            },
-            DiscreteContext::Twentyish => {
-                let est = ret.estimate();
-                if 3. <= est && est <= 33. {return ret}
-                else if -30. <= est && est <= 57. && rng.gen_weighted_bool(3) {return ret}
-            },
+        let aim = match ctx {
+            DiscreteContext::Threeish => p.threeish,
+            DiscreteContext::Twentyish => p.twentyish,
             DiscreteContext::Other => return ret,
-        }
+        };
+        if on_aim(rng, aim, ret.estimate()) {return ret}
     }
 }
 
-fn vec_discrete<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Discrete> {
+fn on_aim<R: Rng>(rng: &mut R, aim: Aim, est: f32) -> bool {
+    let within = |r: (f32, f32)| r.0 <= est && est <= r.1;
+    within(aim.within) || (within(aim.near) && rng.gen_weighted_bool(3))
+}
+
+fn vec_discrete<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Discrete> {
     counter.increment();
     if depth_left == 0 { return vec![] }
     let mut v = vec![];
     while rng.gen_weighted_bool(3) || v.len() < 2 {
-        v.push(discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Other));
+        v.push(discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Other));
     }
     v
 }
 
-fn vec_condition<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Condition> {
+fn vec_condition<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Condition> {
     counter.increment();
     let mut v = vec![];
     while rng.gen_weighted_bool(2) || v.len() == 0 {
-        v.push(condition(rng, counter,  depth_left-1, slots));
+        v.push(condition(rng, p, counter,  depth_left-1, slots));
     }
     v
 }
 
-fn entity<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Entity {
+fn entity<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Entity {
     counter.increment();
     let stop = depth_left <= 1 || rng.gen_weighted_bool(depth_left as u32 + 1);
     use magic::Entity::*;
     if stop && slots.ent > 0 {
         LoadEntity(ESlot(rng.gen::<u8>() % slots.ent))
     } else {
-        match rng.gen::<u8>() % 25 {
-            x if x < 10 => FirstOf(Box::new(entity_set(rng, counter,  depth_left-1, slots))),
-            x if x < 15 => Choose(Box::new(entity_set(rng, counter,  depth_left-1, slots))),
+        match p.entity.any(rng) {
+            "FirstOf" => FirstOf(Box::new(entity_set(rng, p, counter,  depth_left-1, slots))),
+            "Choose" => Choose(Box::new(entity_set(rng, p, counter,  depth_left-1, slots))),
             _ => ClosestFrom(
-                Box::new(entity_set(rng, counter,  depth_left-1, slots)),
-                Box::new(location(rng, counter,  depth_left-1, slots)),
+                Box::new(entity_set(rng, p, counter,  depth_left-1, slots)),
+                Box::new(location(rng, p, counter,  depth_left-1, slots)),
             ),
         }
     }
 }
 
-fn location<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Location {
+fn location<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Location {
     counter.increment();
     use magic::Location::*;
     let stop = depth_left <= 1 || rng.gen_weighted_bool(depth_left as u32 + 1);
     if stop && slots.loc > 0 {
         LoadLocation(LSlot(rng.gen::<u8>() % slots.loc))
     } else {
-        match rng.gen::<u8>() % 20 {
-            x if x < 15 => AtEntity(entity(rng, counter,  depth_left-1, slots)),
-            x if x < 17 => Midpoint(vec_location(rng, counter,  depth_left-1, slots)),
-            _ => Choose(vec_location(rng, counter,  depth_left-1, slots)),
+        match p.location.any(rng) {
+            "AtEntity" => AtEntity(entity(rng, p, counter,  depth_left-1, slots)),
+            "Midpoint" => Midpoint(vec_location(rng, p, counter,  depth_left-1, slots)),
+            _ => Choose(vec_location(rng, p, counter,  depth_left-1, slots)),
         }
     }
 }
 
-fn entity_set_cmp<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> EntitySetCmp {
+fn entity_set_cmp<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> EntitySetCmp {
     counter.increment();
     use magic::EntitySetCmp::*;
     let stoppish = depth_left <= 1 || rng.gen_weighted_bool(depth_left as u32 + 1);
-    if stoppish && slots.loc > 0 {
-        match rng.gen::<u8>() % 20 {
-            x if x < 10 => Contains(
-                entity_set(rng, counter,  depth_left-1, slots),
-                entity(rng, counter,  depth_left-1, slots),
+    // comparing needs no location. waiting for one never ends in on_cooldown
+    if stoppish {
+        match p.leaf_entity_set_cmp.any(rng) {
+            "Contains" => Contains(
+                entity_set(rng, p, counter,  depth_left-1, slots),
+                entity(rng, p, counter,  depth_left-1, slots),
             ),
-            x if x < 13 => Subset(
-                entity_set(rng, counter,  depth_left-1, slots),
-                entity_set(rng, counter,  depth_left-1, slots),
+            "Subset" => Subset(
+                entity_set(rng, p, counter,  depth_left-1, slots),
+                entity_set(rng, p, counter,  depth_left-1, slots),
             ),
-            x if x < 16 => Superset(
-                entity_set(rng, counter,  depth_left-1, slots),
-                entity_set(rng, counter,  depth_left-1, slots),
+            "Superset" => Superset(
+                entity_set(rng, p, counter,  depth_left-1, slots),
+                entity_set(rng, p, counter,  depth_left-1, slots),
             ),
             _ => Equal(
-                entity_set(rng, counter,  depth_left-1, slots),
-                entity_set(rng, counter,  depth_left-1, slots),
+                entity_set(rng, p, counter,  depth_left-1, slots),
+                entity_set(rng, p, counter,  depth_left-1, slots),
             ),
         }
     } else {
-        match rng.gen::<u8>() % 17 {
-            x if x < 2 => Nand(vec_entity_set_cmp(rng, counter,  depth_left-1, slots)),
-            x if x < 10 => And(vec_entity_set_cmp(rng, counter,  depth_left-1, slots)),
-            _ => Or(vec_entity_set_cmp(rng, counter,  depth_left-1, slots)),
+        match p.entity_set_cmp.any(rng) {
+            "Nand" => Nand(vec_entity_set_cmp(rng, p, counter,  depth_left-1, slots)),
+            "And" => And(vec_entity_set_cmp(rng, p, counter,  depth_left-1, slots)),
+            _ => Or(vec_entity_set_cmp(rng, p, counter,  depth_left-1, slots)),
         }
     }
 }
 
-fn vec_entity_set<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<EntitySet> {
+fn vec_entity_set<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<EntitySet> {
     counter.increment();
     let mut v = vec![];
-    v.push(entity_set(rng, counter,  depth_left-1, slots));
+    v.push(entity_set(rng, p, counter,  depth_left-1, slots));
     while rng.gen_weighted_bool(3) {
-        v.push(entity_set(rng, counter,  depth_left-1, slots));
+        v.push(entity_set(rng, p, counter,  depth_left-1, slots));
     }
     v
 }
 
-fn vec_entity_set_cmp<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<EntitySetCmp> {
+fn vec_entity_set_cmp<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<EntitySetCmp> {
     counter.increment();
     let mut v = vec![];
-    v.push(entity_set_cmp(rng, counter,  depth_left-1, slots));
+    v.push(entity_set_cmp(rng, p, counter,  depth_left-1, slots));
     while rng.gen_weighted_bool(3) {
-        v.push(entity_set_cmp(rng, counter,  depth_left-1, slots));
+        v.push(entity_set_cmp(rng, p, counter,  depth_left-1, slots));
     }
     v
 }
 
-fn vec_location<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Location> {
+fn vec_location<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Location> {
     counter.increment();
     let mut v = vec![];
     while v.len() == 0 || rng.gen_weighted_bool(3) {
-        v.push(location(rng, counter,  depth_left-1, slots));
+        v.push(location(rng, p, counter,  depth_left-1, slots));
     }
     v
 }
 
-fn vec_resource<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Resource> {
+fn vec_resource<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Resource> {
     counter.increment();
     let mut v = vec![];
     use magic::Resource::*;
     if rng.gen_weighted_bool(4) {
-        v.push(Mana(discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish)));
+        v.push(Mana(discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Twentyish)));
     }
     if rng.gen_weighted_bool(4) {
-        v.push(Health(discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish)));
+        v.push(Health(discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Twentyish)));
     }
     while v.len() == 0 || rng.gen_weighted_bool(4) {
         let r = BuffStacks(
-            buff(rng, counter),
-            discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish),
+            buff(rng, p, counter),
+            tier(rng, p, counter, true),
+            discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Threeish),
         );
         v.push(r);
     }
     v
 }
 
-fn resource<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Resource {
+fn resource<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Resource {
     counter.increment();
     use magic::Resource::*;
-    match rng.gen::<u8>() % 24 {
-        x if x < 10 => Mana(discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish)),
-        x if x < 17 => Health(discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish)),
+    match p.resource.any(rng) {
+        "Mana" => Mana(discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Twentyish)),
+        "Health" => Health(discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Twentyish)),
         _ => BuffStacks(
-            buff(rng, counter),
-            discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Threeish),
+            buff(rng, p, counter),
+            tier(rng, p, counter, false),
+            discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Threeish),
         ),
     }
 }
 
-fn buff<R: Rng>(rng: &mut R, counter: &mut Counter) -> Buff {
+fn buff<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter) -> Buff {
+    counter.increment();
+    p.buff.any(rng)
+}
+
+// `any` allows AnyTier, for counting and consuming
+fn tier<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, any: bool) -> BuffTier {
     counter.increment();
-    use buffs::Buff::*;
-    match rng.gen::<u8>() % 50 {
-        x if x < 5 => Swarm,
-        x if x < 15 => Scalded,
-        x if x < 25 => Cold,
-        x if x < 35 => Chilled,
-        x if x < 40 => Toxified,
-        x if x < 43 => Envenomed,
-        _ => Electrified,
+    match p.tier(rng, any) {
+        Some(t) => BuffTier::Tier(Box::new(Discrete::Const(t as i32))),
+        None => BuffTier::AnyTier,
     }
 }
 
 
 
 
-fn entity_set<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> EntitySet {
+fn entity_set<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> EntitySet {
     counter.increment();
     use magic::EntitySet::*;
     let stop = depth_left <= 1 || rng.gen_weighted_bool(depth_left as u32 + 1);
     if stop {
-        match rng.gen::<u8>() % 25 {
-            x if x < 15 && slots.ent_set > 0 => {
-                IsInSlot(ESetSlot(rng.gen::<u8>() % slots.loc))
-            },
-            x if x < 10 => IsHuman,            
-            x if x < 15 => IsProjectile,            
-            x if x < 20 => Universe,
+        match p.leaf_entity_set.pick(rng, |x| x != "IsInSlot" || slots.ent_set > 0) {
+            "IsInSlot" => IsInSlot(ESetSlot(rng.gen::<u8>() % slots.ent_set)),
+            "IsHuman" => IsHuman,
+            "IsProjectile" => IsProjectile,
+            "Universe" => Universe,
             _ => Empty,
         }
     } else {
-        match rng.gen::<u8>() % 35 {
-            x if x < 2 => None(vec_entity_set(rng, counter,  depth_left-1, slots)),
-            x if x < 10 => And(vec_entity_set(rng, counter,  depth_left-1, slots)),
-            x if x < 15 => Or(vec_entity_set(rng, counter,  depth_left-1, slots)),
-            x if x < 18 => Only(entity(rng, counter,  depth_left-1, slots)),
-            x if x < 23 => WithinRangeOf(
-                entity(rng, counter,  depth_left-1, slots),
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
+        match p.entity_set.any(rng) {
+            "None" => None(vec_entity_set(rng, p, counter,  depth_left-1, slots)),
+            "And" => And(vec_entity_set(rng, p, counter,  depth_left-1, slots)),
+            "Or" => Or(vec_entity_set(rng, p, counter,  depth_left-1, slots)),
+            "Only" => Only(entity(rng, p, counter,  depth_left-1, slots)),
+            "WithinRangeOf" => WithinRangeOf(
+                entity(rng, p, counter,  depth_left-1, slots),
+                discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
             ),
-            x if x < 27 => HasMinResource(resource(rng, counter,  depth_left-1, slots)),
-            x if x < 32 => EnemiesOf(entity(rng, counter,  depth_left-1, slots)),
-            _ => AllBut(entity(rng, counter,  depth_left-1, slots)),
+            "HasMinResource" => HasMinResource(resource(rng, p, counter,  depth_left-1, slots)),
+            "EnemiesOf" => EnemiesOf(entity(rng, p, counter,  depth_left-1, slots)),
+            _ => AllBut(entity(rng, p, counter,  depth_left-1, slots)),
         }
     }
 }
 
-fn vec_direction<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Direction> {
+fn vec_direction<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Vec<Direction> {
     counter.increment();
     let mut v = vec![];
-    v.push(direction(rng, counter,  depth_left-1, slots));
+    v.push(direction(rng, p, counter,  depth_left-1, slots));
     while rng.gen_weighted_bool(3) {
-        v.push(direction(rng, counter,  depth_left-1, slots));
+        v.push(direction(rng, p, counter,  depth_left-1, slots));
     }
     v
 }
 
-fn direction<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Direction {
+fn direction<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Direction {
     counter.increment();
     use magic::Direction::*;
     let stop = depth_left <= 1 || rng.gen_weighted_bool(depth_left as u32 + 1);
     if stop {
-        if rng.gen() {
-            ConstRad(F32(rng.gen::<f32>() * 3.0 - 1.5))
-        } else {
-            let a = F32(rng.gen::<f32>() * 3.0 - 1.5);
-            let b = F32(rng.gen::<f32>() * 3.0 - 1.5);
-            if a < b {
-                BetweenRad(a,b)
-            } else {
-                BetweenRad(b,a)
-            }
+        match p.leaf_direction.any(rng) {
+            "ConstRad" => ConstRad(p.angle(rng)),
+            _ => {
+                let a = p.angle(rng);
+                let b = p.angle(rng);
+                if a < b {
+                    BetweenRad(a,b)
+                } else {
+                    BetweenRad(b,a)
+                }
+            },
         }
     } else {
-        if rng.gen() {
-            Choose(vec_direction(rng, counter,  depth_left-1, slots))
-        } else {
-            ChooseWithinRadOf(
-                Box::new(direction(rng, counter,  depth_left-1, slots)),
-                F32(rng.gen::<f32>() * 3.0 - 1.5),
-            )
+        match p.direction.any(rng) {
+            "Choose" => Choose(vec_direction(rng, p, counter,  depth_left-1, slots)),
+            _ => ChooseWithinRadOf(
+                Box::new(direction(rng, p, counter,  depth_left-1, slots)),
+                p.angle(rng),
+            ),
         }
     }
 }
 
-fn definition<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Definition {
+fn definition<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Definition {
     counter.increment();
     use magic::Definition::*;
-    match rng.gen::<u8>() % 12 {
-        x if x < 3 => {
-            let y = entity_set(rng, counter,  depth_left-1, slots);
+    match p.definition.any(rng) {
+        "ESet" => {
+            let y = entity_set(rng, p, counter,  depth_left-1, slots);
             slots.ent_set += 1;
             ESet(
                 ESetSlot(slots.ent_set - 1),
                 y,
             )
         },
-        x if x < 7 => {
-            let y = entity(rng, counter,  depth_left-1, slots);
+        "E" => {
+            let y = entity(rng, p, counter,  depth_left-1, slots);
             slots.ent += 1;
             E(
                 ESlot(slots.ent - 1),
                 y,
             )
         },
-        x if x < 9 => {
-            let y = location(rng, counter,  depth_left-1, slots);
+        "L" => {
+            let y = location(rng, p, counter,  depth_left-1, slots);
             slots.loc += 1;
             L(
                 LSlot(slots.loc - 1),
@@ -430,7 +601,7 @@ fn definition<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots
             )
         },
         _ => {
-            let y = discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Other);
+            let y = discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Other);
             slots.disc += 1;
             D(
                 DSlot(slots.disc - 1),
@@ -440,61 +611,66 @@ fn definition<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots
     }
 }
 
-fn projectile_blueprint<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16) -> ProjectileBlueprint {
+fn projectile_blueprint<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16) -> ProjectileBlueprint {
     counter.increment();
     let just_me = SlotsTaken {ent:2,ent_set:0,loc:1,disc:0};
     ProjectileBlueprint {
-        on_create: vec_instruction(rng, counter,  depth_left-1, &mut just_me.clone()),
-        on_collision: vec_instruction(rng, counter,  depth_left-1, &mut SlotsTaken {ent:2,ent_set:0,loc:0,disc:0}),
-        collides_with: entity_set(rng, counter,  depth_left-1, &mut just_me.clone()),
-        on_destroy: vec_instruction(rng, counter,  depth_left-1, &mut just_me.clone()),
-        lifetime: discrete(rng, counter,  depth_left-1, &mut just_me.clone(), DiscreteContext::Threeish),
+        on_create: vec_instruction(rng, p, counter,  depth_left-1, &mut just_me.clone()),
+        on_collision: vec_instruction(rng, p, counter,  depth_left-1, &mut SlotsTaken {ent:3,ent_set:0,loc:1,disc:0}),
+        collides_with: entity_set(rng, p, counter,  depth_left-1, &mut just_me.clone()),
+        on_destroy: vec_instruction(rng, p, counter,  depth_left-1, &mut just_me.clone()),
+        lifetime: discrete(rng, p, counter,  depth_left-1, &mut just_me.clone(), DiscreteContext::Threeish),
     }
 }
 
 
-fn nondef_instruction<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Instruction {
+fn nondef_instruction<R: Rng>(rng: &mut R, p: &Profile, counter: &mut Counter, depth_left: i16, slots: &mut SlotsTaken) -> Instruction {
     counter.increment();
     use magic::Instruction::*;
     let stop1 = depth_left <= 1 || rng.gen_weighted_bool(depth_left as u32 + 1);
     let stop2 = depth_left <= 1 || rng.gen_weighted_bool(depth_left as u32 + 1);
     let mut ins = if stop1 || stop2 {
-        match rng.gen::<u8>() % 24 {
-            x if x < 3 => DestroyWithoutEvent(entity(rng, counter,  depth_left-1, slots)),
-            x if x < 8 => Destroy(entity(rng, counter,  depth_left-1, slots)),
-            x if x < 12 => MoveEntity(
-                entity(rng, counter,  depth_left-1, slots),
-                location(rng, counter,  depth_left-1, slots),
+        match p.effect.any(rng) {
+            "DestroyWithoutEvent" => DestroyWithoutEvent(entity(rng, p, counter,  depth_left-1, slots)),
+            "Destroy" => Destroy(entity(rng, p, counter,  depth_left-1, slots)),
+            "MoveEntity" => MoveEntity(
+                entity(rng, p, counter,  depth_left-1, slots),
+                location(rng, p, counter,  depth_left-1, slots),
             ),
-            x if x < 20 => AddResource(
-                entity(rng, counter,  depth_left-1, slots),
-                resource(rng, counter,  depth_left-1, slots),
+            "AddResource" => AddResource(
+                entity(rng, p, counter,  depth_left-1, slots),
+                resource(rng, p, counter,  depth_left-1, slots),
             ),
             _ => AddVelocity(
-                entity(rng, counter,  depth_left-1, slots),
-                direction(rng, counter,  depth_left-1, slots),
-                discrete(rng, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
+                entity(rng, p, counter,  depth_left-1, slots),
+                direction(rng, p, counter,  depth_left-1, slots),
+                discrete(rng, p, counter,  depth_left-1, slots, DiscreteContext::Twentyish),
             ),
         }
     } else {
-        match rng.gen::<u8>() % 21 {
-            x if x < 10 && depth_left >= 2 => SpawnProjectileAt(
-                Rc::new(projectile_blueprint(rng, counter,  depth_left-1)),
-                location(rng, counter,  depth_left-1, slots),
+        let can_spawn = depth_left >= 2 && slots.loc > 0;
+        match p.instruction.pick(rng, |x| x != "SpawnProjectileAt" || can_spawn) {
+            "SpawnProjectileAt" => SpawnProjectileAt(
+                Rc::new(projectile_blueprint(rng, p, counter,  depth_left-1)),
+                location(rng, p, counter,  depth_left-1, slots),
             ),
-            x if x < 14 => ITE(
-                condition(rng, counter,  depth_left-1, slots),
-                vec_instruction(rng, counter,  depth_left-1, slots),
-                vec_instruction(rng, counter,  depth_left-1, slots),
-            ),
-            x if x < 16 => CallWith(
-                definition(rng, counter,  depth_left-1, slots),
-                vec_instruction(rng, counter,  depth_left-1, slots),
+            "ITE" => ITE(
+                condition(rng, p, counter,  depth_left-1, slots),
+                vec_instruction(rng, p, counter,  depth_left-1, slots),
+                vec_instruction(rng, p, counter,  depth_left-1, slots),
             ),
+            "CallWith" => {
+                // the definition is only visible to the body
+                let mut inner = *slots;
+                CallWith(
+                    definition(rng, p, counter,  depth_left-1, &mut inner),
+                    vec_instruction(rng, p, counter,  depth_left-1, &mut inner),
+                )
+            },
             _ => ForEachAs(
                 ESlot(rng.gen::<u8>() % (slots.ent + 1)),
-                entity_set(rng, counter,  depth_left-1, slots),
-                vec_instruction(rng, counter,  depth_left-1, slots),
+                entity_set(rng, p, counter,  depth_left-1, slots),
+                vec_instruction(rng, p, counter,  depth_left-1, slots),
             ),            
         }
     };
@@ -502,20 +678,6 @@ fn nondef_instruction<R: Rng>(rng: &mut R, counter: &mut Counter, depth_left: i1
     ins
 }
 
-fn rewrite_discrete(disc: &mut Discrete) {
-    let mut repl = None;
-    match disc {
-        &mut Discrete::Sum(ref vec) if vec.len() == 1 =>  {
-            repl = Some(vec[0].clone());
-        },
-        _ => (),
-    };
-    if let Some(x) = repl {
-        // println!("Ins replace ({:?}) => {:?}", ins, &x);
-        *disc = x;    
-    }
-}
-
 fn rewrite_instruction(ins: &mut Instruction) {
     let mut repl = None;
     match ins {
@@ -531,28 +693,13 @@ fn rewrite_instruction(ins: &mut Instruction) {
         *ins = x;    
     }
 }
-fn rewrite_condition(cond: &mut Condition) {
-    let mut repl = None;
-    match cond {
-        &mut Condition::And(ref vec) if vec.len() == 1 =>  {
-            repl = Some(vec[0].clone());
-        },
-        &mut Condition::Or(ref vec) if vec.len() == 1 =>  {
-            repl = Some(vec[0].clone());
-        },
-        _ => (),
-    };
-    if let Some(x) = repl {
-        *cond = x;    
-    }
-}
 
 impl Discrete {
     pub fn estimate(&self) -> f32 {
         use self::Discrete::*;
         match self {
             &Const(x) => x as f32,
-            &Range(x, y) => (x + y) as f32 * 0.5,
+            &Range(x, y) => (x as f32 + y as f32) * 0.5,
             &WithinPercent(x, _) => x as f32,
             &Div(ref a, ref b) => a.estimate() / {
                 let z = b.estimate();
@@ -561,20 +708,11 @@ impl Discrete {
             &Sum(ref x) => x.iter().map(|x| x.estimate()).sum(),
             &Neg(ref x) => -x.estimate(),
             &Mult(ref x) => x.iter().fold(1.0, |x,y| x*y.estimate()),
-            &Max(ref x) => {
-                x.iter().fold(
-                    ::std::f32::MIN,
-                    |x,y| {let y = y.estimate(); if x < y {x} else {y}},
-                )  
-            },
-            &Min(ref x) => {
-                x.iter().fold(
-                    ::std::f32::MAX,
-                    |x,y| {let y = y.estimate(); if x > y {x} else {y}},
-                )  
-            },
-            &CountStacks(_, _) => 2.0,
-            &CountDur(_, _) => 20.0,
+            // from 1, as Space does
+            &Max(ref x) => x.iter().fold(1.0, |x,y| x.max(y.estimate())),
+            &Min(ref x) => x.iter().fold(1.0, |x,y| x.min(y.estimate())),
+            &CountStacks(_, _, _) => 2.0,
+            &CountDur(_, _, _) => 20.0,
             &Choose(ref x) => {
                 let mut tot = 0.0;
                 let mut cnt = 0;
@@ -586,6 +724,109 @@ impl Discrete {
             },
             &Cardinality(_) => 6.0,
             &LoadFrom(_) => 10.0,
+            &CooldownLeft(_, _) => 1.0,
+            &Charges(_, _) => 1.0,
+        }
+    }
+}
+
+// spells from every profile in assets/profiles, the same every time
+#[cfg(test)]
+pub fn samples(per_profile: usize) -> Vec<Spell> {
+    use rand::{Isaac64Rng, SeedableRng};
+    let profiles = [
+        include_str!("../assets/profiles/standard.sexp"),
+        include_str!("../assets/profiles/projectile_heavy.sexp"),
+        include_str!("../assets/profiles/buff_centric.sexp"),
+    ];
+    let mut rng = Isaac64Rng::from_seed(&[7]);
+    let mut out = vec![];
+    for src in profiles.iter() {
+        let p = Profile::parse(src).unwrap();
+        for _ in 0..per_profile {
+            out.push(spell(&p, &mut rng).0);
+        }
+    }
+    out
+}
+
+// what every codec of spells must do, never panicking: samples decode back
+// to themselves, an encoding cut short doesn't decode, and one with a bit
+// flipped decodes to nothing or to a spell that encodes back the same.
+// `seed` picks the cuts and flips
+#[cfg(test)]
+pub fn check_codec<E, D, X>(seed: u64, encode: E, decode: D)
+where E: Fn(&Spell) -> Vec<u8>, D: Fn(&[u8]) -> Result<Spell, X>, X: ::std::fmt::Debug {
+    use rand::{Isaac64Rng, SeedableRng};
+    let shown = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    for s in samples(40) {
+        let bytes = encode(&s);
+        match decode(&bytes) {
+            Ok(x) => assert_eq!(x, s, "{}", shown(&bytes)),
+            Err(e) => panic!("{:?} in {}", e, shown(&bytes)),
+        }
+    }
+    let mut rng = Isaac64Rng::from_seed(&[seed]);
+    for s in samples(10) {
+        let bytes = encode(&s);
+        for _ in 0..20 {
+            let cut = rng.gen_range(0, bytes.len());
+            assert!(decode(&bytes[..cut]).is_err(), "{}", shown(&bytes[..cut]));
+        }
+        for _ in 0..50 {
+            let mut flipped = bytes.clone();
+            flipped[rng.gen_range(0, bytes.len())] ^= 1 << rng.gen_range(0, 8);
+            if let Ok(x) = decode(&flipped) {
+                let again = encode(&x);
+                assert_eq!(decode(&again).map(|y| encode(&y)).ok(), Some(again), "{}", shown(&flipped));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Isaac64Rng, SeedableRng};
+    use magic::Discrete::*;
+    use magic::Resource;
+    use profile::Profile;
+    use super::{spell, Constraint};
+
+    #[test]
+    fn estimate_max_and_min() {
+        assert_eq!(Max(vec![Const(3), Const(5)]).estimate(), 5.0);
+        assert_eq!(Min(vec![Const(-3), Const(5)]).estimate(), -3.0);
+        // both start from 1, as Space does
+        assert_eq!(Max(vec![Const(-3)]).estimate(), 1.0);
+        assert_eq!(Min(vec![Const(3), Const(5)]).estimate(), 1.0);
+        assert_eq!(Max(vec![]).estimate(), 1.0);
+    }
+
+    #[test]
+    fn zero_divisors_dont_hang() {
+        let p = Profile::parse("(constant 0 0) (leaf_discrete (Const 1)) (discrete (Div 1) (Sum 1) (Neg 1) (Mult 1))").unwrap();
+        let mut rng = Isaac64Rng::from_seed(&[1]);
+        for _ in 0..20 {
+            spell(&p, &mut rng);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn consumes_mana_of_a_max() {
+        let mut s = spell(&Profile::standard(), &mut Isaac64Rng::from_seed(&[2])).0;
+        s.consumes = vec![Resource::Mana(Max(vec![Const(3), Const(5)]))];
+        assert!(Constraint::ConsumesMana(5, 5).holds(&s));
+        assert!(!Constraint::ConsumesMana(0, 4).holds(&s));
+    }
+
+    #[test]
+    fn huge_constants_dont_overflow() {
+        assert_eq!(Range(2000000000, 2100000000).estimate(), 2050000000.0);
+        assert_eq!(Range(i32::MIN, i32::MAX).estimate(), 0.0);
+        let p = Profile::parse("(constant 1500000000 2000000000)").unwrap();
+        let mut rng = Isaac64Rng::from_seed(&[3]);
+        for _ in 0..20 {
+            spell(&p, &mut rng);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+extern crate rand;
+#[cfg(feature = "window")]
+extern crate piston_window;
+#[cfg(feature = "window")]
+extern crate find_folder;
+
+pub mod space;
+pub mod magic;
+#[allow(clippy::clone_on_copy, clippy::len_zero, clippy::match_ref_pats, clippy::needless_borrowed_reference)] // kept in its original style
+pub mod generate;
+pub mod profile;
+pub mod mutate;
+pub mod simplify;
+pub mod canonical;
+pub mod evolve;
+pub mod power;
+#[allow(clippy::match_ref_pats, clippy::needless_borrowed_reference)] // kept in its original style
+mod printing;
+#[allow(clippy::new_without_default)] // kept in its original style
+pub mod event_context;
+pub mod eval_error;
+pub mod buffs;
+pub mod modifiers;
+#[allow(clippy::init_numbered_fields, clippy::len_zero, clippy::needless_return, clippy::redundant_field_names, clippy::unnecessary_cast)] // kept in its original style
+pub mod movement_2d;
+#[allow(clippy::redundant_field_names)] // kept in its original style
+pub mod wasd_set;
+pub mod sexp;
+pub mod spell_text;
+pub mod spell_binary;
+pub mod vocalize;
+pub mod replay;
+
+#[cfg(feature = "window")]
+pub mod play;
@@ -114,18 +114,27 @@ pub enum Discrete {
     Mult(Vec<Discrete>),
     Max(Vec<Discrete>),
     Min(Vec<Discrete>),
-    CountStacks(Buff, Entity),
-    CountDur(Buff, Entity),
+    CountStacks(Buff, BuffTier, Entity),
+    CountDur(Buff, BuffTier, Entity),
     Choose(Vec<Discrete>),
     Cardinality(Box<EntitySet>),
     LoadFrom(DSlot),
+    CooldownLeft(u8, Entity), // spell index. seconds until the next charge
+    Charges(u8, Entity), // spell index
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Resource {
     Mana(Discrete),
     Health(Discrete),
-    BuffStacks(Buff, Discrete),
+    BuffStacks(Buff, BuffTier, Discrete),
+}
+
+// buffs that differ only by tier don't stack together. see ideas/system.md
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum BuffTier {
+    Tier(Box<Discrete>), // clamped to [1, MAX_TIER]
+    AnyTier, // counts add up every tier. removes take from the lowest tier first, adds go to tier 1
 }
 
 #[derive(Clone, Eq, PartialEq)]
@@ -142,15 +151,399 @@ pub enum Entity {
 pub struct Spell {
     pub on_cast: Vec<Instruction>, //    ent0:caster, loc0:cursor    
     pub requires: Box<Condition>, //     ent0:caster, loc0:cursor
-    pub on_cooldown: Vec<Instruction>, //ent0:caster,
+    pub on_cooldown: Vec<Instruction>, //ent0:caster, runs whenever a charge is regained
     pub consumes: Vec<Resource>, //      ent0:caster, loc0:cursor
+    pub cast_time: Discrete, //          ent0:caster, loc0:cursor. seconds until on_cast
+    pub on_cast_start: Vec<Instruction>, //ent0:caster, loc0:cursor
+    pub on_interrupt: Vec<Instruction>, //ent0:caster, loc0:cursor. the cast was cut short
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct ProjectileBlueprint {
     pub on_create: Vec<Instruction>, //     ent0:caster, ent1:me, loc0:cursor    
-    pub on_collision: Vec<Instruction>, //  ent0:caster, ent1:me, loc0:cursor    
+    pub on_collision: Vec<Instruction>, //  ent0:caster, ent1:me, ent2:hit, loc0:cursor
     pub collides_with: EntitySet, //        ent0:caster, ent1:me, loc0:cursor    
     pub on_destroy: Vec<Instruction>, //    ent0:caster, ent1:me, loc0:cursor    
     pub lifetime: Discrete, //              ent0:caster, ent1:me, loc0:cursor    
-}
\ No newline at end of file
+}
+
+/////////////////////////////////////////////////////////////////////////
+
+// SLOT CHECKING
+// Statically finds loads from slots that nothing has defined in that field's
+// context. A Define binds for the instructions after it in the same list,
+// ForEachAs and CallWith bind only for their body, and bindings made in a
+// nested list don't escape it. This is deliberately stricter than the engine,
+// where whatever an ITE branch or a ForEachAs (its own slot included) defines
+// stays defined after it: whether it was depends on the branch taken or on
+// the set being empty, so loading it later is reported all the same.
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Slot {
+    E(ESlot),
+    ESet(ESetSlot),
+    D(DSlot),
+    L(LSlot),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct UnboundLoad {
+    pub field: String, // eg. "on_cast.projectile.on_collision"
+    pub slot: Slot,
+}
+
+impl Spell {
+    pub fn unbound_loads(&self) -> Vec<UnboundLoad> {
+        let mut checker = SlotChecker { field: String::new(), found: vec![], every: false };
+        let caster_cursor = [Slot::E(ESlot(0)), Slot::L(LSlot(0))];
+        checker.in_field("on_cast", &caster_cursor, |c, b| c.instructions(b, &self.on_cast));
+        checker.in_field("requires", &caster_cursor, |c, b| c.condition(b, &self.requires));
+        checker.in_field("on_cooldown", &[Slot::E(ESlot(0))], |c, b| c.instructions(b, &self.on_cooldown));
+        checker.in_field("consumes", &caster_cursor, |c, b| {
+            for r in self.consumes.iter() {
+                c.resource(b, r);
+            }
+        });
+        checker.in_field("cast_time", &caster_cursor, |c, b| c.discrete(b, &self.cast_time));
+        checker.in_field("on_cast_start", &caster_cursor, |c, b| c.instructions(b, &self.on_cast_start));
+        checker.in_field("on_interrupt", &caster_cursor, |c, b| c.instructions(b, &self.on_interrupt));
+        checker.found
+    }
+}
+
+impl ProjectileBlueprint {
+    pub fn unbound_loads(&self) -> Vec<UnboundLoad> {
+        let mut checker = SlotChecker { field: String::new(), found: vec![], every: false };
+        checker.blueprint(self);
+        checker.found
+    }
+}
+
+// for instructions that the engine runs outside of spells, eg. buff events
+pub fn unbound_loads_in(field: &str, bound: &[Slot], block: &[Instruction]) -> Vec<UnboundLoad> {
+    let mut checker = SlotChecker { field: String::new(), found: vec![], every: false };
+    checker.in_field(field, bound, |c, b| c.instructions(b, block));
+    checker.found
+}
+
+// every slot the instructions load from, whether they define it or not.
+// projectiles they spawn load from their own
+pub fn loads_in(block: &[Instruction]) -> ::std::collections::HashSet<Slot> {
+    let mut checker = SlotChecker { field: String::new(), found: vec![], every: true };
+    checker.instructions(&Bound::new(), block);
+    checker.found.into_iter().map(|u| u.slot).collect()
+}
+
+type Bound = ::std::collections::HashSet<Slot>;
+
+struct SlotChecker {
+    field: String,
+    found: Vec<UnboundLoad>,
+    every: bool, // report bound loads too
+}
+
+impl SlotChecker {
+    fn in_field<F>(&mut self, name: &str, bound: &[Slot], work: F)
+    where F: FnOnce(&mut Self, &mut Bound) {
+        let outer_len = self.field.len();
+        if outer_len > 0 {
+            self.field.push('.');
+        }
+        self.field.push_str(name);
+        let mut bound = bound.iter().cloned().collect();
+        work(self, &mut bound);
+        self.field.truncate(outer_len);
+    }
+
+    fn load(&mut self, bound: &Bound, slot: Slot) {
+        if self.every || !bound.contains(&slot) {
+            self.found.push(UnboundLoad { field: self.field.clone(), slot });
+        }
+    }
+
+    fn blueprint(&mut self, bp: &ProjectileBlueprint) {
+        let me = [Slot::E(ESlot(0)), Slot::E(ESlot(1)), Slot::L(LSlot(0))];
+        let hit = [Slot::E(ESlot(0)), Slot::E(ESlot(1)), Slot::E(ESlot(2)), Slot::L(LSlot(0))];
+        self.in_field("on_create", &me, |c, b| c.instructions(b, &bp.on_create));
+        self.in_field("on_collision", &hit, |c, b| c.instructions(b, &bp.on_collision));
+        self.in_field("collides_with", &me, |c, b| c.entity_set(b, &bp.collides_with));
+        self.in_field("on_destroy", &me, |c, b| c.instructions(b, &bp.on_destroy));
+        self.in_field("lifetime", &me, |c, b| c.discrete(b, &bp.lifetime));
+    }
+
+    // bindings made in here don't escape
+    fn instructions(&mut self, bound: &Bound, instructions: &[Instruction]) {
+        let mut bound = bound.clone();
+        for i in instructions {
+            self.instruction(&mut bound, i);
+        }
+    }
+
+    fn instruction(&mut self, bound: &mut Bound, instruction: &Instruction) {
+        use self::Instruction::*;
+        match *instruction {
+            Define(ref def) => {
+                let slot = self.definition(bound, def);
+                bound.insert(slot);
+            },
+            ITE(ref cond, ref then, ref els) => {
+                self.condition(bound, cond);
+                self.instructions(bound, then);
+                self.instructions(bound, els);
+            },
+            CallWith(ref def, ref ins) => {
+                let mut inner = bound.clone();
+                let slot = self.definition(bound, def);
+                inner.insert(slot);
+                self.instructions(&inner, ins);
+            },
+            ForEachAs(slot, ref set, ref ins) => {
+                self.entity_set(bound, set);
+                let mut inner = bound.clone();
+                inner.insert(Slot::E(slot));
+                self.instructions(&inner, ins);
+            },
+            DestroyWithoutEvent(ref ent) |
+            Destroy(ref ent) => self.entity(bound, ent),
+            MoveEntity(ref ent, ref loc) => {
+                self.entity(bound, ent);
+                self.location(bound, loc);
+            },
+            AddResource(ref ent, ref res) => {
+                self.entity(bound, ent);
+                self.resource(bound, res);
+            },
+            AddVelocity(ref ent, ref dir, ref disc) => {
+                self.entity(bound, ent);
+                self.direction(bound, dir);
+                self.discrete(bound, disc);
+            },
+            SpawnProjectileAt(ref bp, ref loc) => {
+                self.location(bound, loc);
+                // the new projectile inherits the caster and cursor
+                self.load(bound, Slot::E(ESlot(0)));
+                self.load(bound, Slot::L(LSlot(0)));
+                if !self.every {
+                    self.in_field("projectile", &[], |c, _| c.blueprint(bp));
+                }
+            },
+            Nothing => (),
+        }
+    }
+
+    // returns the slot the definition binds
+    fn definition(&mut self, bound: &Bound, definition: &Definition) -> Slot {
+        use self::Definition::*;
+        match *definition {
+            ESet(s, ref x) => { self.entity_set(bound, x); Slot::ESet(s) },
+            E(s, ref x) => { self.entity(bound, x); Slot::E(s) },
+            D(s, ref x) => { self.discrete(bound, x); Slot::D(s) },
+            L(s, ref x) => { self.location(bound, x); Slot::L(s) },
+        }
+    }
+
+    fn condition(&mut self, bound: &Bound, condition: &Condition) {
+        use self::Condition::*;
+        match *condition {
+            Nand(ref v) | And(ref v) | Or(ref v) => {
+                for c in v {
+                    self.condition(bound, c);
+                }
+            },
+            Top | Bottom => (),
+            Equals(ref a, ref b) |
+            LessThan(ref a, ref b) |
+            MoreThan(ref a, ref b) => {
+                self.discrete(bound, a);
+                self.discrete(bound, b);
+            },
+            EntitySetCmp(ref cmp) => self.entity_set_cmp(bound, cmp),
+        }
+    }
+
+    fn entity_set_cmp(&mut self, bound: &Bound, cmp: &EntitySetCmp) {
+        use self::EntitySetCmp::*;
+        match *cmp {
+            Nand(ref v) | And(ref v) | Or(ref v) => {
+                for c in v {
+                    self.entity_set_cmp(bound, c);
+                }
+            },
+            Subset(ref a, ref b) |
+            Superset(ref a, ref b) |
+            Equal(ref a, ref b) => {
+                self.entity_set(bound, a);
+                self.entity_set(bound, b);
+            },
+            Contains(ref set, ref ent) => {
+                self.entity_set(bound, set);
+                self.entity(bound, ent);
+            },
+        }
+    }
+
+    fn entity_set(&mut self, bound: &Bound, set: &EntitySet) {
+        use self::EntitySet::*;
+        match *set {
+            None(ref v) | And(ref v) | Or(ref v) => {
+                for s in v {
+                    self.entity_set(bound, s);
+                }
+            },
+            Only(ref ent) |
+            EnemiesOf(ref ent) |
+            AllBut(ref ent) => self.entity(bound, ent),
+            IsInSlot(s) => self.load(bound, Slot::ESet(s)),
+            WithinRangeOf(ref ent, ref disc) => {
+                self.entity(bound, ent);
+                self.discrete(bound, disc);
+            },
+            HasMinResource(ref res) => self.resource(bound, res),
+            IsHuman | IsProjectile | Empty | Universe => (),
+        }
+    }
+
+    fn entity(&mut self, bound: &Bound, entity: &Entity) {
+        use self::Entity::*;
+        match *entity {
+            LoadEntity(s) => self.load(bound, Slot::E(s)),
+            FirstOf(ref set) | Choose(ref set) => self.entity_set(bound, set),
+            ClosestFrom(ref set, ref loc) => {
+                self.entity_set(bound, set);
+                self.location(bound, loc);
+            },
+        }
+    }
+
+    fn discrete(&mut self, bound: &Bound, discrete: &Discrete) {
+        use self::Discrete::*;
+        match *discrete {
+            Const(_) | Range(_, _) | WithinPercent(_, _) => (),
+            Div(ref a, ref b) => {
+                self.discrete(bound, a);
+                self.discrete(bound, b);
+            },
+            Neg(ref a) => self.discrete(bound, a),
+            Sum(ref v) | Mult(ref v) | Max(ref v) | Min(ref v) | Choose(ref v) => {
+                for d in v {
+                    self.discrete(bound, d);
+                }
+            },
+            CountStacks(_, ref tier, ref ent) |
+            CountDur(_, ref tier, ref ent) => {
+                self.tier(bound, tier);
+                self.entity(bound, ent);
+            },
+            CooldownLeft(_, ref ent) |
+            Charges(_, ref ent) => self.entity(bound, ent),
+            Cardinality(ref set) => self.entity_set(bound, set),
+            LoadFrom(s) => self.load(bound, Slot::D(s)),
+        }
+    }
+
+    fn location(&mut self, bound: &Bound, location: &Location) {
+        use self::Location::*;
+        match *location {
+            AtEntity(ref ent) => self.entity(bound, ent),
+            Midpoint(ref v) | Choose(ref v) => {
+                for l in v {
+                    self.location(bound, l);
+                }
+            },
+            LoadLocation(s) => self.load(bound, Slot::L(s)),
+        }
+    }
+
+    fn direction(&mut self, bound: &Bound, direction: &Direction) {
+        use self::Direction::*;
+        match *direction {
+            TowardLocation(ref a, ref b) => {
+                self.location(bound, a);
+                self.location(bound, b);
+            },
+            ConstRad(_) | BetweenRad(_, _) => (),
+            Choose(ref v) => {
+                for d in v {
+                    self.direction(bound, d);
+                }
+            },
+            ChooseWithinRadOf(ref d, _) => self.direction(bound, d),
+        }
+    }
+
+    fn resource(&mut self, bound: &Bound, resource: &Resource) {
+        use self::Resource::*;
+        match *resource {
+            Mana(ref d) | Health(ref d) => self.discrete(bound, d),
+            BuffStacks(_, ref tier, ref d) => {
+                self.tier(bound, tier);
+                self.discrete(bound, d);
+            },
+        }
+    }
+
+    fn tier(&mut self, bound: &Bound, tier: &BuffTier) {
+        if let BuffTier::Tier(d) = tier {
+            self.discrete(bound, d);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spell_text::Text;
+    use super::*;
+
+    // the unbound loads of a spell with this on_cast
+    fn unbound(on_cast: &str) -> Vec<(String, Slot)> {
+        let src = format!("(Spell (on_cast {}) (requires Top) (on_cooldown) (consumes) \
+            (cast_time (Const 0)) (on_cast_start) (on_interrupt))", on_cast);
+        Spell::from_text(&src).unwrap().unbound_loads().into_iter().map(|u| (u.field, u.slot)).collect()
+    }
+
+    fn on_cast(slot: Slot) -> Vec<(String, Slot)> {
+        vec![("on_cast".to_string(), slot)]
+    }
+
+    #[test]
+    fn define_binds_what_comes_after() {
+        assert_eq!(unbound("(Define (D_0 (Const 1))) (AddResource (LoadEntity E_0) (Health (LoadFrom D_0)))"), vec![]);
+        assert_eq!(unbound("(AddResource (LoadEntity E_0) (Health (LoadFrom D_0))) (Define (D_0 (Const 1)))"),
+            on_cast(Slot::D(DSlot(0))));
+        assert_eq!(unbound("(Destroy (LoadEntity E_1))"), on_cast(Slot::E(ESlot(1))));
+    }
+
+    #[test]
+    fn branches_see_outside_but_dont_leak() {
+        assert_eq!(unbound("(Define (D_0 (Const 1))) \
+            (ITE Top (then (AddResource (LoadEntity E_0) (Health (LoadFrom D_0)))) (else))"), vec![]);
+        // the engine would keep D_0 defined after taking `then`
+        assert_eq!(unbound("(ITE Top (then (Define (D_0 (Const 1)))) (else)) \
+            (AddResource (LoadEntity E_0) (Health (LoadFrom D_0)))"), on_cast(Slot::D(DSlot(0))));
+    }
+
+    #[test]
+    fn loops_and_calls_bind_for_their_body() {
+        assert_eq!(unbound("(ForEachAs E_1 Universe (Destroy (LoadEntity E_1))) (Destroy (LoadEntity E_1))"),
+            on_cast(Slot::E(ESlot(1))));
+        assert_eq!(unbound("(CallWith (D_0 (Const 2)) \
+                (ForEachAs E_1 Universe \
+                    (ITE (MoreThan (LoadFrom D_0) (Const 1)) \
+                        (then (AddResource (LoadEntity E_1) (Health (LoadFrom D_0)))) \
+                        (else)))) \
+            (AddResource (LoadEntity E_0) (Health (LoadFrom D_0)))"), on_cast(Slot::D(DSlot(0))));
+        // a definition can't load the slot it defines
+        assert_eq!(unbound("(CallWith (D_0 (LoadFrom D_0)))"), on_cast(Slot::D(DSlot(0))));
+    }
+
+    #[test]
+    fn projectiles_start_afresh() {
+        let bp = |lifetime: &str| format!("(Projectile (on_create) (on_collision (Destroy (LoadEntity E_2))) \
+            (collides_with Universe) (on_destroy (Destroy (LoadEntity E_2))) (lifetime {}))", lifetime);
+        assert_eq!(unbound(&format!("(Define (D_0 (Const 1))) (SpawnProjectileAt {} (AtEntity (LoadEntity E_0)))", bp("(LoadFrom D_0)"))),
+            vec![
+                ("on_cast.projectile.on_destroy".to_string(), Slot::E(ESlot(2))),
+                ("on_cast.projectile.lifetime".to_string(), Slot::D(DSlot(0))),
+            ]);
+    }
+}
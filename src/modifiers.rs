@@ -0,0 +1,97 @@
+use buffs::*;
+use buffs::Buff::*;
+use space::{BuffSet, UPDATES_PER_SEC};
+
+// How the buffs an entity carries change what the engine does to it: casting,
+// walking, being pushed around and receiving health or mana. The Space asks
+// these at each of those points; buff events (assets/buffs.sexp) handle the
+// rest. Effects follow the notes next to each variant in buffs.rs, and scale
+// with both tier and stacks.
+//
+// Not covered here: Wet needs electric damage, which doesn't exist yet.
+
+// every stack counts once per tier
+fn weight(buffs: &BuffSet, buff: Buff) -> f32 {
+    buffs.tiers_of(buff).into_iter()
+        .map(|t| t as f32 * buffs.stacks_of(buff, Some(t)) as f32)
+        .sum()
+}
+
+fn top_tier(buffs: &BuffSet, buff: Buff) -> u8 {
+    buffs.tiers_of(buff).last().cloned().unwrap_or(0)
+}
+
+pub fn can_cast(buffs: &BuffSet) -> bool {
+    !buffs.has_min_stacks(Mute, None, 1)
+}
+
+// the cast goes through, but does nothing except remove one stack of Confused
+pub fn cast_confused(buffs: &BuffSet) -> bool {
+    buffs.has_min_stacks(Confused, None, 1)
+}
+
+// multiplies how long a cast takes
+pub fn cast_time_factor(buffs: &BuffSet) -> f32 {
+    1.0 + 0.2 * weight(buffs, Weary)
+}
+
+// whether a cast in progress is interrupted on this tick. the more
+// Shivering, the more often
+#[allow(clippy::manual_is_multiple_of)] // u64::is_multiple_of is too new
+pub fn shivers(buffs: &BuffSet, ticks: u64) -> bool {
+    let w = weight(buffs, Shivering);
+    if w <= 0.0 { return false }
+    let every = (2.0 * UPDATES_PER_SEC as f32 / w).max(1.0) as u64;
+    ticks % every == 0
+}
+
+// how far off the cursor may land
+pub fn cursor_jitter(buffs: &BuffSet) -> f32 {
+    8.0 * weight(buffs, Dizzy)
+}
+
+// multiplies walking speed. `ticks` makes a limp come and go
+pub fn walk_factor(buffs: &BuffSet, ticks: u64) -> f32 {
+    let mut slow = 0.05 * weight(buffs, Chilled);
+    if !buffs.has_min_stacks(Resolute, None, 1) {
+        slow += 0.05 * weight(buffs, Tired);
+    }
+    let mut factor = (1.0 - slow).max(0.2);
+    if factor < 1.0 {
+        factor = (factor + 0.05 * weight(buffs, Calm)).min(1.0);
+    }
+    factor += 0.05 * weight(buffs, Panicked);
+    if buffs.has_min_stacks(Limping, None, 1) && (ticks / 15) % 2 == 1 {
+        factor *= 0.5;
+    }
+    factor
+}
+
+// multiplies velocity that magic adds
+pub fn force_factor(buffs: &BuffSet) -> f32 {
+    1.0 / (1.0 + 0.25 * weight(buffs, Resolute))
+}
+
+// Tired only wears off while standing still
+pub fn ticks_down(buff: Buff, moving: bool) -> bool {
+    !(buff == Tired && moving)
+}
+
+// a change to health, as it is received. `over_time` for damage dealt by buff events
+pub fn health_change(buffs: &BuffSet, x: i32, health_max: u32, over_time: bool) -> i32 {
+    if x >= 0 { return x }
+    let mut damage = -(x as f32);
+    if over_time {
+        damage *= 1.0 + 0.2 * weight(buffs, Delicate);
+    }
+    if damage > 0.2 * health_max as f32 {
+        damage *= 0.9f32.powi(top_tier(buffs, Tough) as i32);
+    }
+    -(damage.round() as i32)
+}
+
+pub fn mana_max(buffs: &BuffSet, mana_max: u32) -> u32 {
+    let plus = 20 * top_tier(buffs, Wise) as u32;
+    let minus = 30 * top_tier(buffs, Drained) as u32;
+    (mana_max + plus).saturating_sub(minus)
+}
@@ -41,6 +41,11 @@ impl Vector {
         Self::new_from_xy(x, y)
     }
 
+    #[inline]
+    pub fn xy(&self) -> (f32, f32) {
+        (self.x, self.y)
+    }
+
     pub fn speed(&self) -> f32 {
         hyp1(self.x, self.y)
     }
@@ -55,7 +60,7 @@ impl Vector {
                 return false
             }
         } else {
-            *self = *self * (speed / old_speed);
+            *self *= speed / old_speed;
             true
         }
     }
@@ -161,7 +166,7 @@ impl Point {
         hyp2(self.0, self.1, other.0, other.1)
     }
 
-    pub fn midpoint(pts: &Vec<Point>) -> Option<Point> {
+    pub fn midpoint(pts: &[Point]) -> Option<Point> {
         if pts.len() == 0 { return None }
         let mut mid_pt = Point::NULL;
         for pt in pts.iter() {
@@ -236,7 +241,7 @@ impl ops::Div<f32> for Point {
 
 fn hyp2(ax: f32, ay: f32, bx: f32, by: f32) -> f32 {
     (
-        sqr![((ax + bx) as f32)] + sqr![((ay + by) as f32)]
+        sqr![(ax - bx)] + sqr![(ay - by)]
     ).sqrt()
 }
 
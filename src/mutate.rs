@@ -0,0 +1,757 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+use rand::Rng;
+use magic::*;
+use buffs::Buff;
+use generate::{self, SlotsTaken};
+use profile::Profile;
+use sexp::Sexp;
+use spell_text::Text;
+
+// Currency items: small changes to a spell someone already has, rather than
+// rolling a new one. Each operator changes one random part of the spell and
+// reports what it did, so the game can show it. A change that would leave a
+// load without its slot (eg. removing the Define it came from) is thrown
+// away and tried elsewhere, ATTEMPTS times at most. On None the spell is as
+// it was.
+
+const ATTEMPTS: u32 = 20;
+
+// a part of a spell that another of its kind can stand in for
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Node {
+    Instruction(Instruction), // never a Define or Nothing
+    Condition(Condition),
+    Discrete(Discrete),
+    Entity(Entity),
+    EntitySet(EntitySet),
+    EntitySetCmp(EntitySetCmp),
+    Location(Location),
+    Direction(Direction),
+    Resource(Resource),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Modification {
+    Rerolled(Node, Node), // before, after
+    Nudged(Discrete, Discrete), // a Const or Range
+    SwappedBuff(Buff, Buff),
+    Added(Instruction),
+    Removed(Instruction),
+    CrossedOver(Node, Node), // ours, and theirs that replaced it
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Change {
+    pub field: String, // as in UnboundLoad, eg. "on_cast.projectile.on_collision"
+    pub modification: Modification,
+}
+
+// generates a new part in place of a random old one. the cast time stays
+pub fn reroll<R: Rng>(spell: &mut Spell, profile: &Profile, rng: &mut R) -> Option<Change> {
+    modify(spell, rng, ATTEMPTS, replaceable, |rng, site| {
+        let before = site.part.node()?;
+        let slots = taken(site.bound);
+        let after = generate::part(profile, rng, &before, depth_left(profile, site.depth), slots);
+        if after == before {
+            return None;
+        }
+        site.part.replace(after.clone());
+        Some(Modification::Rerolled(before, after))
+    })
+}
+
+// moves a constant, or one end of a range, by up to a fifth or so
+pub fn nudge<R: Rng>(spell: &mut Spell, rng: &mut R) -> Option<Change> {
+    use magic::Discrete::*;
+    let constant = |site: &Site| match site.part {
+        Part::Discrete(ref d) => matches!(**d, Const(_) | Range(_, _)),
+        _ => false,
+    };
+    modify(spell, rng, ATTEMPTS, constant, |rng, site| {
+        let d = match site.part {
+            Part::Discrete(ref mut d) => d,
+            _ => return None,
+        };
+        let before = (**d).clone();
+        let after = match before {
+            Const(x) => Const(x.saturating_add(step(rng, x))),
+            Range(a, b) if rng.gen() => {
+                let a = a.saturating_add(step(rng, a));
+                Range(a.min(b), a.max(b))
+            },
+            Range(a, b) => {
+                let b = b.saturating_add(step(rng, b));
+                Range(a.min(b), a.max(b))
+            },
+            _ => return None,
+        };
+        **d = after.clone();
+        Some(Modification::Nudged(before, after))
+    })
+}
+
+// another buff, by the profile's weights
+pub fn swap_buff<R: Rng>(spell: &mut Spell, profile: &Profile, rng: &mut R) -> Option<Change> {
+    let buff = |site: &Site| matches!(site.part, Part::Buff(_));
+    modify(spell, rng, ATTEMPTS, buff, |rng, site| {
+        let b = match site.part {
+            Part::Buff(ref mut b) => b,
+            _ => return None,
+        };
+        let before = **b;
+        let after = profile.buff.pick(rng, |x| x != before);
+        **b = after;
+        Some(Modification::SwappedBuff(before, after))
+    })
+}
+
+// a new instruction anywhere in a list of them. it can use whatever the
+// instructions before it define
+pub fn add_instruction<R: Rng>(spell: &mut Spell, profile: &Profile, rng: &mut R) -> Option<Change> {
+    let block = |site: &Site| matches!(site.part, Part::Block(_));
+    modify(spell, rng, ATTEMPTS, block, |rng, site| {
+        let depth = depth_left(profile, site.depth);
+        let v = match site.part {
+            Part::Block(ref mut v) => v,
+            _ => return None,
+        };
+        let at = rng.gen_range(0, v.len() + 1);
+        let mut bound = site.bound.clone();
+        for i in v[..at].iter() {
+            if let Instruction::Define(def) = i {
+                bound.insert(defined(def));
+            }
+        }
+        match generate::part(profile, rng, &Node::Instruction(Instruction::Nothing), depth, taken(&bound)) {
+            Node::Instruction(Instruction::Nothing) => None,
+            Node::Instruction(ins) => {
+                v.insert(at, ins.clone());
+                Some(Modification::Added(ins))
+            },
+            _ => None,
+        }
+    })
+}
+
+pub fn remove_instruction<R: Rng>(spell: &mut Spell, rng: &mut R) -> Option<Change> {
+    let block = |site: &Site| match site.part {
+        Part::Block(ref v) => !v.is_empty(),
+        _ => false,
+    };
+    modify(spell, rng, ATTEMPTS, block, |rng, site| {
+        let v = match site.part {
+            Part::Block(ref mut v) => v,
+            _ => return None,
+        };
+        let at = rng.gen_range(0, v.len());
+        Some(Modification::Removed(v.remove(at)))
+    })
+}
+
+// replaces a random part of the spell with one of the same kind from `donor`
+pub fn crossover<R: Rng>(spell: &mut Spell, donor: &Spell, rng: &mut R) -> Option<Change> {
+    let mut theirs = vec![];
+    look(&mut donor.clone(), |site| {
+        if replaceable(&site) {
+            theirs.extend(site.part.node());
+        }
+    });
+    for _ in 0..ATTEMPTS {
+        let with = match rng.choose(&theirs) {
+            Some(n) => n.clone(),
+            None => return None,
+        };
+        let same_kind = |site: &Site| replaceable(site) && site.part.fits(&with);
+        let change = modify(spell, rng, 1, same_kind, |_, site| {
+            let before = site.part.node()?;
+            if before == with {
+                return None;
+            }
+            site.part.replace(with.clone());
+            Some(Modification::CrossedOver(before, with.clone()))
+        });
+        if change.is_some() {
+            return change;
+        }
+    }
+    None
+}
+
+// changes the part `change` is given, one picked at random from those
+// `which` picks out. keeps the result if it leaves no load unbound that the
+// spell didn't already, counting each one as often as it appears
+fn modify<R, W, C>(spell: &mut Spell, rng: &mut R, attempts: u32, which: W, mut change: C) -> Option<Change>
+where R: Rng, W: Fn(&Site) -> bool, C: FnMut(&mut R, &mut Site) -> Option<Modification> {
+    let unbound = spell.unbound_loads();
+    for _ in 0..attempts {
+        let mut copy = spell.clone();
+        let mut n = 0;
+        look(&mut copy, |site| if which(&site) { n += 1 });
+        if n == 0 {
+            return None;
+        }
+        let k = rng.gen_range(0, n);
+        let mut i = 0;
+        let mut result = None;
+        walk(&mut copy, |mut site| {
+            if which(&site) {
+                if i == k {
+                    result = change(rng, &mut site)
+                        .map(|m| Change { field: site.field.to_string(), modification: m });
+                }
+                i += 1;
+            }
+        });
+        if let Some(c) = result {
+            let now = copy.unbound_loads();
+            let count = |v: &[UnboundLoad], u: &UnboundLoad| v.iter().filter(|x| *x == u).count();
+            if now.iter().all(|u| count(&now, u) <= count(&unbound, u)) {
+                *spell = copy;
+                return Some(c);
+            }
+        }
+    }
+    None
+}
+
+fn replaceable(site: &Site) -> bool {
+    site.part.node_kind() && site.field != "cast_time"
+}
+
+fn depth_left(profile: &Profile, depth: i16) -> i16 {
+    (profile.max_depth as i16 - depth).max(2)
+}
+
+// generate.rs numbers slots from 0, so only those bound without gaps
+fn taken(bound: &Bound) -> SlotsTaken {
+    SlotsTaken {
+        ent: upto(bound, |i| Slot::E(ESlot(i))),
+        ent_set: upto(bound, |i| Slot::ESet(ESetSlot(i))),
+        loc: upto(bound, |i| Slot::L(LSlot(i))),
+        disc: upto(bound, |i| Slot::D(DSlot(i))),
+    }
+}
+
+fn upto<F: Fn(u8) -> Slot>(bound: &Bound, slot: F) -> u8 {
+    (0..u8::MAX).take_while(|&i| bound.contains(&slot(i))).count() as u8
+}
+
+fn defined(def: &Definition) -> Slot {
+    match *def {
+        Definition::ESet(s, _) => Slot::ESet(s),
+        Definition::E(s, _) => Slot::E(s),
+        Definition::D(s, _) => Slot::D(s),
+        Definition::L(s, _) => Slot::L(s),
+    }
+}
+
+// a step of a size suited to x
+fn step<R: Rng>(rng: &mut R, x: i32) -> i32 {
+    let by = rng.gen_range(1, 2 + x.saturating_abs() / 5);
+    if rng.gen() { by } else { -by }
+}
+
+impl Node {
+    pub fn to_sexp(&self) -> Sexp {
+        use self::Node::*;
+        match *self {
+            Instruction(ref x) => x.to_sexp(),
+            Condition(ref x) => x.to_sexp(),
+            Discrete(ref x) => x.to_sexp(),
+            Entity(ref x) => x.to_sexp(),
+            EntitySet(ref x) => x.to_sexp(),
+            EntitySetCmp(ref x) => x.to_sexp(),
+            Location(ref x) => x.to_sexp(),
+            Direction(ref x) => x.to_sexp(),
+            Resource(ref x) => x.to_sexp(),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Modification::*;
+        write!(f, "{}: ", self.field)?;
+        match self.modification {
+            Rerolled(ref a, ref b) => write!(f, "rerolled {} into {}", a.to_sexp(), b.to_sexp()),
+            Nudged(ref a, ref b) => write!(f, "nudged {} to {}", a.to_sexp(), b.to_sexp()),
+            SwappedBuff(a, b) => write!(f, "swapped {} for {}", a.to_sexp(), b.to_sexp()),
+            Added(ref i) => write!(f, "added {}", i.to_sexp()),
+            Removed(ref i) => write!(f, "removed {}", i.to_sexp()),
+            CrossedOver(ref a, ref b) => write!(f, "replaced {} with {}", a.to_sexp(), b.to_sexp()),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////
+
+// WALKING
+// Visits every part of a spell mutably, in a fixed order, with the slots
+// bound there. Scoping is as in magic.rs' slot checking.
+
+type Bound = HashSet<Slot>;
+
+enum Part<'a> {
+    Instruction(&'a mut Instruction),
+    Condition(&'a mut Condition),
+    Discrete(&'a mut Discrete),
+    Entity(&'a mut Entity),
+    EntitySet(&'a mut EntitySet),
+    EntitySetCmp(&'a mut EntitySetCmp),
+    Location(&'a mut Location),
+    Direction(&'a mut Direction),
+    Resource(&'a mut Resource),
+    Block(&'a mut Vec<Instruction>), // bound is as at its start
+    Buff(&'a mut Buff),
+}
+
+struct Site<'a, 'b> {
+    field: &'b str,
+    bound: &'b Bound,
+    depth: i16, // parts above this one
+    part: Part<'a>,
+}
+
+impl<'a> Part<'a> {
+    fn node_kind(&self) -> bool {
+        !matches!(self, &Part::Block(_) | &Part::Buff(_))
+    }
+
+    fn node(&self) -> Option<Node> {
+        match *self {
+            Part::Instruction(ref x) => Some(Node::Instruction((**x).clone())),
+            Part::Condition(ref x) => Some(Node::Condition((**x).clone())),
+            Part::Discrete(ref x) => Some(Node::Discrete((**x).clone())),
+            Part::Entity(ref x) => Some(Node::Entity((**x).clone())),
+            Part::EntitySet(ref x) => Some(Node::EntitySet((**x).clone())),
+            Part::EntitySetCmp(ref x) => Some(Node::EntitySetCmp((**x).clone())),
+            Part::Location(ref x) => Some(Node::Location((**x).clone())),
+            Part::Direction(ref x) => Some(Node::Direction((**x).clone())),
+            Part::Resource(ref x) => Some(Node::Resource((**x).clone())),
+            Part::Block(_) | Part::Buff(_) => None,
+        }
+    }
+
+    fn fits(&self, node: &Node) -> bool {
+        matches!((self, node),
+            (&Part::Instruction(_), &Node::Instruction(_))
+            | (&Part::Condition(_), &Node::Condition(_))
+            | (&Part::Discrete(_), &Node::Discrete(_))
+            | (&Part::Entity(_), &Node::Entity(_))
+            | (&Part::EntitySet(_), &Node::EntitySet(_))
+            | (&Part::EntitySetCmp(_), &Node::EntitySetCmp(_))
+            | (&Part::Location(_), &Node::Location(_))
+            | (&Part::Direction(_), &Node::Direction(_))
+            | (&Part::Resource(_), &Node::Resource(_)))
+    }
+
+    // does nothing if the node doesn't fit
+    fn replace(&mut self, node: Node) {
+        match (self, node) {
+            (&mut Part::Instruction(ref mut x), Node::Instruction(n)) => **x = n,
+            (&mut Part::Condition(ref mut x), Node::Condition(n)) => **x = n,
+            (&mut Part::Discrete(ref mut x), Node::Discrete(n)) => **x = n,
+            (&mut Part::Entity(ref mut x), Node::Entity(n)) => **x = n,
+            (&mut Part::EntitySet(ref mut x), Node::EntitySet(n)) => **x = n,
+            (&mut Part::EntitySetCmp(ref mut x), Node::EntitySetCmp(n)) => **x = n,
+            (&mut Part::Location(ref mut x), Node::Location(n)) => **x = n,
+            (&mut Part::Direction(ref mut x), Node::Direction(n)) => **x = n,
+            (&mut Part::Resource(ref mut x), Node::Resource(n)) => **x = n,
+            _ => (),
+        }
+    }
+}
+
+// blueprints are shared, so only a walk that may edit them makes its own copies
+fn walk<F: FnMut(Site)>(spell: &mut Spell, visit: F) {
+    Walker { field: String::new(), visit, edit: true }.spell(spell)
+}
+
+// a walk that edits nothing. changes to blueprints are lost
+fn look<F: FnMut(Site)>(spell: &mut Spell, visit: F) {
+    Walker { field: String::new(), visit, edit: false }.spell(spell)
+}
+
+struct Walker<F> {
+    field: String,
+    visit: F,
+    edit: bool,
+}
+
+impl<F: FnMut(Site)> Walker<F> {
+    fn at(&mut self, bound: &Bound, depth: i16, part: Part) {
+        (self.visit)(Site { field: &self.field, bound, depth, part });
+    }
+
+    fn in_field<G>(&mut self, name: &str, bound: &[Slot], work: G)
+    where G: FnOnce(&mut Self, &Bound) {
+        let outer_len = self.field.len();
+        if outer_len > 0 {
+            self.field.push('.');
+        }
+        self.field.push_str(name);
+        let bound = bound.iter().cloned().collect();
+        work(self, &bound);
+        self.field.truncate(outer_len);
+    }
+
+    fn spell(&mut self, s: &mut Spell) {
+        let caster_cursor = [Slot::E(ESlot(0)), Slot::L(LSlot(0))];
+        self.in_field("on_cast", &caster_cursor, |w, b| w.block(b, 0, &mut s.on_cast));
+        self.in_field("requires", &caster_cursor, |w, b| w.condition(b, 0, &mut s.requires));
+        self.in_field("on_cooldown", &[Slot::E(ESlot(0))], |w, b| w.block(b, 0, &mut s.on_cooldown));
+        self.in_field("consumes", &caster_cursor, |w, b| {
+            for r in s.consumes.iter_mut() {
+                w.resource(b, 0, r);
+            }
+        });
+        self.in_field("cast_time", &caster_cursor, |w, b| w.discrete(b, 0, &mut s.cast_time));
+        self.in_field("on_cast_start", &caster_cursor, |w, b| w.block(b, 0, &mut s.on_cast_start));
+        self.in_field("on_interrupt", &caster_cursor, |w, b| w.block(b, 0, &mut s.on_interrupt));
+    }
+
+    fn blueprint(&mut self, d: i16, bp: &mut ProjectileBlueprint) {
+        let me = [Slot::E(ESlot(0)), Slot::E(ESlot(1)), Slot::L(LSlot(0))];
+        let hit = [Slot::E(ESlot(0)), Slot::E(ESlot(1)), Slot::E(ESlot(2)), Slot::L(LSlot(0))];
+        self.in_field("on_create", &me, |w, b| w.block(b, d, &mut bp.on_create));
+        self.in_field("on_collision", &hit, |w, b| w.block(b, d, &mut bp.on_collision));
+        self.in_field("collides_with", &me, |w, b| w.entity_set(b, d, &mut bp.collides_with));
+        self.in_field("on_destroy", &me, |w, b| w.block(b, d, &mut bp.on_destroy));
+        self.in_field("lifetime", &me, |w, b| w.discrete(b, d, &mut bp.lifetime));
+    }
+
+    fn block(&mut self, bound: &Bound, d: i16, v: &mut Vec<Instruction>) {
+        self.at(bound, d, Part::Block(&mut *v));
+        let mut bound = bound.clone();
+        for i in v.iter_mut() {
+            self.instruction(&mut bound, d + 1, i);
+        }
+    }
+
+    fn instruction(&mut self, bound: &mut Bound, d: i16, i: &mut Instruction) {
+        use magic::Instruction::*;
+        if !matches!(*i, Define(_) | Nothing) {
+            self.at(bound, d, Part::Instruction(&mut *i));
+        }
+        let d = d + 1;
+        match i {
+            &mut Define(ref mut def) => {
+                let slot = self.definition(bound, d, def);
+                bound.insert(slot);
+            },
+            &mut ITE(ref mut cond, ref mut then, ref mut els) => {
+                self.condition(bound, d, cond);
+                self.block(bound, d, then);
+                self.block(bound, d, els);
+            },
+            &mut CallWith(ref mut def, ref mut ins) => {
+                let mut inner = bound.clone();
+                let slot = self.definition(bound, d, def);
+                inner.insert(slot);
+                self.block(&inner, d, ins);
+            },
+            &mut ForEachAs(slot, ref mut set, ref mut ins) => {
+                self.entity_set(bound, d, set);
+                let mut inner = bound.clone();
+                inner.insert(Slot::E(slot));
+                self.block(&inner, d, ins);
+            },
+            &mut DestroyWithoutEvent(ref mut ent) |
+            &mut Destroy(ref mut ent) => self.entity(bound, d, ent),
+            &mut MoveEntity(ref mut ent, ref mut loc) => {
+                self.entity(bound, d, ent);
+                self.location(bound, d, loc);
+            },
+            &mut AddResource(ref mut ent, ref mut res) => {
+                self.entity(bound, d, ent);
+                self.resource(bound, d, res);
+            },
+            &mut AddVelocity(ref mut ent, ref mut dir, ref mut disc) => {
+                self.entity(bound, d, ent);
+                self.direction(bound, d, dir);
+                self.discrete(bound, d, disc);
+            },
+            &mut SpawnProjectileAt(ref mut bp, ref mut loc) => {
+                self.location(bound, d, loc);
+                if self.edit {
+                    let bp = Rc::make_mut(bp);
+                    self.in_field("projectile", &[], |w, _| w.blueprint(d, bp));
+                } else {
+                    let mut bp = (**bp).clone();
+                    self.in_field("projectile", &[], |w, _| w.blueprint(d, &mut bp));
+                }
+            },
+            &mut Nothing => (),
+        }
+    }
+
+    fn definition(&mut self, bound: &Bound, d: i16, definition: &mut Definition) -> Slot {
+        use magic::Definition::*;
+        match *definition {
+            ESet(s, ref mut x) => { self.entity_set(bound, d, x); Slot::ESet(s) },
+            E(s, ref mut x) => { self.entity(bound, d, x); Slot::E(s) },
+            D(s, ref mut x) => { self.discrete(bound, d, x); Slot::D(s) },
+            L(s, ref mut x) => { self.location(bound, d, x); Slot::L(s) },
+        }
+    }
+
+    fn condition(&mut self, bound: &Bound, d: i16, condition: &mut Condition) {
+        use magic::Condition::*;
+        self.at(bound, d, Part::Condition(&mut *condition));
+        let d = d + 1;
+        match condition {
+            &mut Nand(ref mut v) | &mut And(ref mut v) | &mut Or(ref mut v) => {
+                for c in v.iter_mut() {
+                    self.condition(bound, d, c);
+                }
+            },
+            &mut Top | &mut Bottom => (),
+            &mut Equals(ref mut a, ref mut b) |
+            &mut LessThan(ref mut a, ref mut b) |
+            &mut MoreThan(ref mut a, ref mut b) => {
+                self.discrete(bound, d, a);
+                self.discrete(bound, d, b);
+            },
+            &mut EntitySetCmp(ref mut cmp) => self.entity_set_cmp(bound, d, cmp),
+        }
+    }
+
+    fn entity_set_cmp(&mut self, bound: &Bound, d: i16, cmp: &mut EntitySetCmp) {
+        use magic::EntitySetCmp::*;
+        self.at(bound, d, Part::EntitySetCmp(&mut *cmp));
+        let d = d + 1;
+        match cmp {
+            &mut Nand(ref mut v) | &mut And(ref mut v) | &mut Or(ref mut v) => {
+                for c in v.iter_mut() {
+                    self.entity_set_cmp(bound, d, c);
+                }
+            },
+            &mut Subset(ref mut a, ref mut b) |
+            &mut Superset(ref mut a, ref mut b) |
+            &mut Equal(ref mut a, ref mut b) => {
+                self.entity_set(bound, d, a);
+                self.entity_set(bound, d, b);
+            },
+            &mut Contains(ref mut set, ref mut ent) => {
+                self.entity_set(bound, d, set);
+                self.entity(bound, d, ent);
+            },
+        }
+    }
+
+    fn entity_set(&mut self, bound: &Bound, d: i16, set: &mut EntitySet) {
+        use magic::EntitySet::*;
+        self.at(bound, d, Part::EntitySet(&mut *set));
+        let d = d + 1;
+        match set {
+            &mut None(ref mut v) | &mut And(ref mut v) | &mut Or(ref mut v) => {
+                for s in v.iter_mut() {
+                    self.entity_set(bound, d, s);
+                }
+            },
+            &mut Only(ref mut ent) |
+            &mut EnemiesOf(ref mut ent) |
+            &mut AllBut(ref mut ent) => self.entity(bound, d, ent),
+            &mut WithinRangeOf(ref mut ent, ref mut disc) => {
+                self.entity(bound, d, ent);
+                self.discrete(bound, d, disc);
+            },
+            &mut HasMinResource(ref mut res) => self.resource(bound, d, res),
+            &mut IsInSlot(_) | &mut IsHuman | &mut IsProjectile | &mut Empty | &mut Universe => (),
+        }
+    }
+
+    fn entity(&mut self, bound: &Bound, d: i16, entity: &mut Entity) {
+        use magic::Entity::*;
+        self.at(bound, d, Part::Entity(&mut *entity));
+        let d = d + 1;
+        match entity {
+            &mut LoadEntity(_) => (),
+            &mut FirstOf(ref mut set) | &mut Choose(ref mut set) => self.entity_set(bound, d, set),
+            &mut ClosestFrom(ref mut set, ref mut loc) => {
+                self.entity_set(bound, d, set);
+                self.location(bound, d, loc);
+            },
+        }
+    }
+
+    fn discrete(&mut self, bound: &Bound, d: i16, discrete: &mut Discrete) {
+        use magic::Discrete::*;
+        self.at(bound, d, Part::Discrete(&mut *discrete));
+        let d = d + 1;
+        match discrete {
+            &mut Const(_) | &mut Range(_, _) | &mut WithinPercent(_, _) | &mut LoadFrom(_) => (),
+            &mut Div(ref mut a, ref mut b) => {
+                self.discrete(bound, d, a);
+                self.discrete(bound, d, b);
+            },
+            &mut Neg(ref mut a) => self.discrete(bound, d, a),
+            &mut Sum(ref mut v) | &mut Mult(ref mut v) | &mut Max(ref mut v)
+            | &mut Min(ref mut v) | &mut Choose(ref mut v) => {
+                for x in v.iter_mut() {
+                    self.discrete(bound, d, x);
+                }
+            },
+            &mut CountStacks(ref mut buff, ref mut tier, ref mut ent) |
+            &mut CountDur(ref mut buff, ref mut tier, ref mut ent) => {
+                self.at(bound, d, Part::Buff(buff));
+                self.tier(bound, d, tier);
+                self.entity(bound, d, ent);
+            },
+            &mut CooldownLeft(_, ref mut ent) |
+            &mut Charges(_, ref mut ent) => self.entity(bound, d, ent),
+            &mut Cardinality(ref mut set) => self.entity_set(bound, d, set),
+        }
+    }
+
+    fn location(&mut self, bound: &Bound, d: i16, location: &mut Location) {
+        use magic::Location::*;
+        self.at(bound, d, Part::Location(&mut *location));
+        let d = d + 1;
+        match location {
+            &mut AtEntity(ref mut ent) => self.entity(bound, d, ent),
+            &mut Midpoint(ref mut v) | &mut Choose(ref mut v) => {
+                for l in v.iter_mut() {
+                    self.location(bound, d, l);
+                }
+            },
+            &mut LoadLocation(_) => (),
+        }
+    }
+
+    fn direction(&mut self, bound: &Bound, d: i16, direction: &mut Direction) {
+        use magic::Direction::*;
+        self.at(bound, d, Part::Direction(&mut *direction));
+        let d = d + 1;
+        match direction {
+            &mut TowardLocation(ref mut a, ref mut b) => {
+                self.location(bound, d, a);
+                self.location(bound, d, b);
+            },
+            &mut ConstRad(_) | &mut BetweenRad(_, _) => (),
+            &mut Choose(ref mut v) => {
+                for x in v.iter_mut() {
+                    self.direction(bound, d, x);
+                }
+            },
+            &mut ChooseWithinRadOf(ref mut x, _) => self.direction(bound, d, x),
+        }
+    }
+
+    fn resource(&mut self, bound: &Bound, d: i16, resource: &mut Resource) {
+        use magic::Resource::*;
+        self.at(bound, d, Part::Resource(&mut *resource));
+        let d = d + 1;
+        match resource {
+            &mut Mana(ref mut x) | &mut Health(ref mut x) => self.discrete(bound, d, x),
+            &mut BuffStacks(ref mut buff, ref mut tier, ref mut x) => {
+                self.at(bound, d, Part::Buff(buff));
+                self.tier(bound, d, tier);
+                self.discrete(bound, d, x);
+            },
+        }
+    }
+
+    fn tier(&mut self, bound: &Bound, d: i16, tier: &mut BuffTier) {
+        if let &mut BuffTier::Tier(ref mut x) = tier {
+            self.discrete(bound, d, x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, Isaac64Rng, SeedableRng};
+    use generate;
+    use super::*;
+
+    fn operate<R: Rng>(op: usize, spell: &mut Spell, profile: &Profile, donor: &Spell, rng: &mut R) -> Option<Change> {
+        match op {
+            0 => reroll(spell, profile, rng),
+            1 => nudge(spell, rng),
+            2 => swap_buff(spell, profile, rng),
+            3 => add_instruction(spell, profile, rng),
+            4 => remove_instruction(spell, rng),
+            _ => crossover(spell, donor, rng),
+        }
+    }
+
+    // the blocks, nodes and buffs of a spell in `field`
+    fn parts_in(spell: &Spell, field: &str) -> (Vec<Vec<Instruction>>, Vec<Node>, Vec<Buff>) {
+        let (mut blocks, mut nodes, mut buffs) = (vec![], vec![], vec![]);
+        look(&mut spell.clone(), |site| {
+            if site.field != field {
+                return;
+            }
+            match site.part {
+                Part::Block(ref v) => blocks.push((**v).clone()),
+                Part::Buff(ref b) => buffs.push(**b),
+                ref part => nodes.extend(part.node()),
+            }
+        });
+        (blocks, nodes, buffs)
+    }
+
+    fn copies(blocks: &[Vec<Instruction>], ins: &Instruction) -> usize {
+        blocks.iter().flat_map(|v| v.iter()).filter(|i| *i == ins).count()
+    }
+
+    // the change names what was in its field before, and what is there now
+    fn reports(before: &Spell, after: &Spell, c: &Change) -> bool {
+        use self::Modification::*;
+        let (old_blocks, old, old_buffs) = parts_in(before, &c.field);
+        let (new_blocks, new, new_buffs) = parts_in(after, &c.field);
+        match c.modification {
+            Rerolled(ref a, ref b) | CrossedOver(ref a, ref b) => a != b && old.contains(a) && new.contains(b),
+            Nudged(ref a, ref b) => old.contains(&Node::Discrete(a.clone())) && new.contains(&Node::Discrete(b.clone())),
+            SwappedBuff(a, b) => a != b && old_buffs.contains(&a) && new_buffs.contains(&b),
+            Added(ref i) => copies(&new_blocks, i) == copies(&old_blocks, i) + 1,
+            Removed(ref i) => copies(&new_blocks, i) + 1 == copies(&old_blocks, i),
+        }
+    }
+
+    #[test]
+    fn every_operator_keeps_loads_bound_and_reports_its_change() {
+        let profile = Profile::parse(include_str!("../assets/profiles/standard.sexp")).unwrap();
+        let spells = generate::samples(10);
+        for op in 0..6 {
+            let mut changed = 0;
+            for seed in 0..200 {
+                let mut rng = Isaac64Rng::from_seed(&[seed]);
+                let before = rng.choose(&spells).unwrap().clone();
+                let donor = rng.choose(&spells).unwrap().clone();
+                let mut after = before.clone();
+                match operate(op, &mut after, &profile, &donor, &mut rng) {
+                    Some(c) => {
+                        assert!(reports(&before, &after, &c), "{}", c);
+                        changed += 1;
+                    },
+                    None => assert_eq!(after, before),
+                }
+                assert_eq!(after.unbound_loads(), before.unbound_loads());
+            }
+            assert!(changed > 20, "operator {} changed {} spells", op, changed);
+        }
+    }
+
+    // E_5 is never bound, so copying its load elsewhere would leave two
+    #[test]
+    fn an_unbound_load_isnt_copied() {
+        let spell = Spell::from_text("(Spell
+            (on_cast (AddResource (LoadEntity E_5) (Health (Const -3))) (AddResource (LoadEntity E_0) (Mana (Const 1))))
+            (requires Top) (on_cooldown) (consumes) (cast_time (Const 0)) (on_cast_start) (on_interrupt))").unwrap();
+        let mut crossed = 0;
+        for seed in 0..200 {
+            let mut rng = Isaac64Rng::from_seed(&[seed]);
+            let mut after = spell.clone();
+            if crossover(&mut after, &spell, &mut rng).is_some() {
+                crossed += 1;
+            }
+            // it may be crossed over by a bound one, though
+            assert!(after.unbound_loads().len() <= 1);
+        }
+        assert!(crossed > 20);
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use magic::*;
+use buffs::MAX_TIER;
+
+// A rough guess at how strong a spell is, so generated spells that are far
+// too strong (or do nothing) can be thrown out. It walks the whole spell,
+// projectiles included, and lists its effects: every instruction that does
+// something, who it lands on and how often it is expected to run per cast.
+// Estimates add those up. Numbers come from Discrete::estimate, while
+// conditions and entity sets get fixed guesses, so a score only means
+// something next to other scores.
+//
+// Everything is counted in health: a point of mana, a stack of a buff or a
+// unit of speed is worth some fixed amount of it (see below).
+
+const MANA_VALUE: f32 = 0.5;
+const STACK_VALUE: f32 = 3.0;  // per tier
+const SPEED_VALUE: f32 = 0.5;
+const TELEPORT: f32 = 30.0;    // speed a MoveEntity is worth
+const DESTROY: f32 = 100.0;    // destroying a player takes them out of the game
+const HIT_CHANCE: f32 = 0.5;   // of a projectile touching each entity it may collide with
+const INTERRUPT_CHANCE: f32 = 0.2; // of a cast that takes time
+const MAX_SET: f32 = 6.0;      // as Discrete::estimate guesses for Cardinality
+const MAX_NUMBER: f32 = 1000.0;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Power {
+    pub cost: f32,         // health the caster loses, and what it pays in consumes
+    pub damage: f32,       // health others lose, less what they gain
+    pub healing: f32,      // health the caster gains
+    pub displacement: f32, // speed given to players
+    pub buff_stacks: f32,  // harmful stacks on others and helpful ones on the caster, less the reverse
+    pub targets: f32,      // entities affected, counted once per effect
+    pub chance: f32,       // that `requires` holds
+    pub cast_time: f32,    // seconds
+}
+
+impl Power {
+    // what a cast does, in health
+    pub fn effect(&self) -> f32 {
+        self.damage + self.healing
+        + SPEED_VALUE * self.displacement
+        + STACK_VALUE * self.buff_stacks
+    }
+
+    // what a cast is worth on average, less what it costs, over each second
+    // of casting. 0 for a spell that does as much as it costs
+    pub fn score(&self) -> f32 {
+        self.chance * (self.effect() - self.cost) / (1.0 + self.cast_time)
+    }
+}
+
+// who an effect lands on
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Target {
+    Caster,
+    Projectile, // one of the spell's own: the one running the event, or one it spawns
+    Enemy,      // drawn from the enemies of the caster
+    Other,      // anyone, possibly the caster
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Effect<'a> {
+    pub target: Target,
+    pub times: f32, // expected runs per cast. 0 if it never runs
+    pub instruction: &'a Instruction, // a Destroy, MoveEntity, AddResource, AddVelocity or SpawnProjectileAt
+}
+
+pub fn effects(spell: &Spell) -> Vec<Effect<'_>> {
+    let mut w = Walker { effects: vec![] };
+    w.instructions(&mut Scope::caster_only(), 1.0, &spell.on_cast_start);
+    w.instructions(&mut Scope::caster_only(), 1.0, &spell.on_cast);
+    w.instructions(&mut Scope::caster_only(), 1.0, &spell.on_cooldown);
+    let interrupted = if number(&spell.cast_time) > 0.0 { INTERRUPT_CHANCE } else { 0.0 };
+    w.instructions(&mut Scope::caster_only(), interrupted, &spell.on_interrupt);
+    w.effects
+}
+
+pub fn estimate(spell: &Spell) -> Power {
+    let mut power = Power {
+        chance: chance(&spell.requires),
+        cast_time: number(&spell.cast_time).max(0.0),
+        ..Power::default()
+    };
+    for r in spell.consumes.iter() {
+        // consuming is adding the negative
+        resource(&mut power, Target::Caster, -1.0, r);
+    }
+    for e in effects(spell) {
+        if e.target != Target::Projectile {
+            power.targets += e.times;
+        }
+        estimate_effect(&mut power, &e);
+    }
+    power
+}
+
+fn estimate_effect(power: &mut Power, e: &Effect) {
+    use magic::Instruction::*;
+    let times = e.times;
+    match (e.target, e.instruction) {
+        (_, AddResource(_, r)) => resource(power, e.target, times, r),
+        (Target::Projectile, _) => (),
+        (Target::Caster, &DestroyWithoutEvent(_)) | (Target::Caster, &Destroy(_)) => power.cost += times * DESTROY,
+        (_, &DestroyWithoutEvent(_)) | (_, &Destroy(_)) => power.damage += times * DESTROY,
+        (_, &MoveEntity(_, _)) => power.displacement += times * TELEPORT,
+        (_, AddVelocity(_, _, speed)) => power.displacement += times * number(speed).abs(),
+        _ => (),
+    }
+}
+
+fn resource(power: &mut Power, target: Target, times: f32, r: &Resource) {
+    use magic::Resource::*;
+    match *r {
+        Health(ref d) => {
+            let x = times * number(d);
+            match target {
+                Target::Caster if x > 0.0 => power.healing += x,
+                Target::Caster => power.cost -= x,
+                Target::Enemy | Target::Other => power.damage -= x,
+                Target::Projectile => (),
+            }
+        },
+        Mana(ref d) => {
+            let x = times * number(d) * MANA_VALUE;
+            match target {
+                Target::Caster => power.cost -= x,
+                Target::Enemy | Target::Other => power.damage -= x,
+                Target::Projectile => (),
+            }
+        },
+        BuffStacks(buff, ref tier, ref d) => {
+            let x = times * number(d) * tier_estimate(tier);
+            let x = if buff.harmful() { x } else { -x };
+            match target {
+                Target::Caster => power.buff_stacks -= x,
+                Target::Enemy | Target::Other => power.buff_stacks += x,
+                Target::Projectile => (),
+            }
+        },
+    }
+}
+
+// who the entity slots and entity set slots in scope hold
+#[derive(Clone)]
+struct Scope {
+    ents: HashMap<ESlot, Target>,
+    sets: HashMap<ESetSlot, Target>,
+}
+
+impl Scope {
+    fn caster_only() -> Scope {
+        let mut ents = HashMap::new();
+        ents.insert(ESlot(0), Target::Caster);
+        Scope { ents, sets: HashMap::new() }
+    }
+
+    fn define(&mut self, d: &Definition) {
+        match *d {
+            Definition::E(slot, ref e) => {
+                let target = self.entity(e);
+                self.ents.insert(slot, target);
+            },
+            Definition::ESet(slot, ref set) => {
+                let target = self.set(set);
+                self.sets.insert(slot, target);
+            },
+            _ => (),
+        }
+    }
+
+    fn entity(&self, e: &Entity) -> Target {
+        use magic::Entity::*;
+        match e {
+            &LoadEntity(slot) => self.ents.get(&slot).cloned().unwrap_or(Target::Other),
+            &FirstOf(ref set) | &Choose(ref set) | &ClosestFrom(ref set, _) => self.set(set),
+        }
+    }
+
+    // who every member of the set is
+    fn set(&self, set: &EntitySet) -> Target {
+        use magic::EntitySet::*;
+        match *set {
+            Only(ref e) => self.entity(e),
+            IsInSlot(slot) => self.sets.get(&slot).cloned().unwrap_or(Target::Other),
+            EnemiesOf(ref e) if self.entity(e) == Target::Caster => Target::Enemy,
+            And(ref v) => {
+                let members = v.iter().map(|s| self.set(s)).collect::<Vec<_>>();
+                if members.contains(&Target::Caster) {
+                    Target::Caster // at most
+                } else if members.contains(&Target::Enemy) {
+                    Target::Enemy
+                } else { Target::Other }
+            },
+            Or(ref v) if !v.is_empty() && v.iter().all(|s| self.set(s) == Target::Enemy) => Target::Enemy,
+            _ => Target::Other,
+        }
+    }
+}
+
+struct Walker<'a> {
+    effects: Vec<Effect<'a>>,
+}
+
+impl<'a> Walker<'a> {
+    // `times`: how often the instructions are expected to run per cast
+    fn instructions(&mut self, scope: &mut Scope, times: f32, block: &'a [Instruction]) {
+        for i in block.iter() {
+            self.instruction(scope, times, i);
+        }
+    }
+
+    fn instruction(&mut self, scope: &mut Scope, times: f32, ins: &'a Instruction) {
+        use magic::Instruction::*;
+        let target = match *ins {
+            Define(ref d) => return scope.define(d),
+            ITE(ref c, ref t, ref e) => {
+                let p = chance(c);
+                self.instructions(&mut scope.clone(), times * p, t);
+                self.instructions(&mut scope.clone(), times * (1.0 - p), e);
+                return;
+            },
+            CallWith(ref d, ref body) => {
+                let mut inner = scope.clone();
+                inner.define(d);
+                return self.instructions(&mut inner, times, body);
+            },
+            ForEachAs(slot, ref set, ref body) => {
+                let mut inner = scope.clone();
+                inner.ents.insert(slot, scope.set(set));
+                return self.instructions(&mut inner, times * set_size(set), body);
+            },
+            DestroyWithoutEvent(ref e) | Destroy(ref e) | MoveEntity(ref e, _)
+            | AddResource(ref e, _) | AddVelocity(ref e, _, _) => scope.entity(e),
+            SpawnProjectileAt(ref bp, _) => {
+                self.blueprint(times, bp);
+                Target::Projectile
+            },
+            Nothing => return,
+        };
+        self.effects.push(Effect { target, times, instruction: ins });
+    }
+
+    fn blueprint(&mut self, times: f32, bp: &'a ProjectileBlueprint) {
+        let mut scope = Scope::caster_only();
+        scope.ents.insert(ESlot(1), Target::Projectile);
+        self.instructions(&mut scope.clone(), times, &bp.on_create);
+        // expiry comes before collisions, so a projectile without lifetime hits nothing
+        if number(&bp.lifetime) > 0.0 {
+            let mut hit = scope.clone();
+            let target = scope.set(&bp.collides_with);
+            hit.ents.insert(ESlot(2), target);
+            let hits = HIT_CHANCE * set_size(&bp.collides_with);
+            self.instructions(&mut hit, times * hits, &bp.on_collision);
+        }
+        self.instructions(&mut scope, times, &bp.on_destroy);
+    }
+}
+
+// Discrete::estimate can come out huge, infinite or NaN, eg. on a Div by
+// something near 0
+fn number(d: &Discrete) -> f32 {
+    let x = d.estimate();
+    if x.is_nan() { 0.0 } else { x.clamp(-MAX_NUMBER, MAX_NUMBER) }
+}
+
+fn tier_estimate(tier: &BuffTier) -> f32 {
+    match *tier {
+        BuffTier::Tier(ref d) => number(d).clamp(1.0, MAX_TIER as f32),
+        BuffTier::AnyTier => 1.0, // adds go to tier 1
+    }
+}
+
+// how many entities a set is expected to hold
+fn set_size(set: &EntitySet) -> f32 {
+    use magic::EntitySet::*;
+    match *set {
+        None(ref v) => MAX_SET - v.iter().map(set_size).sum::<f32>().min(MAX_SET),
+        And(ref v) => v.iter().map(set_size).fold(MAX_SET, f32::min),
+        Or(ref v) => v.iter().map(set_size).sum::<f32>().min(MAX_SET),
+        Only(_) => 1.0,
+        IsInSlot(_) => 2.0,
+        WithinRangeOf(_, _) => 2.0,
+        HasMinResource(_) => 2.0,
+        EnemiesOf(_) => 3.0,
+        AllBut(_) => MAX_SET - 1.0,
+        IsHuman => 2.0,
+        IsProjectile => 3.0,
+        Empty => 0.0,
+        Universe => MAX_SET,
+    }
+}
+
+// the probability that a condition holds
+fn chance(cond: &Condition) -> f32 {
+    use magic::Condition::*;
+    match *cond {
+        Nand(ref v) => 1.0 - v.iter().map(chance).product::<f32>(),
+        And(ref v) => v.iter().map(chance).product(),
+        Or(ref v) => 1.0 - v.iter().map(|c| 1.0 - chance(c)).product::<f32>(),
+        Top => 1.0,
+        Bottom => 0.0,
+        Equals(ref a, ref b) => if number(a) == number(b) { 0.9 } else { 0.1 },
+        LessThan(ref a, ref b) => if number(a) < number(b) { 0.8 } else { 0.2 },
+        MoreThan(ref a, ref b) => if number(a) > number(b) { 0.8 } else { 0.2 },
+        EntitySetCmp(_) => 0.5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spell_text::Text;
+    use super::*;
+
+    fn spell(on_cast: &str, consumes: &str) -> Spell {
+        Spell::from_text(&format!("(Spell (on_cast {}) (requires Top) (on_cooldown) (consumes {})
+            (cast_time (Const 0)) (on_cast_start) (on_interrupt))", on_cast, consumes)).unwrap()
+    }
+
+    const ENEMY: &str = "(FirstOf (EnemiesOf (LoadEntity E_0)))";
+
+    // who each effect lands on, and how often
+    fn landings(on_cast: &str) -> Vec<(Target, f32)> {
+        effects(&spell(on_cast, "")).iter().map(|e| (e.target, e.times)).collect()
+    }
+
+    #[test]
+    fn cost_against_effect() {
+        let p = estimate(&spell(&format!("(AddResource {} (Health (Const -30)))", ENEMY), "(Mana (Const 20))"));
+        assert_eq!((p.cost, p.damage, p.effect()), (10.0, 30.0, 30.0));
+        assert_eq!(p.score(), 20.0);
+        // hurting the caster is a cost, healing it an effect
+        let p = estimate(&spell("(AddResource (LoadEntity E_0) (Health (Const -10)))", ""));
+        assert_eq!((p.cost, p.effect()), (10.0, 0.0));
+        let p = estimate(&spell("(AddResource (LoadEntity E_0) (Health (Const 10)))", "(Health (Const 4))"));
+        assert_eq!((p.cost, p.healing), (4.0, 10.0));
+    }
+
+    #[test]
+    fn effects_land_on_who_they_name() {
+        let hurt = |e: &str| format!("(AddResource {} (Health (Const -1)))", e);
+        assert_eq!(landings(&hurt("(LoadEntity E_0)")), vec![(Target::Caster, 1.0)]);
+        assert_eq!(landings(&hurt(ENEMY)), vec![(Target::Enemy, 1.0)]);
+        assert_eq!(landings(&hurt("(FirstOf Universe)")), vec![(Target::Other, 1.0)]);
+        assert_eq!(landings(&format!("(Define (E_3 {})) {}", ENEMY, hurt("(LoadEntity E_3)"))), vec![(Target::Enemy, 1.0)]);
+        // a projectile that hurts itself when made, and whoever it hits
+        let shot = format!("(SpawnProjectileAt (Projectile (on_create {}) (on_collision {})
+            (collides_with (EnemiesOf (LoadEntity E_0))) (on_destroy) (lifetime (Const 5)))
+            (LoadLocation L_0))", hurt("(LoadEntity E_1)"), hurt("(LoadEntity E_2)"));
+        assert_eq!(landings(&shot), vec![
+            (Target::Projectile, 1.0),
+            (Target::Enemy, HIT_CHANCE * 3.0),
+            (Target::Projectile, 1.0),
+        ]);
+    }
+
+    #[test]
+    fn branches_and_loops_weigh_their_effects() {
+        let hurt = |e: &str| format!("(AddResource {} (Health (Const -1)))", e);
+        let caster = hurt("(LoadEntity E_0)");
+        assert_eq!(landings(&format!("(ITE Top (then {}) (else {}))", caster, hurt(ENEMY))),
+            vec![(Target::Caster, 1.0), (Target::Enemy, 0.0)]);
+        assert_eq!(landings(&format!("(ITE (LessThan (Const 1) (Const 2)) (then {}) (else {}))", caster, caster)),
+            vec![(Target::Caster, 0.8), (Target::Caster, 1.0 - 0.8)]);
+        assert_eq!(landings(&format!("(ForEachAs E_3 (EnemiesOf (LoadEntity E_0)) {})", hurt("(LoadEntity E_3)"))),
+            vec![(Target::Enemy, 3.0)]);
+        let p = estimate(&spell(&format!("(ForEachAs E_3 (EnemiesOf (LoadEntity E_0)) {})", hurt("(LoadEntity E_3)")), ""));
+        assert_eq!((p.damage, p.targets), (3.0, 3.0));
+    }
+
+    #[test]
+    fn strong_spells_outscore_weak_ones() {
+        let hit = |x: i32| spell(&format!("(AddResource {} (Health (Const -{})))", ENEMY, x), "(Mana (Const 10))");
+        assert!(estimate(&hit(50)).score() > estimate(&hit(5)).score());
+        let never = Spell { requires: Box::new(Condition::Bottom), ..hit(50) };
+        assert_eq!(estimate(&never).score(), 0.0);
+    }
+
+    #[test]
+    fn huge_numbers_are_bounded() {
+        let p = estimate(&spell("", "(Mana (Range 2000000000 2100000000))"));
+        assert_eq!(p.cost, MANA_VALUE * MAX_NUMBER);
+        let p = estimate(&spell(&format!("(AddResource {} (Health (Div (Const -5) (Const 0))))", ENEMY), ""));
+        assert_eq!(p.damage, MAX_NUMBER);
+    }
+}
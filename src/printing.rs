@@ -72,11 +72,13 @@ impl fmt::Debug for Discrete {
             &Mult(ref a) => fmt.debug_tuple("Mult") .field(a).finish(),
             &Max(ref a) => fmt.debug_tuple("Max") .field(a).finish(),
             &Min(ref a) => fmt.debug_tuple("Min") .field(a).finish(),
-            &CountStacks(ref a, ref b) => fmt.debug_tuple("CountStacks").field(a).field(b).finish(),
-            &CountDur(ref a, ref b) => fmt.debug_tuple("CountDur").field(a).field(b).finish(),
+            &CountStacks(ref a, ref b, ref c) => fmt.debug_tuple("CountStacks").field(a).field(b).field(c).finish(),
+            &CountDur(ref a, ref b, ref c) => fmt.debug_tuple("CountDur").field(a).field(b).field(c).finish(),
             &Choose(ref a) => fmt.debug_tuple("Choose") .field(a).finish(),
             &Cardinality(ref a) => fmt.debug_tuple("Cardinality") .field(a).finish(),
             &LoadFrom(ref x) => write!(fmt, "LoadFrom({:?})", x),
+            &CooldownLeft(ref a, ref b) => fmt.debug_tuple("CooldownLeft").field(a).field(b).finish(),
+            &Charges(ref a, ref b) => fmt.debug_tuple("Charges").field(a).field(b).finish(),
         }
     }
 }
@@ -0,0 +1,308 @@
+use std::rc::Rc;
+use rand::Rng;
+use buffs::{Buff, MAX_TIER};
+use magic::F32;
+use sexp::{Sexp, ParseError};
+use spell_text::Text;
+
+// What generate.rs makes spells out of: how likely each production is, how
+// deep spells nest and the ranges constants come from. A profile is written
+// like assets/buffs.sexp; assets/profiles/standard.sexp documents every
+// field. Weighted tables list variants by their names in magic.rs, and a
+// variant left out of a table that is given is never picked. Fields left
+// out of a profile keep their standard values, so a profile only says how
+// it differs from the standard one.
+
+// nesting instructions, tried when not yet at the bottom
+pub const INSTRUCTION: &[&str] = &["SpawnProjectileAt", "ITE", "CallWith", "ForEachAs"];
+// instructions that do something
+pub const EFFECT: &[&str] = &["DestroyWithoutEvent", "Destroy", "MoveEntity", "AddResource", "AddVelocity"];
+pub const DEFINITION: &[&str] = &["ESet", "E", "L", "D"];
+pub const CONDITION: &[&str] = &["Nand", "And", "Or", "Equals", "LessThan", "MoreThan", "EntitySetCmp"];
+pub const LEAF_CONDITION: &[&str] = &["Top", "Bottom"];
+pub const DISCRETE: &[&str] = &["Div", "Sum", "Neg", "Mult", "Max", "Min", "CountStacks", "CountDur", "Choose", "Cardinality"];
+pub const LEAF_DISCRETE: &[&str] = &["LoadFrom", "Const", "Range", "WithinPercent"];
+pub const ENTITY: &[&str] = &["FirstOf", "Choose", "ClosestFrom"];
+pub const LOCATION: &[&str] = &["AtEntity", "Midpoint", "Choose"];
+pub const ENTITY_SET: &[&str] = &["None", "And", "Or", "Only", "WithinRangeOf", "HasMinResource", "EnemiesOf", "AllBut"];
+pub const LEAF_ENTITY_SET: &[&str] = &["IsInSlot", "IsHuman", "IsProjectile", "Universe", "Empty"];
+pub const ENTITY_SET_CMP: &[&str] = &["Nand", "And", "Or"];
+pub const LEAF_ENTITY_SET_CMP: &[&str] = &["Contains", "Subset", "Superset", "Equal"];
+pub const DIRECTION: &[&str] = &["Choose", "ChooseWithinRadOf"];
+pub const LEAF_DIRECTION: &[&str] = &["ConstRad", "BetweenRad"];
+pub const RESOURCE: &[&str] = &["Mana", "Health", "BuffStacks"];
+
+const ANY_TIER: u8 = 0;
+const TIERS: &[u8] = &[ANY_TIER, 1, 2, MAX_TIER];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Weights<K> {
+    options: Vec<(K, u32)>,
+}
+
+impl<K: Copy + PartialEq> Weights<K> {
+    // every option at weight 0
+    fn zero(keys: &[K]) -> Weights<K> {
+        Weights { options: keys.iter().map(|&k| (k, 0)).collect() }
+    }
+
+    // one of the options `allowed` lets through, by weight. when those all
+    // weigh nothing, any of them. at least one option must be allowed
+    pub fn pick<R: Rng, F: Fn(K) -> bool>(&self, rng: &mut R, allowed: F) -> K {
+        let options = self.options.iter().filter(|o| allowed(o.0)).collect::<Vec<_>>();
+        let total: u32 = options.iter().map(|o| o.1).sum();
+        if total == 0 {
+            return options[rng.gen_range(0, options.len())].0;
+        }
+        let mut x = rng.gen_range(0, total);
+        for o in options.iter() {
+            if x < o.1 {
+                return o.0;
+            }
+            x -= o.1;
+        }
+        unreachable!()
+    }
+
+    pub fn any<R: Rng>(&self, rng: &mut R) -> K {
+        self.pick(rng, |_| true)
+    }
+
+    // (key weight)*. `fixed` keys are the only ones allowed, and those not
+    // given weigh 0. otherwise the keys given are the options
+    fn read<F>(x: &Sexp, args: &[Sexp], fixed: Option<&[K]>, key: F) -> Result<Weights<K>, ParseError>
+    where F: Fn(&Sexp) -> Result<K, ParseError> {
+        let (name, _) = x.as_call()?;
+        let mut w = Weights::zero(fixed.unwrap_or(&[]));
+        let mut seen = vec![];
+        for arg in args {
+            let pair = arg.as_list()?;
+            if pair.len() != 2 {
+                return Err(ParseError::new(arg.pos(), format!("expected `(option weight)` in `{}`", name)));
+            }
+            let k = key(&pair[0])?;
+            let weight = i32::from_sexp(&pair[1])?;
+            if weight < 0 {
+                return Err(ParseError::new(pair[1].pos(), "weights can't be negative"));
+            }
+            if seen.contains(&k) {
+                return Err(ParseError::new(arg.pos(), format!("{} is weighed twice", pair[0].describe())));
+            }
+            seen.push(k);
+            match w.options.iter().position(|o| o.0 == k) {
+                Some(i) => w.options[i].1 = weight as u32,
+                None => w.options.push((k, weight as u32)),
+            }
+        }
+        if w.options.is_empty() {
+            return Err(ParseError::new(x.pos(), format!("`{}` needs an option", name)));
+        }
+        Ok(w)
+    }
+}
+
+// where an estimate should fall, and where it may now and then
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Aim {
+    pub within: (f32, f32),
+    pub near: (f32, f32),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Profile {
+    pub max_depth: u16,
+    pub constant: (i32, i32), // of Const, Range and WithinPercent. inclusive
+    pub angle: (f32, f32),    // radians, for ConstRad, BetweenRad and ChooseWithinRadOf
+    pub threeish: Aim,        // small numbers, eg. stacks and lifetimes
+    pub twentyish: Aim,       // bigger ones, eg. health, mana and speed
+    pub cast_time: Weights<i32>,
+    pub buff: Weights<Buff>,
+    pub tier: Weights<u8>, // 0 for AnyTier, where it's allowed
+    pub instruction: Weights<&'static str>,
+    pub effect: Weights<&'static str>,
+    pub definition: Weights<&'static str>,
+    pub condition: Weights<&'static str>,
+    pub leaf_condition: Weights<&'static str>,
+    pub discrete: Weights<&'static str>,
+    pub leaf_discrete: Weights<&'static str>,
+    pub entity: Weights<&'static str>,
+    pub location: Weights<&'static str>,
+    pub entity_set: Weights<&'static str>,
+    pub leaf_entity_set: Weights<&'static str>,
+    pub entity_set_cmp: Weights<&'static str>,
+    pub leaf_entity_set_cmp: Weights<&'static str>,
+    pub direction: Weights<&'static str>,
+    pub leaf_direction: Weights<&'static str>,
+    pub resource: Weights<&'static str>,
+}
+
+const STANDARD_PROFILE: &str = include_str!("../assets/profiles/standard.sexp");
+
+thread_local! {
+    static STANDARD: Rc<Profile> = Rc::new({
+        let mut p = Profile::empty();
+        p.read(&Sexp::parse_all(STANDARD_PROFILE).expect("assets/profiles/standard.sexp parses"))
+            .expect("assets/profiles/standard.sexp is valid");
+        p
+    });
+}
+
+impl Profile {
+    // everything equally likely
+    fn empty() -> Profile {
+        let any = Aim { within: (f32::MIN, f32::MAX), near: (0., 0.) };
+        Profile {
+            max_depth: 4,
+            constant: (-49, 49),
+            angle: (-1.5, 1.5),
+            threeish: any,
+            twentyish: any,
+            cast_time: Weights { options: vec![(0, 1)] },
+            buff: Weights::zero(&Buff::ALL),
+            tier: Weights::zero(TIERS),
+            instruction: Weights::zero(INSTRUCTION),
+            effect: Weights::zero(EFFECT),
+            definition: Weights::zero(DEFINITION),
+            condition: Weights::zero(CONDITION),
+            leaf_condition: Weights::zero(LEAF_CONDITION),
+            discrete: Weights::zero(DISCRETE),
+            leaf_discrete: Weights::zero(LEAF_DISCRETE),
+            entity: Weights::zero(ENTITY),
+            location: Weights::zero(LOCATION),
+            entity_set: Weights::zero(ENTITY_SET),
+            leaf_entity_set: Weights::zero(LEAF_ENTITY_SET),
+            entity_set_cmp: Weights::zero(ENTITY_SET_CMP),
+            leaf_entity_set_cmp: Weights::zero(LEAF_ENTITY_SET_CMP),
+            direction: Weights::zero(DIRECTION),
+            leaf_direction: Weights::zero(LEAF_DIRECTION),
+            resource: Weights::zero(RESOURCE),
+        }
+    }
+
+    // assets/profiles/standard.sexp, as it was when compiled
+    pub fn standard() -> Rc<Profile> {
+        STANDARD.with(|p| p.clone())
+    }
+
+    // the standard profile, with the fields given replaced
+    pub fn parse(src: &str) -> Result<Profile, ParseError> {
+        let mut p = (*Profile::standard()).clone();
+        p.read(&Sexp::parse_all(src)?)?;
+        Ok(p)
+    }
+
+    fn read(&mut self, entries: &[Sexp]) -> Result<(), ParseError> {
+        let mut seen = vec![];
+        for x in entries {
+            let (name, args) = x.as_call()?;
+            if seen.contains(&name) {
+                return Err(ParseError::new(x.pos(), format!("`{}` is given twice", name)));
+            }
+            seen.push(name);
+            let names = |keys: &'static [&'static str]| move |k: &Sexp| {
+                let s = k.as_atom()?;
+                keys.iter().find(|&&n| n == s).cloned()
+                    .ok_or_else(|| ParseError::new(k.pos(), format!("`{}` is not an option here", s)))
+            };
+            match name {
+                "max_depth" => {
+                    let d = i32::from_sexp(one(x, args)?)?;
+                    if !(1..=64).contains(&d) {
+                        return Err(ParseError::new(x.pos(), "max_depth must be within 1 to 64"));
+                    }
+                    self.max_depth = d as u16;
+                },
+                "constant" => {
+                    let (lo, hi) = pair(x, args, i32::from_sexp)?;
+                    if lo > hi || lo == i32::MIN || hi == i32::MAX {
+                        return Err(ParseError::new(x.pos(), "expected `(constant low high)` with low <= high"));
+                    }
+                    self.constant = (lo, hi);
+                },
+                "angle" => self.angle = float_range(x, args)?,
+                "threeish" => self.threeish = aim(x, args)?,
+                "twentyish" => self.twentyish = aim(x, args)?,
+                "cast_time" => self.cast_time = Weights::read(x, args, None, i32::from_sexp)?,
+                "buff" => self.buff = Weights::read(x, args, Some(&Buff::ALL[..]), Buff::from_sexp)?,
+                "tier" => self.tier = Weights::read(x, args, Some(TIERS), tier)?,
+                "instruction" => self.instruction = Weights::read(x, args, Some(INSTRUCTION), names(INSTRUCTION))?,
+                "effect" => self.effect = Weights::read(x, args, Some(EFFECT), names(EFFECT))?,
+                "definition" => self.definition = Weights::read(x, args, Some(DEFINITION), names(DEFINITION))?,
+                "condition" => self.condition = Weights::read(x, args, Some(CONDITION), names(CONDITION))?,
+                "leaf_condition" => self.leaf_condition = Weights::read(x, args, Some(LEAF_CONDITION), names(LEAF_CONDITION))?,
+                "discrete" => self.discrete = Weights::read(x, args, Some(DISCRETE), names(DISCRETE))?,
+                "leaf_discrete" => self.leaf_discrete = Weights::read(x, args, Some(LEAF_DISCRETE), names(LEAF_DISCRETE))?,
+                "entity" => self.entity = Weights::read(x, args, Some(ENTITY), names(ENTITY))?,
+                "location" => self.location = Weights::read(x, args, Some(LOCATION), names(LOCATION))?,
+                "entity_set" => self.entity_set = Weights::read(x, args, Some(ENTITY_SET), names(ENTITY_SET))?,
+                "leaf_entity_set" => self.leaf_entity_set = Weights::read(x, args, Some(LEAF_ENTITY_SET), names(LEAF_ENTITY_SET))?,
+                "entity_set_cmp" => self.entity_set_cmp = Weights::read(x, args, Some(ENTITY_SET_CMP), names(ENTITY_SET_CMP))?,
+                "leaf_entity_set_cmp" => self.leaf_entity_set_cmp = Weights::read(x, args, Some(LEAF_ENTITY_SET_CMP), names(LEAF_ENTITY_SET_CMP))?,
+                "direction" => self.direction = Weights::read(x, args, Some(DIRECTION), names(DIRECTION))?,
+                "leaf_direction" => self.leaf_direction = Weights::read(x, args, Some(LEAF_DIRECTION), names(LEAF_DIRECTION))?,
+                "resource" => self.resource = Weights::read(x, args, Some(RESOURCE), names(RESOURCE))?,
+                _ => return Err(ParseError::new(x.pos(), format!("a profile has no field `{}`", name))),
+            }
+        }
+        Ok(())
+    }
+
+    // a constant from the profile's range
+    pub fn constant<R: Rng>(&self, rng: &mut R) -> i32 {
+        rng.gen_range(self.constant.0, self.constant.1 + 1)
+    }
+
+    pub fn angle<R: Rng>(&self, rng: &mut R) -> F32 {
+        F32(self.angle.0 + rng.gen::<f32>() * (self.angle.1 - self.angle.0))
+    }
+
+    // None for AnyTier
+    pub fn tier<R: Rng>(&self, rng: &mut R, any: bool) -> Option<u8> {
+        match self.tier.pick(rng, |t| any || t != ANY_TIER) {
+            ANY_TIER => None,
+            t => Some(t),
+        }
+    }
+}
+
+fn one<'a>(x: &Sexp, args: &'a [Sexp]) -> Result<&'a Sexp, ParseError> {
+    if args.len() == 1 {
+        Ok(&args[0])
+    } else {
+        Err(ParseError::new(x.pos(), "expected one value"))
+    }
+}
+
+fn pair<T, F>(x: &Sexp, args: &[Sexp], f: F) -> Result<(T, T), ParseError>
+where F: Fn(&Sexp) -> Result<T, ParseError> {
+    if args.len() == 2 {
+        Ok((f(&args[0])?, f(&args[1])?))
+    } else {
+        Err(ParseError::new(x.pos(), "expected a low and a high value"))
+    }
+}
+
+fn float_range(x: &Sexp, args: &[Sexp]) -> Result<(f32, f32), ParseError> {
+    let (lo, hi) = pair(x, args, F32::from_sexp)?;
+    let valid = lo.0 <= hi.0; // false for NaN too
+    if !valid {
+        return Err(ParseError::new(x.pos(), "the low value can't be above the high one"));
+    }
+    Ok((lo.0, hi.0))
+}
+
+// (name (low high) (low high))
+fn aim(x: &Sexp, args: &[Sexp]) -> Result<Aim, ParseError> {
+    let (within, near) = pair(x, args, |r| float_range(r, r.as_list()?))?;
+    Ok(Aim { within, near })
+}
+
+fn tier(k: &Sexp) -> Result<u8, ParseError> {
+    if k.as_atom() == Ok("AnyTier") {
+        return Ok(ANY_TIER);
+    }
+    match u8::from_sexp(k) {
+        Ok(t) if (1..=MAX_TIER).contains(&t) => Ok(t),
+        _ => Err(ParseError::new(k.pos(), format!("expected AnyTier or a tier from 1 to {}", MAX_TIER))),
+    }
+}
@@ -0,0 +1,452 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::error::Error;
+use std::path::Path;
+use std::rc::Rc;
+use magic::Spell;
+use space::{Space,Player,Token};
+use buffs::BuffTable;
+use movement_2d::*;
+use wasd_set::WasdDirection;
+use sexp::{Sexp,ParseError};
+use spell_text::Text;
+
+// Recording and replaying games. A replay holds everything that decides how a
+// Space plays out: the seed, the buff table, the players that entered at the
+// start and every input in every tick. After each tick it also holds
+// Space::checksum, so replaying can report the first tick where the
+// simulation no longer agrees.
+//
+// The file is an S-expression (see sexp.rs), with spells in the syntax of
+// spell_text.rs and buffs in that of assets/buffs.sexp:
+//
+//   (Replay
+//     (version 4)
+//     (seed 7)
+//     (buffs (Swarm ...) ...)
+//     (Entrant (at 200.0 100.0) (health_max 100) (mana_max 100)
+//       (spells (1.0 1 (Spell ...)) ...))
+//     (tick 9f3e02a1c4d5b678)
+//     (tick 01f6e5d4c3b2a190 (Walk 0 WA) (Cast 0 3 140.5 62.0))
+//     ...)
+//
+// Each tick lists the inputs given before Space::tick, in order, followed by
+// the checksum after it.
+
+pub const VERSION: u32 = 4;
+pub const WALK_SPEED: f32 = 4.0;
+
+// a player as it enters at the start of the game
+#[derive(Clone, PartialEq, Debug)]
+pub struct Entrant {
+    pub at: Point,
+    pub health_max: u32,
+    pub mana_max: u32,
+    pub spells: Vec<(Spell, f32, u8)>, // spell, cast cooldown, max charges
+}
+
+impl Entrant {
+    pub fn to_player(&self) -> Player {
+        let mut player = Player::new(self.health_max, self.mana_max);
+        for &(ref spell, cast_cooldown, max_charges) in self.spells.iter() {
+            player.add_spell_with_cooldown(spell.clone(), cast_cooldown, max_charges);
+        }
+        player
+    }
+}
+
+// players are referred to by their index among the entrants
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Input {
+    Walk(usize, WasdDirection),
+    Cast(usize, usize, Point), // player, spell index, cursor
+}
+
+impl Input {
+    fn apply(&self, space: &mut Space, tokens: &[Token]) {
+        match *self {
+            Input::Walk(p, dir) => {
+                if let (Some(&tok), Some(angle)) = (tokens.get(p), dir.angle()) {
+                    space.walk(tok, angle, WALK_SPEED);
+                }
+            },
+            Input::Cast(p, spell_index, cursor) => {
+                if let Some(&tok) = tokens.get(p) {
+                    space.player_cast(tok, spell_index, cursor);
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Replay {
+    pub seed: u64,
+    pub buffs: Rc<BuffTable>,
+    pub entrants: Vec<Entrant>,
+    pub ticks: Vec<(Vec<Input>, u64)>, // inputs, checksum after the tick
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Divergence {
+    pub tick: usize, // 0-based
+    pub expected: u64,
+    pub found: u64,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "replay diverges at tick {}: checksum {:016x}, expected {:016x}",
+            self.tick, self.found, self.expected)
+    }
+}
+
+impl Error for Divergence {}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Io(ref e) => write!(f, "{}", e),
+            ReplayError::Parse(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self { ReplayError::Io(e) }
+}
+
+impl From<ParseError> for ReplayError {
+    fn from(e: ParseError) -> Self { ReplayError::Parse(e) }
+}
+
+// the Space a replay starts from, and the tokens of its entrants
+fn start(seed: u64, buffs: &Rc<BuffTable>, entrants: &[Entrant]) -> (Space, Vec<Token>) {
+    let mut space = Space::with_seed(seed);
+    space.set_buff_table(buffs.clone());
+    let tokens = entrants.iter()
+        .map(|e| space.player_enter(e.at, e.to_player()))
+        .collect();
+    (space, tokens)
+}
+
+pub struct Recorder {
+    space: Space,
+    tokens: Vec<Token>,
+    inputs: Vec<Input>,
+    replay: Replay,
+}
+
+impl Recorder {
+    pub fn new(seed: u64, entrants: Vec<Entrant>) -> Recorder {
+        Recorder::with_buffs(seed, BuffTable::standard(), entrants)
+    }
+
+    pub fn with_buffs(seed: u64, buffs: Rc<BuffTable>, entrants: Vec<Entrant>) -> Recorder {
+        let (space, tokens) = start(seed, &buffs, &entrants);
+        Recorder {
+            space,
+            tokens,
+            inputs: vec![],
+            replay: Replay { seed, buffs, entrants, ticks: vec![] },
+        }
+    }
+
+    pub fn space(&self) -> &Space {
+        &self.space
+    }
+
+    pub fn set_log(&mut self, log: Option<fn(&str)>) {
+        self.space.set_log(log);
+    }
+
+    pub fn token_of(&self, player: usize) -> Option<Token> {
+        self.tokens.get(player).cloned()
+    }
+
+    pub fn input(&mut self, input: Input) {
+        input.apply(&mut self.space, &self.tokens);
+        self.inputs.push(input);
+    }
+
+    pub fn tick(&mut self) {
+        self.space.tick();
+        let inputs = ::std::mem::take(&mut self.inputs);
+        self.replay.ticks.push((inputs, self.space.checksum()));
+    }
+
+    // inputs given since the last tick are not part of the replay
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+}
+
+impl Replay {
+    // plays the replay headless and checks it against the recorded checksums
+    pub fn verify(&self) -> Result<Space, Divergence> {
+        self.play(|_, _| ())
+    }
+
+    // calls `on_tick` with the index and state after every tick
+    pub fn play<F: FnMut(usize, &Space)>(&self, mut on_tick: F) -> Result<Space, Divergence> {
+        let (mut space, tokens) = start(self.seed, &self.buffs, &self.entrants);
+        for (i, &(ref inputs, expected)) in self.ticks.iter().enumerate() {
+            for input in inputs {
+                input.apply(&mut space, &tokens);
+            }
+            space.tick();
+            on_tick(i, &space);
+            let found = space.checksum();
+            if found != expected {
+                return Err(Divergence { tick: i, expected, found });
+            }
+        }
+        Ok(space)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        File::create(path)?.write_all(self.to_string().as_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay, ReplayError> {
+        let mut src = String::new();
+        File::open(path)?.read_to_string(&mut src)?;
+        Ok(Replay::parse(&src)?)
+    }
+
+    pub fn parse(src: &str) -> Result<Replay, ParseError> {
+        let x = Sexp::parse(src)?;
+        let (head, args) = x.as_call()?;
+        if head != "Replay" {
+            return Err(ParseError::new(x.pos(), format!("expected `(Replay ...)`, found {}", x.describe())));
+        }
+        let mut replay = Replay { seed: 0, buffs: BuffTable::standard(), entrants: vec![], ticks: vec![] };
+        let mut version = None;
+        for arg in args {
+            let (name, a) = arg.as_call()?;
+            match name {
+                "version" => {
+                    let v: u32 = number(one(arg, a)?)?;
+                    if v != VERSION {
+                        return Err(ParseError::new(arg.pos(), format!("unsupported replay version {}", v)));
+                    }
+                    version = Some(v);
+                },
+                "seed" => replay.seed = number(one(arg, a)?)?,
+                "buffs" => replay.buffs = Rc::new(BuffTable::from_sexp(a)?),
+                "Entrant" => replay.entrants.push(parse_entrant(arg)?),
+                "tick" => {
+                    if a.is_empty() {
+                        return Err(ParseError::new(arg.pos(), "`tick` needs a checksum"));
+                    }
+                    let checksum = u64::from_str_radix(a[0].as_atom()?, 16)
+                        .map_err(|_| ParseError::new(a[0].pos(), "expected a checksum in hex"))?;
+                    let inputs = a[1..].iter().map(parse_input).collect::<Result<_, _>>()?;
+                    replay.ticks.push((inputs, checksum));
+                },
+                _ => return Err(ParseError::new(arg.pos(), format!("`Replay` has no field `{}`", name))),
+            }
+        }
+        if version.is_none() {
+            return Err(ParseError::new(x.pos(), "`Replay` is missing field `version`"));
+        }
+        Ok(replay)
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "(Replay")?;
+        writeln!(f, "  (version {})", VERSION)?;
+        writeln!(f, "  (seed {})", self.seed)?;
+        let buffs = Sexp::list(Some(Sexp::atom("buffs")).into_iter().chain(self.buffs.to_sexp()).collect());
+        writeln!(f, "  {}", buffs.pretty(78).replace('\n', "\n  "))?;
+        for e in self.entrants.iter() {
+            let spells = e.spells.iter()
+                .map(|&(ref spell, cooldown, charges)| Sexp::list(vec![
+                    Sexp::atom(format!("{:?}", cooldown)),
+                    Sexp::atom(charges.to_string()),
+                    spell.to_sexp(),
+                ]));
+            let entrant = Sexp::list(vec![
+                Sexp::atom("Entrant"),
+                Sexp::list(vec![Sexp::atom("at"), Sexp::atom(format!("{:?}", e.at.0)), Sexp::atom(format!("{:?}", e.at.1))]),
+                Sexp::list(vec![Sexp::atom("health_max"), Sexp::atom(e.health_max.to_string())]),
+                Sexp::list(vec![Sexp::atom("mana_max"), Sexp::atom(e.mana_max.to_string())]),
+                Sexp::list(Some(Sexp::atom("spells")).into_iter().chain(spells).collect()),
+            ]);
+            writeln!(f, "  {}", entrant.pretty(78).replace('\n', "\n  "))?;
+        }
+        for &(ref inputs, checksum) in self.ticks.iter() {
+            write!(f, "  (tick {:016x}", checksum)?;
+            for input in inputs {
+                match *input {
+                    Input::Walk(p, dir) => write!(f, " (Walk {} {:?})", p, dir)?,
+                    Input::Cast(p, i, pt) => write!(f, " (Cast {} {} {:?} {:?})", p, i, pt.0, pt.1)?,
+                }
+            }
+            writeln!(f, ")")?;
+        }
+        write!(f, ")")
+    }
+}
+
+fn number<T: ::std::str::FromStr>(x: &Sexp) -> Result<T, ParseError> {
+    let s = x.as_atom()?;
+    s.parse().map_err(|_| ParseError::new(x.pos(), format!("`{}` is not a valid number here", s)))
+}
+
+fn one<'a>(x: &Sexp, args: &'a [Sexp]) -> Result<&'a Sexp, ParseError> {
+    if args.len() == 1 {
+        Ok(&args[0])
+    } else {
+        Err(ParseError::new(x.pos(), format!("expected one value in {}", x.describe())))
+    }
+}
+
+fn parse_entrant(x: &Sexp) -> Result<Entrant, ParseError> {
+    let (_, args) = x.as_call()?;
+    let mut at = None;
+    let mut health_max = None;
+    let mut mana_max = None;
+    let mut spells = None;
+    for arg in args {
+        let (name, a) = arg.as_call()?;
+        match name {
+            "at" if a.len() == 2 => at = Some(Point(number(&a[0])?, number(&a[1])?)),
+            "health_max" => health_max = Some(number(one(arg, a)?)?),
+            "mana_max" => mana_max = Some(number(one(arg, a)?)?),
+            "spells" => spells = Some(a.iter().map(|s| {
+                match s.as_list()? {
+                    [cooldown, charges, spell] => {
+                        let max_charges: u8 = number(charges)?;
+                        if max_charges == 0 {
+                            return Err(ParseError::new(charges.pos(), "a spell needs at least 1 charge"));
+                        }
+                        Ok((Spell::from_sexp(spell)?, number(cooldown)?, max_charges))
+                    },
+                    _ => Err(ParseError::new(s.pos(), "expected `(cooldown charges (Spell ...))`")),
+                }
+            }).collect::<Result<_, _>>()?),
+            _ => return Err(ParseError::new(arg.pos(), format!("unexpected {} in `Entrant`", arg.describe()))),
+        }
+    }
+    match (at, health_max, mana_max, spells) {
+        (Some(at), Some(health_max), Some(mana_max), Some(spells)) => Ok(Entrant {
+            at,
+            health_max,
+            mana_max,
+            spells,
+        }),
+        _ => Err(ParseError::new(x.pos(), "`Entrant` needs `at`, `health_max`, `mana_max` and `spells`")),
+    }
+}
+
+fn parse_input(x: &Sexp) -> Result<Input, ParseError> {
+    let (head, a) = x.as_call()?;
+    match (head, a.len()) {
+        ("Walk", 2) => {
+            let s = a[1].as_atom()?;
+            match WasdDirection::ALL.iter().find(|d| format!("{:?}", d) == s) {
+                Some(&dir) => Ok(Input::Walk(number(&a[0])?, dir)),
+                None => Err(ParseError::new(a[1].pos(), format!("`{}` is not a direction", s))),
+            }
+        },
+        ("Cast", 4) => Ok(Input::Cast(number(&a[0])?, number(&a[1])?, Point(number(&a[2])?, number(&a[3])?))),
+        _ => Err(ParseError::new(x.pos(), format!("expected `(Walk player dir)` or `(Cast player spell x y)`, found {}", x.describe()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, Isaac64Rng, SeedableRng};
+    use generate;
+    use profile::Profile;
+    use super::*;
+
+    fn entrant(at: Point, spells: Vec<Spell>) -> Entrant {
+        Entrant {
+            at,
+            health_max: 100,
+            mana_max: 100,
+            spells: spells.into_iter().map(|s| (s, 1.0, 2)).collect(),
+        }
+    }
+
+    fn spells(seed: u64, n: usize) -> Vec<Spell> {
+        let mut rng = Isaac64Rng::from_seed(&[seed]);
+        (0..n).map(|_| generate::spell(&Profile::standard(), &mut rng).0).collect()
+    }
+
+    // two players walking about and casting at each other at random
+    fn game(seed: u64, ticks: usize) -> Recorder {
+        let mut r = Recorder::new(seed, vec![
+            entrant(Point(100., 100.), spells(seed, 4)),
+            entrant(Point(300., 100.), spells(seed + 1, 4)),
+        ]);
+        let mut rng = Isaac64Rng::from_seed(&[seed]);
+        for _ in 0..ticks {
+            for p in 0..2 {
+                if rng.gen_weighted_bool(3) {
+                    r.input(Input::Walk(p, *rng.choose(&WasdDirection::ALL).unwrap()));
+                }
+                if rng.gen_weighted_bool(10) {
+                    r.input(Input::Cast(p, rng.gen_range(0, 4), Point(rng.gen_range(0., 400.), rng.gen_range(0., 200.))));
+                }
+            }
+            r.tick();
+        }
+        r
+    }
+
+    #[test]
+    fn replays_reproduce_every_checksum() {
+        for seed in 0..5 {
+            let r = game(seed, 150);
+            let mut found = vec![];
+            let end = r.replay().play(|_, space| found.push(space.checksum())).unwrap();
+            let expected: Vec<u64> = r.replay().ticks.iter().map(|t| t.1).collect();
+            assert_eq!(found, expected);
+            assert_eq!(end.checksum(), r.space().checksum());
+        }
+    }
+
+    #[test]
+    fn replays_survive_text() {
+        let r = game(11, 150);
+        let text = r.replay().to_string();
+        let back = Replay::parse(&text).unwrap();
+        assert_eq!(&back, r.replay());
+        assert_eq!(back.to_string(), text);
+        assert!(back.verify().is_ok());
+    }
+
+    #[test]
+    fn divergence_is_found() {
+        let mut replay = game(12, 50).replay().clone();
+        replay.ticks[30].1 ^= 1;
+        assert_eq!(replay.verify().map(|_| ()).unwrap_err().tick, 30);
+    }
+
+    #[test]
+    fn zero_charges_dont_parse() {
+        let mut r = Recorder::new(3, vec![entrant(Point(0., 0.), spells(3, 1))]);
+        r.tick();
+        let mut replay = r.replay().clone();
+        assert!(Replay::parse(&replay.to_string()).is_ok());
+        replay.entrants[0].spells[0].2 = 0;
+        assert!(Replay::parse(&replay.to_string()).is_err());
+        // made by hand, it still plays
+        let player = replay.entrants[0].to_player();
+        assert_eq!(player.cooldown(0).map(|c| c.max_charges), Some(1));
+    }
+}
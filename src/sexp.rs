@@ -0,0 +1,281 @@
+use std::fmt;
+use std::error::Error;
+
+// A minimal S-expression reader and pretty-printer. This is the shared
+// surface syntax of everything designers edit by hand: spells
+// (see spell_text.rs) and the data files built on top of them.
+//
+//   expr    := atom | '(' expr* ')'
+//   atom    := any run of characters other than whitespace, '(', ')' and ';'
+//   comment := ';' up to the end of the line
+//
+// Every expression remembers where it started, so that errors found while
+// interpreting it can still point at a line and column.
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Pos {
+    pub line: usize, // 1-based
+    pub col: usize,  // 1-based, in chars
+}
+
+#[derive(Clone, Debug)]
+pub enum Sexp {
+    Atom(String, Pos),
+    List(Vec<Sexp>, Pos),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseError {
+    pub pos: Pos,
+    pub msg: String,
+}
+
+impl ParseError {
+    pub fn new<S: Into<String>>(pos: Pos, msg: S) -> ParseError {
+        ParseError { pos, msg: msg.into() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.col, self.msg)
+    }
+}
+
+impl Error for ParseError {}
+
+impl Sexp {
+    pub fn atom<S: Into<String>>(s: S) -> Sexp {
+        Sexp::Atom(s.into(), Pos { line: 0, col: 0 })
+    }
+
+    pub fn list(v: Vec<Sexp>) -> Sexp {
+        Sexp::List(v, Pos { line: 0, col: 0 })
+    }
+
+    pub fn pos(&self) -> Pos {
+        match self {
+            &Sexp::Atom(_, pos) | &Sexp::List(_, pos) => pos,
+        }
+    }
+
+    // a short rendering for error messages
+    pub fn describe(&self) -> String {
+        match *self {
+            Sexp::Atom(ref s, _) => format!("`{}`", s),
+            Sexp::List(ref v, _) => match v.first() {
+                Some(Sexp::Atom(head, _)) => format!("`({} ...)`", head),
+                Some(_) => "a list".to_owned(),
+                None => "`()`".to_owned(),
+            },
+        }
+    }
+
+    // the head atom and the remaining elements of a list like `(Head a b)`.
+    // a bare atom is treated as a list with no arguments.
+    pub fn as_call(&self) -> Result<(&str, &[Sexp]), ParseError> {
+        match *self {
+            Sexp::Atom(ref s, _) => Ok((s, &[])),
+            Sexp::List(ref v, pos) => match v.first() {
+                Some(Sexp::Atom(head, _)) => Ok((head, &v[1..])),
+                _ => Err(ParseError::new(pos, "expected a list starting with a name")),
+            },
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&[Sexp], ParseError> {
+        match *self {
+            Sexp::List(ref v, _) => Ok(v),
+            Sexp::Atom(_, pos) => Err(ParseError::new(pos, format!("expected a list, found {}", self.describe()))),
+        }
+    }
+
+    pub fn as_atom(&self) -> Result<&str, ParseError> {
+        match *self {
+            Sexp::Atom(ref s, _) => Ok(s),
+            Sexp::List(_, pos) => Err(ParseError::new(pos, format!("expected a name or number, found {}", self.describe()))),
+        }
+    }
+
+    pub fn parse_all(src: &str) -> Result<Vec<Sexp>, ParseError> {
+        let mut reader = Reader::new(src);
+        let mut exprs = vec![];
+        while let Some(x) = reader.next_expr()? {
+            exprs.push(x);
+        }
+        Ok(exprs)
+    }
+
+    // exactly one expression
+    pub fn parse(src: &str) -> Result<Sexp, ParseError> {
+        let mut reader = Reader::new(src);
+        let x = match reader.next_expr()? {
+            Some(x) => x,
+            None => return Err(ParseError::new(reader.pos(), "expected an expression, found end of input")),
+        };
+        reader.skip_blank();
+        if reader.peek().is_some() {
+            return Err(ParseError::new(reader.pos(), "expected end of input"));
+        }
+        Ok(x)
+    }
+
+    // flat when it fits in `width` columns, otherwise one argument per line
+    pub fn pretty(&self, width: usize) -> String {
+        let mut s = String::new();
+        self.write_pretty(&mut s, 0, width);
+        s
+    }
+
+    fn flat_len(&self) -> usize {
+        match *self {
+            Sexp::Atom(ref s, _) => s.chars().count(),
+            Sexp::List(ref v, _) => 2 + v.iter().map(|x| x.flat_len() + 1).sum::<usize>() - if !v.is_empty() {1} else {0},
+        }
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize, width: usize) {
+        match *self {
+            Sexp::Atom(ref s, _) => out.push_str(s),
+            Sexp::List(ref v, _) => {
+                if indent + self.flat_len() <= width || v.len() <= 1 {
+                    out.push_str(&self.to_string());
+                    return;
+                }
+                out.push('(');
+                v[0].write_pretty(out, indent + 1, width);
+                for x in v[1..].iter() {
+                    out.push('\n');
+                    for _ in 0..(indent + 2) {
+                        out.push(' ');
+                    }
+                    x.write_pretty(out, indent + 2, width);
+                }
+                out.push(')');
+            },
+        }
+    }
+}
+
+impl fmt::Display for Sexp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Sexp::Atom(ref s, _) => write!(f, "{}", s),
+            Sexp::List(ref v, _) => {
+                write!(f, "(")?;
+                for (i, x) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", x)?;
+                }
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+// structural equality. positions are ignored
+impl PartialEq for Sexp {
+    fn eq(&self, other: &Sexp) -> bool {
+        match (self, other) {
+            (Sexp::Atom(a, _), Sexp::Atom(b, _)) => a == b,
+            (Sexp::List(a, _), Sexp::List(b, _)) => a == b,
+            _ => false,
+        }
+    }
+}
+impl Eq for Sexp {}
+
+struct Reader<'a> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>,
+    line: usize,
+    col: usize,
+}
+
+impl<'a> Reader<'a> {
+    const MAX_DEPTH: usize = 256;
+
+    fn new(src: &'a str) -> Reader<'a> {
+        Reader { chars: src.chars().peekable(), line: 1, col: 1 }
+    }
+
+    fn pos(&self) -> Pos {
+        Pos { line: self.line, col: self.col }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().cloned()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+            self.col = 1;
+        } else if c.is_some() {
+            self.col += 1;
+        }
+        c
+    }
+
+    fn skip_blank(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ';' {
+                while self.peek().map(|c| c != '\n').unwrap_or(false) {
+                    self.bump();
+                }
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn next_expr(&mut self) -> Result<Option<Sexp>, ParseError> {
+        self.skip_blank();
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+        self.expr(0).map(Some)
+    }
+
+    fn expr(&mut self, depth: usize) -> Result<Sexp, ParseError> {
+        self.skip_blank();
+        let start = self.pos();
+        match self.peek() {
+            None => Err(ParseError::new(start, "expected an expression, found end of input")),
+            Some(')') => Err(ParseError::new(start, "unexpected `)`")),
+            Some('(') => {
+                if depth >= Self::MAX_DEPTH {
+                    return Err(ParseError::new(start, "expressions are nested too deeply"));
+                }
+                self.bump();
+                let mut v = vec![];
+                loop {
+                    self.skip_blank();
+                    match self.peek() {
+                        None => return Err(ParseError::new(start, "this `(` is never closed")),
+                        Some(')') => {
+                            self.bump();
+                            return Ok(Sexp::List(v, start));
+                        },
+                        Some(_) => v.push(self.expr(depth + 1)?),
+                    }
+                }
+            },
+            Some(_) => {
+                let mut s = String::new();
+                while let Some(c) = self.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == ';' {
+                        break;
+                    }
+                    s.push(c);
+                    self.bump();
+                }
+                Ok(Sexp::Atom(s, start))
+            },
+        }
+    }
+}
@@ -0,0 +1,672 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::mem;
+use magic::*;
+
+// Rewrites a spell into a smaller one that does exactly what it did: the
+// same effects, the same errors and the same draws from the rng in the same
+// order. The rules below run over the whole spell until none applies:
+//
+//   - arithmetic on constants is done ahead of time, the way the engine
+//     does it: saturating, Max and Min of nothing being 1
+//   - an ITE on Top or Bottom becomes the instructions it would run
+//   - And and Or in And and Or, and Sum, Mult, Max and Min in the same, are
+//     flattened where that evaluates alike
+//   - Nothing, Defines that nothing loads from and CallWiths of nothing
+//     are removed
+//   - Universe and Empty are dropped from entity set Ands and Ors, or
+//     swallow them
+//
+// A part is only ever dropped when it's pure (see below), ie. can't draw
+// from the rng or fail. Spells are taken to have no unbound loads, so loads
+// are pure.
+
+pub fn spell(s: &mut Spell) {
+    loop {
+        let before = s.clone();
+        simplify_spell(s);
+        if *s == before {
+            return;
+        }
+    }
+}
+
+fn simplify_spell(s: &mut Spell) {
+    block(&mut s.on_cast, &HashSet::new());
+    condition(&mut s.requires);
+    block(&mut s.on_cooldown, &HashSet::new());
+    for r in s.consumes.iter_mut() {
+        resource(r);
+    }
+    discrete(&mut s.cast_time);
+    // when the cast is instant, consumes and on_cast run on in the context
+    // on_cast_start leaves, so its Defines are left be
+    let leaks = defined_in(&s.on_cast_start);
+    block(&mut s.on_cast_start, &leaks);
+    block(&mut s.on_interrupt, &HashSet::new());
+}
+
+// every slot a Define in the instructions binds, however nested
+fn defined_in(block: &[Instruction]) -> HashSet<Slot> {
+    use magic::Instruction::*;
+    let mut slots = HashSet::new();
+    for ins in block {
+        match *ins {
+            Define(ref def) => {
+                slots.insert(defined(def));
+            },
+            ITE(_, ref then, ref els) => {
+                slots.extend(defined_in(then));
+                slots.extend(defined_in(els));
+            },
+            CallWith(_, ref body) | ForEachAs(_, _, ref body) => slots.extend(defined_in(body)),
+            _ => (),
+        }
+    }
+    slots
+}
+
+fn blueprint(bp: &mut ProjectileBlueprint) {
+    // each event starts from a fresh context
+    block(&mut bp.on_create, &HashSet::new());
+    block(&mut bp.on_collision, &HashSet::new());
+    entity_set(&mut bp.collides_with);
+    block(&mut bp.on_destroy, &HashSet::new());
+    discrete(&mut bp.lifetime);
+}
+
+// `live`: slots loaded after the block is done, in the same context. at run
+// time Defines in ITE and ForEachAs bodies stay defined after them
+fn block(v: &mut Vec<Instruction>, live: &HashSet<Slot>) {
+    use magic::Instruction::*;
+    let mut out = Vec::with_capacity(v.len());
+    for mut ins in v.drain(..) {
+        instruction(&mut ins);
+        match ins {
+            Nothing => (),
+            ITE(Condition::Top, then, _) => out.extend(then),
+            ITE(Condition::Bottom, _, els) => out.extend(els),
+            ITE(ref cond, ref then, ref els) if then.is_empty() && els.is_empty() && pure_condition(cond) => (),
+            CallWith(ref def, ref body) if body.is_empty() && pure_definition(def) => (),
+            _ => out.push(ins),
+        }
+    }
+    // back to front, so what's loaded later is known
+    let mut live = live.clone();
+    let mut kept = Vec::with_capacity(out.len());
+    for mut ins in out.into_iter().rev() {
+        match ins {
+            Define(ref def) if !live.contains(&defined(def)) && pure_definition(def) => continue,
+            ITE(_, ref mut then, ref mut els) => {
+                block(then, &live);
+                block(els, &live);
+            },
+            // bindings made in the body are undone after it
+            CallWith(_, ref mut body) => block(body, &HashSet::new()),
+            // and the body runs again after itself
+            ForEachAs(_, _, ref mut body) => {
+                let mut again = live.clone();
+                again.extend(loads_in(body));
+                block(body, &again);
+            },
+            _ => (),
+        }
+        live.extend(loads_in(::std::slice::from_ref(&ins)));
+        kept.push(ins);
+    }
+    kept.reverse();
+    *v = kept;
+}
+
+fn defined(def: &Definition) -> Slot {
+    match *def {
+        Definition::ESet(s, _) => Slot::ESet(s),
+        Definition::E(s, _) => Slot::E(s),
+        Definition::D(s, _) => Slot::D(s),
+        Definition::L(s, _) => Slot::L(s),
+    }
+}
+
+fn instruction(ins: &mut Instruction) {
+    use magic::Instruction::*;
+    match ins {
+        &mut Define(ref mut def) => definition(def),
+        // the blocks are left to `block`, which knows what's loaded after them
+        &mut ITE(ref mut cond, _, _) => condition(cond),
+        &mut CallWith(ref mut def, _) => definition(def),
+        &mut ForEachAs(_, ref mut set, _) => entity_set(set),
+        &mut DestroyWithoutEvent(ref mut ent) |
+        &mut Destroy(ref mut ent) => entity(ent),
+        &mut MoveEntity(ref mut ent, ref mut loc) => {
+            entity(ent);
+            location(loc);
+        },
+        &mut AddResource(ref mut ent, ref mut res) => {
+            entity(ent);
+            resource(res);
+        },
+        &mut AddVelocity(ref mut ent, ref mut dir, ref mut disc) => {
+            entity(ent);
+            direction(dir);
+            discrete(disc);
+        },
+        &mut SpawnProjectileAt(ref mut bp, ref mut loc) => {
+            location(loc);
+            blueprint(Rc::make_mut(bp));
+        },
+        &mut Nothing => (),
+    }
+}
+
+fn definition(def: &mut Definition) {
+    use magic::Definition::*;
+    match *def {
+        ESet(_, ref mut x) => entity_set(x),
+        E(_, ref mut x) => entity(x),
+        D(_, ref mut x) => discrete(x),
+        L(_, ref mut x) => location(x),
+    }
+}
+
+fn condition(cond: &mut Condition) {
+    use magic::Condition::*;
+    match cond {
+        &mut Nand(ref mut v) | &mut And(ref mut v) | &mut Or(ref mut v) => {
+            for c in v.iter_mut() {
+                condition(c);
+            }
+        },
+        &mut Equals(ref mut a, ref mut b) |
+        &mut LessThan(ref mut a, ref mut b) |
+        &mut MoreThan(ref mut a, ref mut b) => {
+            discrete(a);
+            discrete(b);
+        },
+        &mut EntitySetCmp(ref mut cmp) => entity_set_cmp(cmp),
+        &mut Top | &mut Bottom => (),
+    }
+    let simpler = match *cond {
+        // every operand is evaluated, so their order is all that matters
+        And(ref mut v) => {
+            flatten(v, |c| match c { &mut And(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            v.retain(|c| *c != Top);
+            if v.contains(&Bottom) && v.iter().all(pure_condition) {
+                Some(Bottom)
+            } else {
+                single(v, Top)
+            }
+        },
+        Or(ref mut v) => {
+            flatten(v, |c| match c { &mut Or(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            v.retain(|c| *c != Bottom);
+            if v.contains(&Top) && v.iter().all(pure_condition) {
+                Some(Top)
+            } else {
+                single(v, Bottom)
+            }
+        },
+        Nand(ref mut v) => {
+            flatten(v, |c| match c { &mut And(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            v.retain(|c| *c != Top);
+            if v.contains(&Bottom) && v.iter().all(pure_condition) {
+                Some(Top)
+            } else if v.is_empty() {
+                Some(Bottom)
+            } else { None }
+        },
+        Equals(Discrete::Const(a), Discrete::Const(b)) => Some(truth(a == b)),
+        LessThan(Discrete::Const(a), Discrete::Const(b)) => Some(truth(a < b)),
+        MoreThan(Discrete::Const(a), Discrete::Const(b)) => Some(truth(a > b)),
+        EntitySetCmp(::magic::EntitySetCmp::And(ref v)) if v.is_empty() => Some(Top),
+        EntitySetCmp(::magic::EntitySetCmp::Or(ref v)) if v.is_empty() => Some(Bottom),
+        EntitySetCmp(::magic::EntitySetCmp::Nand(ref v)) if v.is_empty() => Some(Bottom),
+        _ => None,
+    };
+    if let Some(x) = simpler {
+        *cond = x;
+    }
+}
+
+fn truth(b: bool) -> Condition {
+    if b { Condition::Top } else { Condition::Bottom }
+}
+
+fn entity_set_cmp(cmp: &mut EntitySetCmp) {
+    use magic::EntitySetCmp::*;
+    match cmp {
+        &mut Nand(ref mut v) | &mut And(ref mut v) | &mut Or(ref mut v) => {
+            for c in v.iter_mut() {
+                entity_set_cmp(c);
+            }
+        },
+        &mut Subset(ref mut a, ref mut b) |
+        &mut Superset(ref mut a, ref mut b) |
+        &mut Equal(ref mut a, ref mut b) => {
+            entity_set(a);
+            entity_set(b);
+        },
+        &mut Contains(ref mut set, ref mut ent) => {
+            entity_set(set);
+            entity(ent);
+        },
+    }
+    let simpler = match *cmp {
+        And(ref mut v) => {
+            flatten(v, |c| match c { &mut And(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            if v.len() == 1 { v.pop() } else { None }
+        },
+        Or(ref mut v) => {
+            flatten(v, |c| match c { &mut Or(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            if v.len() == 1 { v.pop() } else { None }
+        },
+        Nand(ref mut v) => {
+            flatten(v, |c| match c { &mut And(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            None
+        },
+        _ => None,
+    };
+    if let Some(x) = simpler {
+        *cmp = x;
+    }
+}
+
+fn entity_set(set: &mut EntitySet) {
+    use magic::EntitySet::*;
+    match set {
+        &mut None(ref mut v) | &mut And(ref mut v) | &mut Or(ref mut v) => {
+            for s in v.iter_mut() {
+                entity_set(s);
+            }
+        },
+        &mut Only(ref mut ent) |
+        &mut EnemiesOf(ref mut ent) |
+        &mut AllBut(ref mut ent) => entity(ent),
+        &mut WithinRangeOf(ref mut ent, ref mut disc) => {
+            entity(ent);
+            discrete(disc);
+        },
+        &mut HasMinResource(ref mut res) => resource(res),
+        &mut IsInSlot(_) | &mut IsHuman | &mut IsProjectile | &mut Empty | &mut Universe => (),
+    }
+    let simpler = match *set {
+        // And keeps only what's in the universe, which a lone member may not be
+        And(ref mut v) => {
+            flatten(v, |s| match s { &mut And(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            v.retain(|s| *s != Universe);
+            if v.contains(&Empty) && v.iter().all(pure_entity_set) {
+                Some(Empty)
+            } else if v.len() == 1 && in_universe(&v[0]) {
+                v.pop()
+            } else if v.is_empty() {
+                Some(Universe)
+            } else { Option::None }
+        },
+        Or(ref mut v) => {
+            flatten(v, |s| match s { &mut Or(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            v.retain(|s| *s != Empty);
+            if v.contains(&Universe) && v.iter().all(|s| pure_entity_set(s) && in_universe(s)) {
+                Some(Universe)
+            } else {
+                single(v, Empty)
+            }
+        },
+        None(ref mut v) => {
+            flatten(v, |s| match s { &mut Or(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            v.retain(|s| *s != Empty);
+            if v.contains(&Universe) && v.iter().all(pure_entity_set) {
+                Some(Empty)
+            } else if v.is_empty() {
+                Some(Universe)
+            } else { Option::None }
+        },
+        _ => Option::None,
+    };
+    if let Some(x) = simpler {
+        *set = x;
+    }
+}
+
+// the set can only hold entities that exist. Only and IsInSlot may hold
+// NULL, or something since destroyed
+fn in_universe(set: &EntitySet) -> bool {
+    use magic::EntitySet::*;
+    match *set {
+        Only(_) | IsInSlot(_) => false,
+        Or(ref v) => v.iter().all(in_universe),
+        _ => true,
+    }
+}
+
+fn entity(ent: &mut Entity) {
+    use magic::Entity::*;
+    match ent {
+        &mut LoadEntity(_) => (),
+        &mut FirstOf(ref mut set) | &mut Choose(ref mut set) => entity_set(set),
+        &mut ClosestFrom(ref mut set, ref mut loc) => {
+            entity_set(set);
+            location(loc);
+        },
+    }
+}
+
+fn location(loc: &mut Location) {
+    use magic::Location::*;
+    match loc {
+        &mut AtEntity(ref mut ent) => entity(ent),
+        &mut Midpoint(ref mut v) | &mut Choose(ref mut v) => {
+            for l in v.iter_mut() {
+                location(l);
+            }
+        },
+        &mut LoadLocation(_) => (),
+    }
+}
+
+fn direction(dir: &mut Direction) {
+    use magic::Direction::*;
+    match dir {
+        &mut TowardLocation(ref mut a, ref mut b) => {
+            location(a);
+            location(b);
+        },
+        &mut Choose(ref mut v) => {
+            for d in v.iter_mut() {
+                direction(d);
+            }
+        },
+        &mut ChooseWithinRadOf(ref mut d, _) => direction(d),
+        &mut ConstRad(_) | &mut BetweenRad(_, _) => (),
+    }
+}
+
+fn resource(res: &mut Resource) {
+    use magic::Resource::*;
+    match res {
+        &mut Mana(ref mut d) | &mut Health(ref mut d) => discrete(d),
+        &mut BuffStacks(_, ref mut tier, ref mut d) => {
+            buff_tier(tier);
+            discrete(d);
+        },
+    }
+}
+
+fn buff_tier(tier: &mut BuffTier) {
+    if let &mut BuffTier::Tier(ref mut d) = tier {
+        discrete(d);
+    }
+}
+
+fn discrete(d: &mut Discrete) {
+    use magic::Discrete::*;
+    match d {
+        &mut Div(ref mut a, ref mut b) => {
+            discrete(a);
+            discrete(b);
+        },
+        &mut Neg(ref mut a) => discrete(a),
+        &mut Sum(ref mut v) | &mut Mult(ref mut v) | &mut Max(ref mut v)
+        | &mut Min(ref mut v) | &mut Choose(ref mut v) => {
+            for x in v.iter_mut() {
+                discrete(x);
+            }
+        },
+        &mut CountStacks(_, ref mut tier, ref mut ent) |
+        &mut CountDur(_, ref mut tier, ref mut ent) => {
+            buff_tier(tier);
+            entity(ent);
+        },
+        &mut CooldownLeft(_, ref mut ent) |
+        &mut Charges(_, ref mut ent) => entity(ent),
+        &mut Cardinality(ref mut set) => entity_set(set),
+        &mut Const(_) | &mut Range(_, _) | &mut WithinPercent(_, _) | &mut LoadFrom(_) => (),
+    }
+    let simpler = match *d {
+        // no draw is made for an empty range
+        Range(x, y) if y <= x => Some(Const(x)),
+        Div(ref a, ref b) => match (&**a, &**b) {
+            (&Const(x), &Const(y)) if y != 0 => Some(Const(x.wrapping_div(y))),
+            _ => None,
+        },
+        Neg(ref a) => match **a {
+            Const(x) => Some(Const(x.saturating_neg())),
+            _ => None,
+        },
+        // saturating adds from the left, so only a leading Sum flattens and
+        // only leading constants add up. adding 0 does nothing anywhere
+        Sum(ref mut v) => {
+            v.retain(|x| *x != Const(0));
+            leading(v, |x| match x { &mut Sum(ref mut v) => Some(mem::take(v)), _ => Option::None }, i32::saturating_add);
+            single(v, Const(0))
+        },
+        Mult(ref mut v) => {
+            v.retain(|x| *x != Const(1));
+            if v.contains(&Const(0)) && v.iter().all(pure_discrete) {
+                Some(Const(0))
+            } else {
+                leading(v, |x| match x { &mut Mult(ref mut v) => Some(mem::take(v)), _ => Option::None }, i32::saturating_mul);
+                single(v, Const(1))
+            }
+        },
+        // these start from 1, and constants don't care where they are
+        Max(ref mut v) => {
+            flatten(v, |x| match x { &mut Max(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            gather(v, |a, b| a.max(b), |c| c <= 1).map(|c| Const(c.max(1)))
+        },
+        Min(ref mut v) => {
+            flatten(v, |x| match x { &mut Min(ref mut v) => Some(mem::take(v)), _ => Option::None });
+            gather(v, |a, b| a.min(b), |c| c >= 1).map(|c| Const(c.min(1)))
+        },
+        // no draw is made to choose from nothing
+        Choose(ref v) if v.is_empty() => Some(Const(0)),
+        _ => None,
+    };
+    if let Some(x) = simpler {
+        *d = x;
+    }
+}
+
+// moves the operands of nested operations of the same kind up into `v`.
+// `inner` takes a nested one's operands
+fn flatten<T, F>(v: &mut Vec<T>, inner: F)
+where F: Fn(&mut T) -> Option<Vec<T>> {
+    let mut out = Vec::with_capacity(v.len());
+    for mut x in v.drain(..) {
+        match inner(&mut x) {
+            Some(nested) => out.extend(nested),
+            None => out.push(x),
+        }
+    }
+    *v = out;
+}
+
+// flattens a leading nested operation and combines leading constants
+fn leading<F, G>(v: &mut Vec<Discrete>, inner: F, combine: G)
+where F: Fn(&mut Discrete) -> Option<Vec<Discrete>>, G: Fn(i32, i32) -> i32 {
+    use magic::Discrete::Const;
+    while !v.is_empty() {
+        match inner(&mut v[0]) {
+            Some(nested) => {
+                v.splice(..1, nested);
+            },
+            None => break,
+        }
+    }
+    while v.len() >= 2 {
+        match (&v[0], &v[1]) {
+            (&Const(a), &Const(b)) => {
+                v.drain(..2);
+                v.insert(0, Const(combine(a, b)));
+            },
+            _ => break,
+        }
+    }
+}
+
+// folds every constant in `v` into one at the end, dropping it if `moot`.
+// when only constants were left, what they came to
+fn gather<F, M>(v: &mut Vec<Discrete>, combine: F, moot: M) -> Option<i32>
+where F: Fn(i32, i32) -> i32, M: Fn(i32) -> bool {
+    use magic::Discrete::Const;
+    let mut constant = None;
+    v.retain(|x| match x {
+        &Const(c) => {
+            constant = Some(constant.map_or(c, |a| combine(a, c)));
+            false
+        },
+        _ => true,
+    });
+    match constant {
+        Some(c) if v.is_empty() => return Some(c),
+        Some(c) if !moot(c) => v.push(Const(c)),
+        None if v.is_empty() => return Some(1),
+        _ => (),
+    }
+    None
+}
+
+// what an operation of one operand is, or of none
+fn single<T>(v: &mut Vec<T>, none: T) -> Option<T> {
+    match v.len() {
+        0 => Some(none),
+        1 => v.pop(),
+        _ => None,
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////
+
+// PURITY
+// Pure parts don't draw from the rng and can't fail, so dropping them
+// changes nothing. Anything that might is counted impure.
+
+fn pure_discrete(d: &Discrete) -> bool {
+    use magic::Discrete::*;
+    match *d {
+        Const(_) | LoadFrom(_) => true,
+        Range(x, y) => y <= x,
+        Sum(ref v) | Mult(ref v) | Max(ref v) | Min(ref v) => v.iter().all(pure_discrete),
+        Neg(ref a) => pure_discrete(a),
+        Div(ref a, ref b) => pure_discrete(a) && matches!(**b, Const(y) if y != 0),
+        _ => false,
+    }
+}
+
+fn pure_condition(c: &Condition) -> bool {
+    use magic::Condition::*;
+    match c {
+        &Top | &Bottom => true,
+        &Nand(ref v) | &And(ref v) | &Or(ref v) => v.iter().all(pure_condition),
+        &Equals(ref a, ref b) | &LessThan(ref a, ref b) | &MoreThan(ref a, ref b) => {
+            pure_discrete(a) && pure_discrete(b)
+        },
+        &EntitySetCmp(_) => false,
+    }
+}
+
+fn pure_entity_set(s: &EntitySet) -> bool {
+    use magic::EntitySet::*;
+    match s {
+        &IsInSlot(_) | &IsHuman | &IsProjectile | &Empty | &Universe => true,
+        &None(ref v) | &And(ref v) | &Or(ref v) => v.iter().all(pure_entity_set),
+        &Only(ref e) | &EnemiesOf(ref e) | &AllBut(ref e) => pure_entity(e),
+        &WithinRangeOf(_, _) | &HasMinResource(_) => false,
+    }
+}
+
+fn pure_entity(e: &Entity) -> bool {
+    match *e {
+        Entity::LoadEntity(_) => true,
+        Entity::FirstOf(ref s) => pure_entity_set(s),
+        _ => false,
+    }
+}
+
+fn pure_location(l: &Location) -> bool {
+    match *l {
+        Location::LoadLocation(_) => true,
+        Location::Midpoint(ref v) => v.iter().all(pure_location),
+        _ => false,
+    }
+}
+
+fn pure_definition(def: &Definition) -> bool {
+    match *def {
+        Definition::ESet(_, ref x) => pure_entity_set(x),
+        Definition::E(_, ref x) => pure_entity(x),
+        Definition::D(_, ref x) => pure_discrete(x),
+        Definition::L(_, ref x) => pure_location(x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, Isaac64Rng, SeedableRng};
+    use generate;
+    use movement_2d::Point;
+    use mutate;
+    use profile::Profile;
+    use space::{Space, Player};
+    use spell_text::Text;
+    use super::*;
+
+    // the checksum after every tick of two players close by casting `s` at
+    // each other now and then
+    fn trace(s: &Spell, seed: u64) -> Vec<u64> {
+        let mut space = Space::with_seed(seed);
+        let mut players = vec![];
+        for &x in [0., 10.].iter() {
+            let mut p = Player::new(1000, 1000);
+            p.add_spell(s.clone());
+            players.push(space.player_enter(Point(x, 0.), p));
+        }
+        let mut out = vec![];
+        for t in 0..150 {
+            if t % 20 == 0 {
+                space.player_cast(players[0], 0, Point(10., 0.));
+            }
+            if t % 30 == 0 {
+                space.player_cast(players[1], 0, Point(0., 0.));
+            }
+            space.tick();
+            out.push(space.checksum());
+        }
+        out
+    }
+
+    #[test]
+    fn behaviour_is_preserved() {
+        let profile = Profile::standard();
+        let samples = generate::samples(10);
+        let mut rng = Isaac64Rng::from_seed(&[23]);
+        let mut changed = 0;
+        for (i, original) in samples.iter().enumerate() {
+            for _ in 0..5 {
+                let mut s = original.clone();
+                match rng.gen_range(0, 4) {
+                    0 => mutate::reroll(&mut s, &profile, &mut rng),
+                    1 => mutate::nudge(&mut s, &mut rng),
+                    2 => mutate::add_instruction(&mut s, &profile, &mut rng),
+                    _ => mutate::crossover(&mut s, rng.choose(&samples).unwrap(), &mut rng),
+                };
+                let mut simpler = s.clone();
+                spell(&mut simpler);
+                if simpler != s {
+                    changed += 1;
+                }
+                assert_eq!(trace(&s, i as u64), trace(&simpler, i as u64), "{}\nsimplified to\n{}", s.to_text(), simpler.to_text());
+            }
+        }
+        // or there was nothing to compare
+        assert!(changed > 10);
+    }
+
+    #[test]
+    fn empty_calls_go() {
+        let mut s = Spell::from_text("(Spell
+            (on_cast (CallWith (D_3 (Const 2))) (CallWith (D_3 (Range 1 5))))
+            (requires Top) (on_cooldown) (consumes) (cast_time (Const 0)) (on_cast_start) (on_interrupt))").unwrap();
+        spell(&mut s);
+        // the Range draws, so it stays
+        assert_eq!(s.on_cast, vec![Instruction::CallWith(Definition::D(DSlot(3), Discrete::Range(1, 5)), vec![])]);
+    }
+}
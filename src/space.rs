@@ -0,0 +1,2148 @@
+use ::std::collections::HashMap;
+use ::std::collections::hash_map;
+use magic::*;
+use buffs::*;
+use std::rc::Rc;
+use rand::{Rng,Isaac64Rng,SeedableRng};
+use event_context::{EventContext,ContextFor};
+use eval_error::{EvalError,Recovery};
+use movement_2d::*;
+use modifiers;
+
+pub const UPDATES_PER_SEC: u64 = 30;
+
+// a line for the Space's log, if it has one. nothing is formatted otherwise
+macro_rules! log {
+    ($space:expr, $($arg:tt)*) => {
+        if let Some(log) = $space.log {
+            log(&format!($($arg)*));
+        }
+    }
+}
+
+
+#[allow(dead_code)]
+pub struct Projectile {
+    bp: Rc<ProjectileBlueprint>,
+    caster: Token,
+    pos: Point, 
+    cursor: Point,
+    sec_left: f32,
+    velocity: Vector,
+    touching: TokenSet, // on_collision fires only when contact begins
+    destroying: bool, // on_destroy is running. guards against re-entry
+    events: u64, // how many of its events have drawn a random stream so far
+    stream: u64, // keys its streams. drawn from the event that spawned it
+    buffs: BuffSet,
+    health: Option<i32>, // none until magic gives it some. at 0 it is destroyed
+}
+
+impl Projectile {
+    pub fn buffs(&self) -> &BuffSet {
+        &self.buffs
+    }
+
+    #[inline]
+    pub fn health(&self) -> Option<i32> {
+        self.health
+    }
+}
+
+#[allow(dead_code)]
+pub struct Space {
+    players: HashMap<Token, (Point, Player)>,
+    projectiles: HashMap<Token, (Point, Projectile)>,
+    seed: u64,
+    rng: Isaac64Rng, // only for picking tokens. events use streams
+    ticks: u64,
+    buff_table: Rc<BuffTable>,
+    buff_events: u64,
+    buff_depth: u8, // buff events running inside one another
+    token_players: TokenSet,
+    token_projectiles: TokenSet,
+    token_universe: TokenSet,
+    player_radius: f32,
+    projectile_radius: f32,
+    recovery: Recovery,
+    log: Option<fn(&str)>, // what happens in casts and events. off by default
+}
+
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct Token(usize);
+impl Token {
+    const NULL: Token = Token(0);
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+type IRng = Isaac64Rng;
+
+// Every event draws random numbers from its own stream, seeded by the Space's
+// seed and by what the event is: a player's nth cast, a projectile's nth event,
+// or a spell regaining a charge on some tick. So the results vary from event to
+// event, yet a replay reproduces them, and the draws of one event never shift
+// those of another. Tokens come from a shared stream, so they key nothing but
+// the casts of players, who enter before anything is cast.
+#[derive(Copy, Clone)]
+enum Stream {
+    Tokens,
+    Cast,       // caster token, its casts so far, then 1 when it goes off or 2 when interrupted
+    Projectile, // key drawn by the spawning event, events of that projectile so far
+    Cooldown,   // tick, caster token, spell index
+    Buff,       // buff events so far
+}
+
+impl Default for Space {
+    fn default() -> Space {
+        Space::new()
+    }
+}
+
+impl Space {
+    const TICK_PERIOD: f32 = 1.0 / UPDATES_PER_SEC as f32;
+    pub const DEFAULT_PLAYER_RADIUS: f32 = 12.0;
+    pub const DEFAULT_PROJECTILE_RADIUS: f32 = 5.0;
+    const MAX_BUFF_DEPTH: u8 = 8; // eg. Warm and Cold applying one another forever
+
+    pub fn new() -> Space {
+        Space::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u64) -> Space {
+        Space {
+            players: HashMap::new(),
+            projectiles: HashMap::new(),
+            seed,
+            rng: Isaac64Rng::from_seed(&[seed, Stream::Tokens as u64]),
+            ticks: 0,
+            buff_table: BuffTable::standard(),
+            buff_events: 0,
+            buff_depth: 0,
+
+            //optimization
+            token_universe: TokenSet::new(),
+            token_players: TokenSet::new(),
+            token_projectiles: TokenSet::new(),
+
+            player_radius: Self::DEFAULT_PLAYER_RADIUS,
+            projectile_radius: Self::DEFAULT_PROJECTILE_RADIUS,
+            recovery: Recovery::SkipInstruction,
+            log: None,
+        }
+    }
+
+    fn stream(&self, kind: Stream, key: &[u64]) -> IRng {
+        let mut seed = vec![self.seed, kind as u64];
+        seed.extend_from_slice(key);
+        Isaac64Rng::from_seed(&seed)
+    }
+
+    // a fresh stream for the next event of this projectile
+    fn projectile_stream(&mut self, tok: Token) -> IRng {
+        let (key, n) = match self.projectiles.get_mut(&tok) {
+            Some(&mut (_, ref mut proj)) => {
+                proj.events += 1;
+                (proj.stream, proj.events)
+            },
+            None => (0, 0),
+        };
+        self.stream(Stream::Projectile, &[key, n])
+    }
+
+    pub fn set_recovery(&mut self, recovery: Recovery) {
+        self.recovery = recovery;
+    }
+
+    pub fn set_log(&mut self, log: Option<fn(&str)>) {
+        self.log = log;
+    }
+
+    pub fn set_buff_table(&mut self, table: Rc<BuffTable>) {
+        self.buff_table = table;
+    }
+
+    pub fn buff_table(&self) -> &Rc<BuffTable> {
+        &self.buff_table
+    }
+
+    pub fn set_radii(&mut self, player_radius: f32, projectile_radius: f32) {
+        self.player_radius = player_radius;
+        self.projectile_radius = projectile_radius;
+    }
+
+    pub fn radius_of(&self, token: Token) -> Option<f32> {
+        if self.is_player(token) {
+            Some(self.player_radius)
+        } else if self.is_projectile(token) {
+            Some(self.projectile_radius)
+        } else {
+            None
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+        let mut rm_tokens: Vec<Token> = vec![];
+
+        //PLAYERS
+        let table = self.buff_table.clone();
+        let mut buff_ticks: Vec<(Token, Buff, u8, bool)> = vec![];
+        let mut buff_expiries: Vec<(Token, Buff, u8)> = vec![];
+        let mut off_cooldown: Vec<(Token, usize)> = vec![];
+        let mut casts_ended: Vec<(Token, bool)> = vec![];
+        let ticks = self.ticks;
+        for (&tok, &mut (ref mut pt, ref mut player)) in self.players.iter_mut() {
+            for (i, &mut (_, ref mut cooldown)) in player.spells.iter_mut().enumerate() {
+                if cooldown.tick(Self::TICK_PERIOD) {
+                    off_cooldown.push((tok, i));
+                }
+            }
+            let moving = player.velocity.speed() > 0.;
+            player.buffs.tick(tok, &table, moving, &mut buff_ticks, &mut buff_expiries);
+            //TODO move all players 
+            pt.apply_vector(&player.velocity);
+
+            // moving, being muted or shivering cuts a cast short
+            if let Some(ref mut casting) = player.casting {
+                if moving || !modifiers::can_cast(&player.buffs) || modifiers::shivers(&player.buffs, ticks) {
+                    casts_ended.push((tok, true));
+                } else {
+                    casting.sec_left -= Self::TICK_PERIOD;
+                    if casting.sec_left <= 0. {
+                        casts_ended.push((tok, false));
+                    }
+                }
+            }
+
+            //Decelerate all players
+            player.velocity *= 0.8;
+            player.velocity.slow_by(1.0);
+        }
+        for token in rm_tokens.drain(..) {
+            self.player_leave(token);
+        }
+        off_cooldown.sort();
+        for (tok, spell_index) in off_cooldown {
+            self.spell_off_cooldown(tok, spell_index);
+        }
+        casts_ended.sort();
+        for (tok, interrupted) in casts_ended {
+            self.end_cast(tok, interrupted);
+        }
+        self.run_buff_ticks(&mut buff_ticks, &mut buff_expiries);
+
+        // PROJECTILES
+        for (&tok, &mut (ref mut pt, ref mut proj)) in self.projectiles.iter_mut() {
+            // tick down
+            proj.sec_left -= Space::TICK_PERIOD;
+            if proj.sec_left <= 0.0 {
+                rm_tokens.push(tok);
+            }
+            let moving = proj.velocity.speed() > 0.;
+            proj.buffs.tick(tok, &table, moving, &mut buff_ticks, &mut buff_expiries);
+
+            // move
+            pt.apply_vector(&proj.velocity);
+        }
+        self.run_buff_ticks(&mut buff_ticks, &mut buff_expiries);
+        rm_tokens.sort();
+        for token in rm_tokens.drain(..) {
+            self.destroy(token, true); // expiry
+        }
+
+        // COLLISIONS
+        let mut proj_tokens = self.projectiles.keys().cloned().collect::<Vec<_>>();
+        proj_tokens.sort();
+        for tok in proj_tokens {
+            self.collide_projectile(tok);
+        }
+    }
+
+    // every buff ticks before any expires, so a buff ticks on its last tick too
+    fn run_buff_ticks(&mut self, ticks: &mut Vec<(Token, Buff, u8, bool)>, expiries: &mut Vec<(Token, Buff, u8)>) {
+        ticks.sort();
+        for (tok, buff, tier, moving) in ticks.drain(..) {
+            let stacks = self.buffs_of(tok).map(|b| b.stacks_of(buff, Some(tier))).unwrap_or(0);
+            if stacks > 0 {
+                self.buff_event_with(tok, buff, tier, stacks as u8, &[(DSlot(2), moving as i32)], |d| &d.on_tick[..]);
+            }
+        }
+        expiries.sort();
+        for (tok, buff, tier) in expiries.drain(..) {
+            let expired = self.buffs_of(tok).map(|b| b.buff_left(buff, Some(tier)) <= 0.).unwrap_or(false);
+            if expired {
+                self.remove_buff(tok, buff, Some(tier), u8::MAX); // buff complete falloff
+            }
+        }
+    }
+
+    // a digest of all the state that decides how the simulation continues.
+    // replays compare it tick by tick to find where two runs part ways
+    pub fn checksum(&self) -> u64 {
+        let mut d = Digest::new();
+        let mut players = self.players.iter().collect::<Vec<_>>();
+        players.sort_by_key(|x| x.0);
+        for (tok, &(pt, ref player)) in players {
+            d.token(*tok);
+            d.point(pt);
+            d.u64(player.health as u64);
+            d.u64(player.health_max as u64);
+            d.u64(player.mana as u64);
+            d.u64(player.mana_max as u64);
+            player.buffs.digest(&mut d);
+            d.vector(player.velocity);
+            d.u64(player.casts);
+            for (_, cd) in player.spells.iter() {
+                d.f32(cd.remaining);
+                d.u64(cd.charges as u64);
+            }
+            match player.casting {
+                Some(c) => {
+                    d.u64(c.spell_index as u64 + 1);
+                    d.point(c.cursor);
+                    d.f32(c.sec_left);
+                    d.u64(c.cast);
+                },
+                None => d.u64(0),
+            }
+        }
+        let mut projectiles = self.projectiles.iter().collect::<Vec<_>>();
+        projectiles.sort_by_key(|x| x.0);
+        for (tok, &(pt, ref proj)) in projectiles {
+            d.token(*tok);
+            d.point(pt);
+            d.token(proj.caster);
+            d.point(proj.cursor);
+            d.f32(proj.sec_left);
+            d.vector(proj.velocity);
+            d.u64(proj.events);
+            d.u64(proj.stream);
+            proj.buffs.digest(&mut d);
+            d.u64(proj.health.map(|h| h as u64 + 1).unwrap_or(0));
+            for t in proj.touching.0.iter() {
+                d.token(*t);
+            }
+        }
+        d.u64(self.rng.clone().gen());
+        d.u64(self.ticks);
+        d.u64(self.buff_events);
+        d.0
+    }
+
+    fn collide_projectile(&mut self, tok: Token) {
+        let (bp, ctx) = if let Some((_, proj)) = self.projectiles.get(&tok) {
+            (proj.bp.clone(), Self::projectile_context(tok, proj))
+        } else { return }; // destroyed by an earlier collision
+        let mut rng2 = self.projectile_stream(tok);
+        let candidates = match self.eval_entity_set(&mut rng2, &ctx, &bp.collides_with) {
+            Ok(x) => x,
+            Err(e) => {
+                log!(self, "collides_with failed: {}", e);
+                TokenSet::new()
+            },
+        };
+        let mut now_touching = TokenSet::new();
+        if let (Some(pt), Some(radius)) = (self.point_of(tok), self.radius_of(tok)) {
+            for &other in candidates.0.iter() {
+                if other == tok { continue }
+                if let (Some(other_pt), Some(other_radius)) = (self.point_of(other), self.radius_of(other)) {
+                    if pt.dist_to(&other_pt) <= radius + other_radius {
+                        now_touching.insert(other);
+                    }
+                }
+            }
+        }
+        let was_touching = if let Some(&mut (_, ref mut proj)) = self.projectiles.get_mut(&tok) {
+            ::std::mem::replace(&mut proj.touching, now_touching.clone())
+        } else { return };
+        for &other in now_touching.0.iter() {
+            if was_touching.contains(other) { continue }
+            if !self.is_projectile(tok) { return } // destroyed by its own collision
+            let mut ctx = ctx.clone();
+            ctx.define(ESlot(2), other);
+            self.run_event(&mut rng2, &mut ctx, &bp.on_collision);
+        }
+    }
+
+    // ent0:caster, ent1:me, loc0:cursor
+    fn projectile_context(tok: Token, proj: &Projectile) -> EventContext {
+        let mut ctx = EventContext::new();
+        ctx.define(ESlot(0), proj.caster);
+        ctx.define(ESlot(1), tok);
+        ctx.define(LSlot(0), proj.cursor);
+        ctx
+    }
+
+    pub fn add_velocity_to(&mut self, token: Token, velocity: Vector) -> bool {
+        self.add_velocity_to_player(token, velocity)
+        || self.add_velocity_to_projectile(token, velocity)
+    }
+
+    // forces are dampened by the entity's buffs. see modifiers::force_factor
+    pub fn add_velocity_to_player(&mut self, token: Token, velocity: Vector) -> bool {
+        if let Some(&mut (_, ref mut player)) = self.players.get_mut(&token) {
+            player.velocity += velocity * modifiers::force_factor(&player.buffs);
+            true
+        } else { false }
+    }
+
+    pub fn add_velocity_to_projectile(&mut self, token: Token, velocity: Vector) -> bool {
+        if let Some(&mut (_, ref mut proj)) = self.projectiles.get_mut(&token) {
+            proj.velocity += velocity * modifiers::force_factor(&proj.buffs);
+            true
+        } else { false }
+    }
+
+    // a player walking of their own accord, at a speed their buffs may change
+    pub fn walk(&mut self, token: Token, direction: f32, speed: f32) -> bool {
+        let ticks = self.ticks;
+        if let Some(&mut (_, ref mut player)) = self.players.get_mut(&token) {
+            let speed = speed * modifiers::walk_factor(&player.buffs, ticks);
+            player.velocity += Vector::new_from_directional(direction, speed);
+            true
+        } else { false }
+    }
+
+    pub fn pt_of_player(&self, token: Token) -> Option<Point> {
+        self.players.get(&token).map(|x| x.0)
+    }
+
+    pub fn player(&self, token: Token) -> Option<&Player> {
+        self.players.get(&token).map(|x| &x.1)
+    }
+
+    pub fn players(&self) -> hash_map::Iter<'_, Token, (Point, Player)> {
+        self.players.iter()
+    }
+
+    pub fn projectiles(&self) -> hash_map::Iter<'_, Token, (Point, Projectile)> {
+        self.projectiles.iter()
+    }
+
+    fn spawn_projectile(&mut self, rng: &mut IRng, caster: Token, spawn_at: Point, cursor: Point, bp: Rc<ProjectileBlueprint>) -> Result<(), EvalError> {
+        let tok = self.free_token();
+        // each spawn draws the next key, so the streams of projectiles don't
+        // depend on their tokens, nor on what else was spawned before
+        let key = rng.gen::<u64>();
+        let mut ctx = EventContext::new();
+        ctx.define(ESlot(0), caster);
+        ctx.define(ESlot(1), tok);
+        ctx.define(LSlot(0), cursor);
+        // creation is event 0 of the projectile
+        let mut rng2 = self.stream(Stream::Projectile, &[key, 0]);
+        let lifetime = self.eval_discrete(&mut rng2, &ctx, &bp.lifetime)? as f32;
+        let projectile = Projectile {
+            bp: bp.clone(),
+            caster,
+            pos: spawn_at, //TODO allow projectiles to spawn elsewhere 
+            cursor,
+            sec_left: lifetime,
+            velocity: Vector::NULL,
+            touching: TokenSet::new(),
+            destroying: false,
+            events: 0,
+            stream: key,
+            buffs: BuffSet::new(),
+            health: None,
+        };
+
+        let pt = self.point_of(caster).unwrap_or(spawn_at);
+        self.projectiles.insert(tok, (pt, projectile));
+        self.token_universe.insert(tok);
+        self.token_projectiles.insert(tok);
+
+        self.run_event(&mut rng2, &mut ctx, &bp.on_create);
+        Ok(())
+    }
+
+    fn free_token(&mut self) -> Token {
+        let mut tok = Token(self.rng.gen());
+        loop {
+            if !tok.is_null()
+            && !self.token_universe.contains(tok) {
+                break;
+            }
+            tok.0 += 1;
+        }
+        tok
+    }
+
+    pub fn player_enter(&mut self, pt: Point, player: Player) -> Token {
+        let tok = self.free_token();
+        self.players.insert(tok, (pt, player));
+        self.token_universe.insert(tok);
+        self.token_players.insert(tok);
+        tok
+    }
+
+    pub fn spell_of(&self, caster_token: Token, spell_index: usize) -> Option<Rc<Spell>> {
+        if let Some((_pt, player)) = self.players.get(&caster_token) {
+            if let Some((player_spell, _)) = player.spells.get(spell_index) {
+                return Some(player_spell.clone());
+            }
+        }
+        None
+    }
+
+
+    pub fn player_cast(&mut self, caster_token: Token, spell_index: usize, cursor_point: Point) {
+        log!(self, "player_cast");
+        let cast = match self.players.get_mut(&caster_token) {
+            Some(&mut (_, ref mut player)) => {
+                player.casts += 1;
+                player.casts
+            },
+            None => 0,
+        };
+        let mut rng2 = self.stream(Stream::Cast, &[caster_token.0 as u64, cast]);
+        let spell: Option<Rc<Spell>> = self.spell_of(caster_token, spell_index);
+        if spell.is_none() {
+            log!(self, "no spell in slot {:?}", spell_index);
+            return;
+        }
+        let spell = spell.unwrap();
+        if let Some(0) = self.player(caster_token).and_then(|p| p.cooldown(spell_index)).map(|c| c.charges) {
+            log!(self, "spell in slot {:?} is on cooldown", spell_index);
+            return;
+        }
+        if self.player(caster_token).map(|p| p.casting.is_some()).unwrap_or(false) {
+            log!(self, "already casting");
+            return;
+        }
+        let (can_cast, jitter) = match self.buffs_of(caster_token) {
+            Some(buffs) => (modifiers::can_cast(buffs), modifiers::cursor_jitter(buffs)),
+            None => (true, 0.),
+        };
+        if !can_cast {
+            log!(self, "caster is mute");
+            return;
+        }
+        let mut cursor_point = cursor_point;
+        if jitter > 0. {
+            let off = Vector::new_from_directional(rng2.gen::<f32>() * 2. * ::std::f32::consts::PI, rng2.gen::<f32>() * jitter);
+            cursor_point.apply_vector(&off);
+        }
+        let mut ctx = EventContext::new();
+        ctx.e.insert(ESlot(0), caster_token);
+        ctx.l.insert(LSlot(0), cursor_point);
+        log!(self, "{:#?}", &spell);
+        match self.eval_condition(&mut rng2, &ctx, &spell.requires) {
+            Ok(true) => log!(self, "Condition met!"),
+            Ok(false) => {
+                log!(self, "Condition not met!");
+                return;
+            },
+            Err(e) => {
+                log!(self, "Condition failed: {}", e);
+                return;
+            },
+        }
+        let cast_time = match self.eval_discrete(&mut rng2, &ctx, &spell.cast_time) {
+            Ok(x) => x as f32 * self.buffs_of(caster_token).map(modifiers::cast_time_factor).unwrap_or(1.),
+            Err(e) => {
+                log!(self, "cast_time failed: {}", e);
+                return;
+            },
+        };
+        self.run_event(&mut rng2, &mut ctx, &spell.on_cast_start);
+        if cast_time <= 0. {
+            self.resolve_cast(&mut rng2, &mut ctx, caster_token, spell_index, &spell);
+        } else if let Some(&mut (_, ref mut player)) = self.players.get_mut(&caster_token) {
+            player.casting = Some(Casting {
+                spell_index,
+                cursor: cursor_point,
+                sec_left: cast_time,
+                cast,
+            });
+        }
+    }
+
+    // the cast in progress goes off, or is cut short
+    fn end_cast(&mut self, caster_token: Token, interrupted: bool) {
+        let casting = match self.players.get_mut(&caster_token) {
+            Some(&mut (_, ref mut player)) => player.casting.take(),
+            None => None,
+        };
+        let casting = match casting {
+            Some(x) => x,
+            None => return,
+        };
+        let spell = match self.spell_of(caster_token, casting.spell_index) {
+            Some(x) => x,
+            None => return,
+        };
+        let mut rng2 = self.stream(Stream::Cast, &[caster_token.0 as u64, casting.cast, if interrupted { 2 } else { 1 }]);
+        let mut ctx = EventContext::new();
+        ctx.e.insert(ESlot(0), caster_token);
+        ctx.l.insert(LSlot(0), casting.cursor);
+        if interrupted {
+            log!(self, "cast interrupted");
+            self.run_event(&mut rng2, &mut ctx, &spell.on_interrupt);
+        } else {
+            self.resolve_cast(&mut rng2, &mut ctx, caster_token, casting.spell_index, &spell);
+        }
+    }
+
+    // pays for the spell, then runs on_cast
+    fn resolve_cast(&mut self, rng2: &mut IRng, ctx: &mut EventContext, caster_token: Token, spell_index: usize, spell: &Spell) {
+        let consume = {
+            spell.consumes
+            .iter()
+            .map(|r| self.eval_resource(rng2, ctx, r))
+            .collect::<Result<Vec<_>, _>>()
+        };
+        let consume = match consume {
+            Ok(x) => x,
+            Err(e) => {
+                log!(self, "Consumes failed: {}", e);
+                return;
+            },
+        };
+        log!(self, "consuming... {:#?}", &consume);
+        let x = self.players.get_mut(&caster_token).and_then(
+            |&mut (_, ref mut player)| {
+                let buffs = player.try_remove_resources(&consume[..])?;
+                if player.spells[spell_index].1.use_charge() { Some(buffs) } else { None }
+            }
+        );
+        if let Some(buffs) = x {
+            log!(self, "consume success!");
+            for (buff, tier, x) in buffs {
+                if x < 0 {
+                    self.apply_buff(caster_token, buff, tier.unwrap_or(1), (-x).min(u8::MAX as i32) as u8);
+                } else {
+                    self.remove_buff(caster_token, buff, tier, x.min(u8::MAX as i32) as u8);
+                }
+            }
+            if self.buffs_of(caster_token).map(modifiers::cast_confused).unwrap_or(false) {
+                log!(self, "confused! the spell fizzles");
+                self.remove_buff(caster_token, Buff::Confused, None, 1);
+                return;
+            }
+            self.run_event(rng2, ctx, &spell.on_cast);
+        } else {
+            log!(self, "consume failure!");
+        }
+    }
+
+    fn spell_off_cooldown(&mut self, caster_token: Token, spell_index: usize) {
+        if let Some(spell) = self.spell_of(caster_token, spell_index) {
+            let mut ctx = EventContext::new();
+            ctx.define(ESlot(0), caster_token);
+            let mut rng2 = self.stream(Stream::Cooldown, &[self.ticks, caster_token.0 as u64, spell_index as u64]);
+            self.run_event(&mut rng2, &mut ctx, &spell.on_cooldown);
+        }
+    }
+
+    pub fn move_to(&mut self, token: Token, pt: Point) -> bool {
+        if let Some(&mut (ref mut old_pt, _)) = self.players.get_mut(&token) {
+            *old_pt = pt;
+            true
+        } else {
+            false
+        }
+    }   
+
+    pub fn player_leave(&mut self, token: Token) -> Option<(Point, Player)> {
+        self.token_universe.remove(token);
+        self.token_players.remove(token);
+        self.players.remove(&token)
+    }
+
+    pub fn is_player(&self, token: Token) -> bool {
+        self.players.contains_key(&token)
+    }
+
+    pub fn is_projectile(&self, token: Token) -> bool {
+        self.projectiles.contains_key(&token)
+    }
+
+    fn point_of(&self, tok: Token) -> Option<Point> {
+        if let Some(&(pt,_)) = self.players.get(&tok) {
+            Some(pt)
+        } else if let Some(&(pt,_)) = self.projectiles.get(&tok) {
+            Some(pt)
+        } else {
+            None
+        }
+    }
+
+    // runs a hook's instructions. failures are handled according to `self.recovery`
+    fn run_event(&mut self, rng: &mut IRng, ctx: &mut EventContext, block: &[Instruction]) {
+        if let Err(e) = self.execute_block(rng, ctx, block) {
+            log!(self, "event aborted: {}", e);
+        }
+    }
+
+    fn execute_block(&mut self, rng: &mut IRng, ctx: &mut EventContext, block: &[Instruction]) -> Result<(), EvalError> {
+        for ins in block {
+            if let Err(e) = self.execute_instruction(rng, ctx, ins) {
+                if self.recovery == Recovery::AbortEvent {
+                    return Err(e);
+                }
+                log!(self, "skipping instruction: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // `default` under Recovery::SubstituteDefault, and Err under the others
+    fn recover<T>(&self, err: EvalError, default: T) -> Result<T, EvalError> {
+        if self.recovery == Recovery::SubstituteDefault {
+            Ok(default)
+        } else {
+            Err(err)
+        }
+    }
+
+    fn execute_instruction(&mut self, rng: &mut IRng, ctx: &mut EventContext, ins: &Instruction) -> Result<(), EvalError> {
+        use magic::Instruction::*;
+        log!(self, "Executing ... {:?}", ins);
+        match *ins {
+            Define(ref def) => self.execute_defintion(rng, ctx, def)?,
+            ITE(ref cond, ref then, ref els) => {
+                if self.eval_condition(rng, ctx, cond)? {
+                    self.execute_block(rng, ctx, then)?;
+                } else {
+                    self.execute_block(rng, ctx, els)?;
+                }
+            },
+            CallWith(ref def, ref ins) => {
+                let ctx_was = ctx.clone();
+                let result = self.execute_defintion(rng, ctx, def)
+                    .and_then(|_| self.execute_block(rng, ctx, ins));
+                *ctx = ctx_was;
+                result?;
+            },
+            ForEachAs(slot, ref set, ref ins) => {
+                let set = self.eval_entity_set(rng, ctx, set)?;
+                for &tok in set.0.iter() {
+                    ctx.define(slot, tok);
+                    self.execute_block(rng, ctx, ins)?;
+                }
+            },
+            DestroyWithoutEvent(ref ent) => {
+                let tok = self.eval_entity(rng, ctx, ent)?;
+                self.destroy(tok, false);
+            },
+            Destroy(ref ent) => {
+                let tok = self.eval_entity(rng, ctx, ent)?;
+                self.destroy(tok, true);
+            },
+            MoveEntity(ref ent, ref loc) => {
+                let pt = self.eval_location(rng, ctx, loc)?;
+                let token = self.eval_entity(rng, ctx, ent)?;
+                self.move_to(token, pt);
+            },
+            AddResource(ref ent, ref rsrc) => {
+                let token = self.eval_entity(rng, ctx, ent)?;
+                let r = self.eval_resource(rng, ctx, rsrc)?;
+                self.add_resource_to(token, r);
+            },
+            AddVelocity(ref ent, ref dir, ref disc) => { // last arg is "speed"
+                let tok = self.eval_entity(rng, ctx, ent)?;
+                let f = self.eval_direction(rng, ctx, dir)?;
+                let d = self.eval_discrete(rng, ctx, disc)?;
+                let vel = Vector::new_from_directional(f, d as f32);
+                self.add_velocity_to(tok, vel);
+            },
+            SpawnProjectileAt(ref rc_proj, ref loc) => {
+                let spawn_loc = self.eval_location(rng, ctx, loc)?;
+                if let (Some(&token), Some(&cursor_loc)) = (ctx.load(&ESlot(0)), ctx.load(&LSlot(0))) {
+                    self.spawn_projectile(rng, token, spawn_loc, cursor_loc, rc_proj.clone())?;
+                }
+            },
+            Nothing => (),
+        }
+        Ok(())
+    }
+
+    pub fn add_resource_to(&mut self, token: Token, resource: ConcreteResource) -> bool {
+        if let ConcreteResource::BuffStacks(buff, tier, x) = resource {
+            return if x >= 0 {
+                self.apply_buff(token, buff, tier.unwrap_or(1), x as u8)
+            } else {
+                self.remove_buff(token, buff, tier, (-(x as i16)) as u8)
+            };
+        }
+        let over_time = self.buff_depth > 0; // dealt by a buff event
+        if let Some(&mut (_, ref mut player)) = self.players.get_mut(&token) {
+            let resource = match resource {
+                ConcreteResource::Health(x) => ConcreteResource::Health(
+                    modifiers::health_change(&player.buffs, x, player.health_max, over_time)
+                ),
+                r => r,
+            };
+            player.add_resource(resource);
+            return true;
+        }
+        let shot_down = match (self.projectiles.get_mut(&token), resource) {
+            (Some(&mut (_, ref mut proj)), ConcreteResource::Health(x)) => {
+                // a projectile without health gains some, but can't lose any
+                match proj.health {
+                    Some(ref mut h) => {
+                        let x = modifiers::health_change(&proj.buffs, x, (*h).max(0) as u32, over_time);
+                        *h = h.saturating_add(x).max(0)
+                    },
+                    None if x > 0 => proj.health = Some(x),
+                    None => (),
+                }
+                proj.health == Some(0)
+            },
+            _ => return false, // projectiles have no mana
+        };
+        if shot_down {
+            self.destroy(token, true);
+        }
+        true
+    }
+
+    // the health of a player, or of a projectile that has any
+    pub fn health_of(&self, token: Token) -> Option<i32> {
+        if let Some((_, player)) = self.players.get(&token) {
+            Some(player.health)
+        } else {
+            self.projectiles.get(&token).and_then(|x| x.1.health)
+        }
+    }
+
+    pub fn buffs_of(&self, token: Token) -> Option<&BuffSet> {
+        if let Some((_, player)) = self.players.get(&token) {
+            Some(&player.buffs)
+        } else {
+            self.projectiles.get(&token).map(|x| &x.1.buffs)
+        }
+    }
+
+    fn buffs_of_mut(&mut self, token: Token) -> Option<&mut BuffSet> {
+        if let Some(&mut (_, ref mut player)) = self.players.get_mut(&token) {
+            Some(&mut player.buffs)
+        } else {
+            self.projectiles.get_mut(&token).map(|x| &mut x.1.buffs)
+        }
+    }
+
+    // adds stacks to a tier according to the buff's stacking behaviour, then runs its on_apply,
+    // and its on_excess if that went past max_stacks. returns false if there is nothing to carry the buff
+    pub fn apply_buff(&mut self, token: Token, buff: Buff, tier: u8, stacks: u8) -> bool {
+        let tier = tier.clamp(1, MAX_TIER);
+        let table = self.buff_table.clone();
+        let (now, excess) = match self.buffs_of_mut(token) {
+            Some(buffs) => {
+                if stacks == 0 { return true }
+                buffs.apply_stacks(buff, tier, stacks, table.get(buff))
+            },
+            None => return false,
+        };
+        self.buff_event(token, buff, tier, now, |d| &d.on_apply[..]);
+        if excess > 0 {
+            let now = self.buffs_of(token).map(|b| b.stacks_of(buff, Some(tier)) as u8).unwrap_or(0);
+            self.buff_event_with(token, buff, tier, now, &[(DSlot(2), excess as i32)], |d| &d.on_excess[..]);
+        }
+        true
+    }
+
+    // on_remove runs for each tier with no stacks left afterwards, and sees the stacks it had.
+    // with no tier given, stacks are taken from the lowest tier first.
+    // returns true iff no stacks of the tier (or of any tier) are left
+    pub fn remove_buff(&mut self, token: Token, buff: Buff, tier: Option<u8>, stacks: u8) -> bool {
+        let tiers = match (self.buffs_of(token), tier) {
+            (Some(buffs), None) => buffs.tiers_of(buff),
+            (Some(_), Some(t)) => vec![t.clamp(1, MAX_TIER)],
+            (None, _) => return false,
+        };
+        if stacks == 0 { return false }
+        let mut left = stacks;
+        let mut gone = true;
+        for tier in tiers {
+            if left == 0 {
+                gone = false;
+                break;
+            }
+            let had = match self.buffs_of_mut(token) {
+                Some(buffs) => {
+                    let had = buffs.stacks_of(buff, Some(tier)) as u8;
+                    if !buffs.forcibly_remove(buff, tier, left) {
+                        return false;
+                    }
+                    had
+                },
+                None => return false,
+            };
+            left = left.saturating_sub(had);
+            self.buff_event(token, buff, tier, had, |d| &d.on_remove[..]);
+        }
+        gone
+    }
+
+    // ent0:bearer, loc0:bearer's position, d0:stacks, d1:tier
+    fn buff_event<F>(&mut self, token: Token, buff: Buff, tier: u8, stacks: u8, event: F)
+    where F: Fn(&BuffDef) -> &[Instruction] {
+        self.buff_event_with(token, buff, tier, stacks, &[], event)
+    }
+
+    // the same, with more dslots defined
+    fn buff_event_with<F>(&mut self, token: Token, buff: Buff, tier: u8, stacks: u8, more: &[(DSlot, i32)], event: F)
+    where F: Fn(&BuffDef) -> &[Instruction] {
+        let table = self.buff_table.clone();
+        let block = event(table.get(buff));
+        if block.is_empty() { return }
+        if self.buff_depth >= Self::MAX_BUFF_DEPTH {
+            log!(self, "{:?} not run: buff events nested too deeply", buff);
+            return;
+        }
+        let mut ctx = EventContext::new();
+        ctx.define(ESlot(0), token);
+        if let Some(pt) = self.point_of(token) {
+            ctx.define(LSlot(0), pt);
+        }
+        ctx.define(DSlot(0), stacks as i32);
+        ctx.define(DSlot(1), tier as i32);
+        for &(slot, x) in more {
+            ctx.define(slot, x);
+        }
+        let mut rng2 = self.stream(Stream::Buff, &[self.buff_events]);
+        self.buff_events += 1;
+        self.buff_depth += 1;
+        self.run_event(&mut rng2, &mut ctx, block);
+        self.buff_depth -= 1;
+    }
+
+    // projectiles run on_destroy before they are removed, so `me` can still be located.
+    // on_destroy draws from the projectile's own stream, whichever event destroyed it
+    fn destroy(&mut self, token: Token, trigger_event: bool) -> bool {
+        if self.player_leave(token).is_some() {
+            return true;
+        }
+        let (bp, ctx) = match self.projectiles.get_mut(&token) {
+            Some(&mut (_, ref mut proj)) if !proj.destroying => {
+                proj.destroying = true;
+                (proj.bp.clone(), Self::projectile_context(token, proj))
+            },
+            _ => return false, // absent or already being destroyed
+        };
+        if trigger_event {
+            let mut ctx = ctx;
+            let mut rng2 = self.projectile_stream(token);
+            self.run_event(&mut rng2, &mut ctx, &bp.on_destroy);
+        }
+        self.token_universe.remove(token);
+        self.token_projectiles.remove(token);
+        self.projectiles.remove(&token);
+        true
+    }
+
+    fn execute_defintion(&mut self, rng: &mut IRng, ctx: &mut EventContext, def: &Definition) -> Result<(), EvalError> {
+        use magic::Definition::*;
+        match *def {
+            ESet(s, ref eset) => {
+                let x = self.eval_entity_set(rng, ctx, eset)?;
+                ctx.define(s, x)
+            },
+            E(s, ref e) => {
+                let x = self.eval_entity(rng, ctx, e)?;
+                ctx.define(s, x)
+            },
+            D(s, ref d) => {
+                let x = self.eval_discrete(rng, ctx, d)?;
+                ctx.define(s, x)
+            },
+            L(s, ref l) => {
+                let x = self.eval_location(rng, ctx, l)?;
+                ctx.define(s, x)
+            },
+        }
+        Ok(())
+    }
+
+
+
+    fn eval_resource(&self, rng: &mut IRng, ctx: &EventContext, resource: &Resource) -> Result<ConcreteResource, EvalError> {
+        use magic::Resource::*;
+        Ok(match *resource {
+            Mana(ref x) => ConcreteResource::Mana(
+                self.eval_discrete(rng, ctx, x)?
+            ),
+            Health(ref x) => ConcreteResource::Health(
+                self.eval_discrete(rng, ctx, x)?
+            ),
+            BuffStacks(b, ref tier, ref x) => ConcreteResource::BuffStacks(
+                b,
+                self.eval_tier(rng, ctx, tier)?,
+                self.eval_discrete(rng, ctx, x)?.max(i8::MIN as i32).min(i8::MAX as i32) as i8,
+            ),
+        })
+    }
+
+    // None for AnyTier
+    fn eval_tier(&self, rng: &mut IRng, ctx: &EventContext, tier: &BuffTier) -> Result<Option<u8>, EvalError> {
+        Ok(match *tier {
+            BuffTier::Tier(ref d) => Some(self.eval_discrete(rng, ctx, d)?.clamp(1, MAX_TIER as i32) as u8),
+            BuffTier::AnyTier => None,
+        })
+    }
+
+    fn eval_direction(&self, rng: &mut IRng, ctx: &EventContext, direction: &Direction) -> Result<f32, EvalError> {
+        use magic::Direction::*;
+        Ok(match *direction {
+            TowardLocation(ref from, ref to) => {
+                let from = self.eval_location(rng, ctx, from)?;
+                let to = self.eval_location(rng, ctx, to)?;
+                if from != Point::NULL && to != Point::NULL {
+                    from.direction_to(&to)
+                } else { 0.0 }
+            },
+            ConstRad(new_f32) => new_f32.0,
+            BetweenRad(a, b) => a.0 + (rng.gen::<f32>() * (b.0 - a.0)),
+            Choose(ref dirs) => {
+                match rng.choose(dirs) {
+                    Some(d) => self.eval_direction(rng, ctx, d)?,
+                    None => 0.0,
+                }
+            },
+            ChooseWithinRadOf(ref dir, ref new_f32) => {
+                let mut val = rng.gen::<f32>() * new_f32.0;
+                if rng.gen() {val *= -1.0}
+                val + self.eval_direction(rng, ctx, dir)?
+            },
+        })
+    }
+
+    fn eval_discrete(&self, rng: &mut IRng, ctx: &EventContext, discrete: &Discrete) -> Result<i32, EvalError> {
+        use magic::Discrete::*;
+        Ok(match *discrete {
+            Const(x) => x,
+            Range(x, y) => {
+                if y <= x { x } else {
+                    (x as i64 + (rng.gen::<u32>() as i64 % (y as i64 - x as i64))) as i32
+                }
+            },
+            WithinPercent(ref x, ref y) => ((rng.gen::<f32>() * y.0) * (*x as f32)) as i32,
+            Div(ref x, ref y) => {
+                let x = self.eval_discrete(rng, ctx, x)?;
+                let y = self.eval_discrete(rng, ctx, y)?;
+                if y == 0 {
+                    self.recover(EvalError::DivisionByZero, 0)?
+                } else {
+                    x.wrapping_div(y)
+                }
+            },
+            Sum(ref x) => {
+                let mut tot: i32 = 0;
+                for q in x {
+                    tot = tot.saturating_add(self.eval_discrete(rng, ctx, q)?);
+                }
+                tot
+            },
+            Neg(ref x) => self.eval_discrete(rng, ctx, x)?.saturating_neg(),
+            Mult(ref x) => {
+                let mut tot: i32 = 1;
+                for q in x {
+                    tot = tot.saturating_mul(self.eval_discrete(rng, ctx, q)?);
+                }
+                tot
+            },
+            Max(ref x) => {
+                let mut a = 1;
+                for b in x {
+                    let b = self.eval_discrete(rng, ctx, b)?;
+                    if b > a {a = b}
+                }
+                a
+            },
+            Min(ref x) => {
+                let mut a = 1;
+                for b in x {
+                    let b = self.eval_discrete(rng, ctx, b)?;
+                    if b < a {a = b}
+                }
+                a
+            },
+            CountStacks(buff, ref tier, ref ent) => {
+                let tier = self.eval_tier(rng, ctx, tier)?;
+                let tok = self.eval_entity(rng, ctx, ent)?;
+                self.buffs_of(tok).map(|b| b.stacks_of(buff, tier) as i32).unwrap_or(0)
+            },
+            CountDur(buff, ref tier, ref ent) => {
+                let tier = self.eval_tier(rng, ctx, tier)?;
+                let tok = self.eval_entity(rng, ctx, ent)?;
+                self.buffs_of(tok).map(|b| b.buff_left(buff, tier) as i32).unwrap_or(0)
+            },
+            Choose(ref x) => {
+                if let Some(x) = rng.choose(x) {
+                    self.eval_discrete(rng, ctx, x)?
+                } else { 0 }
+            },
+            Cardinality(ref eset) => self.eval_entity_set(rng, ctx, eset)?.cardinality() as i32,
+            LoadFrom(dslot) => match ctx.load(&dslot) {
+                Some(&x) => x,
+                None => self.recover(EvalError::UnboundDSlot(dslot), 0)?,
+            },
+            CooldownLeft(spell_index, ref ent) => {
+                let tok = self.eval_entity(rng, ctx, ent)?;
+                self.player(tok)
+                .and_then(|p| p.cooldown(spell_index as usize))
+                .map(|c| c.remaining as i32)
+                .unwrap_or(0)
+            },
+            Charges(spell_index, ref ent) => {
+                let tok = self.eval_entity(rng, ctx, ent)?;
+                self.player(tok)
+                .and_then(|p| p.cooldown(spell_index as usize))
+                .map(|c| c.charges as i32)
+                .unwrap_or(0)
+            },
+        })
+    }
+
+    // may well evaluate to Token::NULL, eg. for the FirstOf an empty set.
+    fn eval_entity(&self, rng: &mut IRng, ctx: &EventContext, entity: &Entity) -> Result<Token, EvalError> {
+        use magic::Entity::*;
+        Ok(match *entity {
+            LoadEntity(eslot) => match ctx.load(&eslot) {
+                Some(&tok) => tok,
+                None => self.recover(EvalError::UnboundESlot(eslot), Token::NULL)?,
+            },
+            FirstOf(ref eset) => self.eval_entity_set(rng, ctx, eset)?.first(),
+            Choose(ref eset) => self.eval_entity_set(rng, ctx, eset)?.choose(rng),
+            ClosestFrom(ref eset, ref loc) => {
+                let ref_pt = self.eval_location(rng, ctx, loc)?;
+                let (mut closest, mut smallest_dist) = (Token::NULL, f32::MAX);
+                for ent_tok in self.eval_entity_set(rng, ctx, eset)?.0 {
+                    if let Some(pt) = self.point_of(ent_tok) {
+                        let dist = pt.dist_to(&ref_pt);
+                        if dist < smallest_dist {
+                            smallest_dist = dist;
+                            closest = ent_tok;
+                        }
+                    }
+                }
+                closest
+            },
+        })
+    }
+
+    // the location of an entity that must exist
+    fn eval_point_of(&self, tok: Token) -> Result<Point, EvalError> {
+        if tok.is_null() {
+            self.recover(EvalError::NullToken, Point::NULL)
+        } else if let Some(pt) = self.point_of(tok) {
+            Ok(pt)
+        } else {
+            self.recover(EvalError::DeadToken(tok), Point::NULL)
+        }
+    }
+
+    fn eval_entity_set(&self, rng: &mut IRng, ctx: &EventContext, entity_set: &EntitySet) -> Result<TokenSet, EvalError> {
+        use magic::EntitySet::*;
+        Ok(match *entity_set {
+            None(ref sets) => {
+                let mut evaluated = vec![];
+                for s in sets.iter() {
+                    evaluated.push(self.eval_entity_set(rng, ctx, s)?);
+                }
+                let mut ret = TokenSet::new();
+                for &tok in self.token_universe.0.iter() {
+                    if !evaluated.iter().any(|s| s.contains(tok)) {
+                        ret.insert(tok);
+                    }
+                }
+                ret
+            },
+            And(ref sets) => {
+                let mut evaluated = vec![];
+                for s in sets.iter() {
+                    evaluated.push(self.eval_entity_set(rng, ctx, s)?);
+                }
+                let mut ret = TokenSet::new();
+                for &tok in self.token_universe.0.iter() {
+                    if evaluated.iter().all(|s| s.contains(tok)) {
+                        ret.insert(tok);
+                    }
+                }
+                ret
+            },
+            Or(ref sets) => {
+                let mut ret = TokenSet::new();
+                for s in sets.iter() {
+                    for &tok in self.eval_entity_set(rng, ctx, s)?.0.iter() {
+                        ret.insert(tok);
+                    }
+                }
+                ret
+            },
+            Only(ref ent) => {
+                let mut s = TokenSet::new();
+                s.insert(self.eval_entity(rng, ctx, ent)?);
+                s   
+            },
+            IsInSlot(eset_slot) => {
+                if let Some(set) = ctx.load(&eset_slot) {
+                    set.clone()
+                } else {
+                    self.recover(EvalError::UnboundESetSlot(eset_slot), TokenSet::new())?
+                }
+            },
+            WithinRangeOf(ref ent, ref disc) => {
+                let e = self.eval_entity(rng, ctx, ent)?;
+                let ref_loc = self.eval_point_of(e)?;
+                let thresh = self.eval_discrete(rng, ctx, disc)? as f32;
+                let mut s = TokenSet::new();
+                for &tok in self.token_universe.0.iter() {
+                    if let Some(pt) = self.point_of(tok) {
+                        if ref_loc.dist_to(&pt) < thresh {
+                            s.insert(tok);
+                        }
+                    }
+                }
+                s
+            },
+            HasMinResource(ref res) => {
+                let mut ret = TokenSet::new();
+                for &tok in self.token_universe.0.iter() {
+                    use magic::Resource::*; //TODO make resource more powerful
+                    match *res {
+                        Mana(ref x) => {
+                            let need = self.eval_discrete(rng, ctx, x)?;
+                            if let Some((_, player)) = self.players.get(&tok) {
+                                if player.mana >= need {
+                                    ret.insert(tok);
+                                }
+                            }
+                        },
+                        Health(ref x) => {
+                            let need = self.eval_discrete(rng, ctx, x)?;
+                            if let Some(health) = self.health_of(tok) {
+                                if health >= need {
+                                    ret.insert(tok);
+                                }
+                            }
+                        },
+                        BuffStacks(buff, ref tier, ref disc) => {
+                            let tier = self.eval_tier(rng, ctx, tier)?;
+                            let need = self.eval_discrete(rng, ctx, disc)?;
+                            if let Some(buffs) = self.buffs_of(tok) {
+                                if buffs.stacks_of(buff, tier) as i64 >= need as i64 {
+                                    ret.insert(tok);
+                                }
+                            }
+                        },
+                    }
+                }
+                ret
+            },
+            EnemiesOf(ref ent) => {
+                let mut s = self.token_players.clone();
+                s.remove(self.eval_entity(rng, ctx, ent)?);
+                s
+            },
+            AllBut(ref ent) => {
+                let mut s = self.token_universe.clone();
+                s.remove(self.eval_entity(rng, ctx, ent)?);
+                s
+            },
+            IsHuman => self.token_players.clone(),
+            IsProjectile => self.token_projectiles.clone(),
+            Empty => TokenSet::new(),
+            Universe => self.token_universe.clone(),
+        })
+    }
+
+    // every operand is evaluated (no short circuiting) so rng use doesn't depend on the outcome
+    fn eval_condition(&self, rng: &mut IRng, ctx: &EventContext, condition: &Condition) -> Result<bool, EvalError> {
+        use magic::Condition::*;
+        Ok(match *condition {
+            Nand(ref conds) => !self.eval_conditions(rng, ctx, conds)?.into_iter().all(|b| b),
+            And(ref conds) => self.eval_conditions(rng, ctx, conds)?.into_iter().all(|b| b),
+            Or(ref conds) => self.eval_conditions(rng, ctx, conds)?.into_iter().any(|b| b),
+            Top => true,
+            Bottom => false,
+            Equals(ref disc_a, ref disc_b) => self.eval_discrete(rng, ctx, disc_a)? == self.eval_discrete(rng, ctx, disc_b)?,
+            LessThan(ref disc_a, ref disc_b) => self.eval_discrete(rng, ctx, disc_a)? < self.eval_discrete(rng, ctx, disc_b)?,
+            MoreThan(ref disc_a, ref disc_b) => self.eval_discrete(rng, ctx, disc_a)? > self.eval_discrete(rng, ctx, disc_b)?,
+            EntitySetCmp(ref esetcmp) => self.eval_entity_set_cmp(rng, ctx, esetcmp)?,
+        })
+    }
+
+    fn eval_conditions(&self, rng: &mut IRng, ctx: &EventContext, conditions: &[Condition]) -> Result<Vec<bool>, EvalError> {
+        conditions.iter().map(|x| self.eval_condition(rng, ctx, x)).collect()
+    }
+
+    fn eval_entity_set_cmps(&self, rng: &mut IRng, ctx: &EventContext, cmps: &[EntitySetCmp]) -> Result<Vec<bool>, EvalError> {
+        cmps.iter().map(|x| self.eval_entity_set_cmp(rng, ctx, x)).collect()
+    }
+
+    fn eval_entity_set_cmp(&self, rng: &mut IRng, ctx: &EventContext, ent_set_cmp: &EntitySetCmp) -> Result<bool, EvalError> {
+        use magic::EntitySetCmp::*;
+        Ok(match *ent_set_cmp {
+            Nand(ref v) => !self.eval_entity_set_cmps(rng, ctx, v)?.into_iter().all(|b| b),
+            And(ref v) => self.eval_entity_set_cmps(rng, ctx, v)?.into_iter().all(|b| b),
+            Or(ref v) => self.eval_entity_set_cmps(rng, ctx, v)?.into_iter().any(|b| b),
+            Subset(ref a, ref b) => {
+                let a = self.eval_entity_set(rng, ctx, a)?;
+                let b = self.eval_entity_set(rng, ctx, b)?;
+                a.0.iter().all(|e| b.contains(*e))
+            },
+            Superset(ref a, ref b) => {
+                let a = self.eval_entity_set(rng, ctx, a)?;
+                let b = self.eval_entity_set(rng, ctx, b)?;
+                b.0.iter().all(|e| a.contains(*e))
+            },
+            Equal(ref a, ref b) => {
+                self.eval_entity_set(rng, ctx, a)? == self.eval_entity_set(rng, ctx, b)?
+            },
+            Contains(ref eset, ref e) => {
+                let set = self.eval_entity_set(rng, ctx, eset)?;
+                let ent = self.eval_entity(rng, ctx, e)?;
+                ent != Token::NULL && set.contains(ent)
+            },
+        })
+    }
+
+    fn eval_location(&self, rng: &mut IRng, ctx: &EventContext, location: &Location) -> Result<Point, EvalError> {
+        use magic::Location::*;
+        Ok(match *location {
+            AtEntity(ref ent) => {
+                let e = self.eval_entity(rng, ctx, ent)?;
+                self.eval_point_of(e)?
+            },
+            Midpoint(ref locs) => {
+                let mut pts = vec![];
+                for x in locs.iter() {
+                    pts.push(self.eval_location(rng, ctx, x)?);
+                }
+                Point::midpoint(&pts).unwrap_or(Point::NULL)
+            },
+            Choose(ref locs) => {
+                if let Some(x) = rng.choose(locs) {
+                    self.eval_location(rng, ctx, x)?
+                } else {
+                    Point::NULL
+                }
+            },
+            LoadLocation(lslot) => match ctx.load(&lslot) {
+                Some(&pt) => pt,
+                None => self.recover(EvalError::UnboundLSlot(lslot), Point::NULL)?,
+            },
+        })
+    }
+}
+
+// FNV-1a. unlike the std hashers, it is guaranteed to stay the same
+// between builds, so checksums can be saved
+struct Digest(u64);
+impl Digest {
+    fn new() -> Self {
+        Digest(0xcbf29ce484222325)
+    }
+    fn u64(&mut self, x: u64) {
+        for i in 0..8 {
+            self.0 ^= (x >> (8 * i)) & 0xff;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    fn f32(&mut self, x: f32) {
+        self.u64(x.to_bits() as u64)
+    }
+    fn token(&mut self, t: Token) {
+        self.u64(t.0 as u64)
+    }
+    fn point(&mut self, pt: Point) {
+        self.f32(pt.0);
+        self.f32(pt.1);
+    }
+    fn vector(&mut self, v: Vector) {
+        let (x, y) = v.xy();
+        self.f32(x);
+        self.f32(y);
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct TokenSet(Vec<Token>);
+impl TokenSet {
+    pub fn new() -> Self {
+         TokenSet(vec![])
+    }
+    #[inline]
+    pub fn cardinality(&self) -> usize {
+        self.0.len()
+    }
+    pub fn first(&self) -> Token {
+        *self.0.first()
+        .unwrap_or(&Token::NULL)
+    }
+    pub fn contains(&self, tok: Token) -> bool {
+        self.0.binary_search(&tok).is_ok()
+    }
+    pub fn remove(&mut self, tok: Token) -> Option<Token> {
+        if let Ok(index) = self.0.binary_search(&tok) {
+            Some(self.0.remove(index))
+        } else {
+            None
+        }
+    }
+    pub fn insert(&mut self, tok: Token) -> bool {
+        if tok == Token::NULL { return false }
+        if let Err(index) = self.0.binary_search(&tok) {
+            self.0.insert(index, tok);
+            true
+        } else {
+            false
+        }
+    }
+    pub fn choose<R: Rng>(&self, rng: &mut R) -> Token {
+        if let Some(z) = rng.choose(&self.0) {
+            *z
+        } else {
+            Token::NULL
+        }
+    }
+}
+
+
+// the buffs an entity carries. players and projectiles both have one
+#[derive(Debug, Default)]
+pub struct BuffSet {
+    map: HashMap<(Buff, u8), (u8, f32, f32)>, // by buff and tier: stacks, seconds left, seconds until it next ticks
+}
+
+impl BuffSet {
+    pub fn new() -> BuffSet {
+        BuffSet { map: HashMap::new() }
+    }
+
+    // with no tier given, the stacks of every tier added up
+    pub fn stacks_of(&self, buff: Buff, tier: Option<u8>) -> u32 {
+        match tier {
+            Some(t) => self.map.get(&(buff, t)).map(|x| x.0 as u32).unwrap_or(0),
+            None => self.tiers_of(buff).into_iter().map(|t| self.stacks_of(buff, Some(t))).sum(),
+        }
+    }
+
+    // with no tier given, the longest left of any tier
+    pub fn buff_left(&self, buff: Buff, tier: Option<u8>) -> f32 {
+        match tier {
+            Some(t) => self.map.get(&(buff, t)).map(|x| x.1).unwrap_or(0.),
+            None => self.tiers_of(buff).into_iter().map(|t| self.buff_left(buff, Some(t))).fold(0., f32::max),
+        }
+    }
+
+    // the tiers with stacks, lowest first
+    pub fn tiers_of(&self, buff: Buff) -> Vec<u8> {
+        (1..=MAX_TIER).filter(|&t| self.map.contains_key(&(buff, t))).collect()
+    }
+
+    // returns true iff no stacks are left. runs no events; see Space::remove_buff
+    pub fn forcibly_remove(&mut self, buff: Buff, tier: u8, stacks: u8) -> bool {
+        if stacks == 0 { return self.map.contains_key(&(buff, tier)) }
+        let mut removed_all = false;
+        if let Some(&mut (ref mut s, _, _)) = self.map.get_mut(&(buff, tier)) {
+            if *s <= stacks {
+                removed_all = true;
+            } else { *s -= stacks }
+        } else { return false }
+        if removed_all {
+            self.map.remove(&(buff, tier));
+            true
+        } else { false }
+    }
+
+    // with no tier given, counts the stacks of every tier
+    pub fn has_min_stacks(&self, buff: Buff, tier: Option<u8>, stacks: u32) -> bool {
+        stacks == 0 || self.stacks_of(buff, tier) >= stacks
+    }
+
+    // creates the buff if there are no stacks of it yet. returns the stacks it now has, and the
+    // EXCESS: how many stacks went past max_stacks and were dropped. runs no events; see Space::apply_buff.
+    // `def` is assumed to pass BuffDef::check, as those in a BuffTable do
+    pub fn apply_stacks(&mut self, buff: Buff, tier: u8, stacks: u8, def: &BuffDef) -> (u8, u8) {
+        if stacks == 0 {
+            return (self.stacks_of(buff, Some(tier)) as u8, 0);
+        }
+        let duration = def.duration;
+        let cap = |total: u16| -> (u8, u8) {
+            let max = def.max_stacks as u16;
+            if total > max {
+                (max as u8, (total - max).min(u8::MAX as u16) as u8)
+            } else {
+                (total as u8, 0)
+            }
+        };
+        if let Some(&mut (ref mut old_stacks, ref mut old_duration, _)) = self.map.get_mut(&(buff, tier)) {
+            use buffs::StackingBehaviour::*;
+            let total = *old_stacks as u16 + stacks as u16;
+            let (now, excess) = match def.stacking {
+                Min => {
+                    if duration < *old_duration {
+                        *old_duration = duration;
+                    }
+                    cap(total)
+                },
+                Max => {
+                    if duration > *old_duration {
+                        *old_duration = duration;
+                    }
+                    cap(total)
+                },
+                Replace => {
+                    *old_duration = duration;
+                    cap(stacks as u16)
+                },
+                IfMax => {
+                    if duration >= *old_duration {
+                        cap(total)
+                    } else {
+                        (*old_stacks, 0)
+                    }
+                },
+            };
+            *old_stacks = now;
+            (now, excess)
+        } else {
+            let (now, excess) = cap(stacks as u16);
+            self.map.insert((buff, tier), (now, duration, def.period));
+            (now, excess)
+        }
+    }
+
+    // counts down durations, noting the buffs due to tick and those that expired
+    fn tick(&mut self, tok: Token, table: &BuffTable, moving: bool, ticks: &mut Vec<(Token, Buff, u8, bool)>, expiries: &mut Vec<(Token, Buff, u8)>) {
+        for (&(buff, tier), &mut (_, ref mut left, ref mut next_tick)) in self.map.iter_mut() {
+            if modifiers::ticks_down(buff, moving) {
+                *left -= Space::TICK_PERIOD;
+            }
+            *next_tick -= Space::TICK_PERIOD;
+            let period = table.get(buff).period;
+            if *next_tick <= 0. && period > 0. {
+                *next_tick += period;
+                ticks.push((tok, buff, tier, moving));
+            }
+            if *left <= 0. {
+                expiries.push((tok, buff, tier)); // buff dur falloff
+            }
+        }
+    }
+
+    fn digest(&self, d: &mut Digest) {
+        for &buff in Buff::ALL.iter() {
+            for tier in 1..=MAX_TIER {
+                if let Some(&(stacks, left, next_tick)) = self.map.get(&(buff, tier)) {
+                    d.u64(tier as u64);
+                    d.u64(stacks as u64);
+                    d.f32(left);
+                    d.f32(next_tick);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Player {
+    health: i32,
+    health_max: u32,
+    mana: i32,
+    mana_max: u32,
+    buffs: BuffSet,
+    velocity: Vector,
+    spells: Vec<(Rc<Spell>, Cooldown)>,
+    casting: Option<Casting>,
+    casts: u64, // casts tried so far, for their RNG streams
+}
+
+impl Player {
+    pub fn new(health_max: u32, mana_max: u32) -> Player {
+        Player {
+            health_max,
+            health: health_max as i32,
+            mana_max,
+            mana: mana_max as i32,
+            buffs: BuffSet::new(),
+            velocity: Vector::NULL,
+            spells: Vec::new(),
+            casting: None,
+            casts: 0,
+        }
+    }
+
+    pub fn add_spell(&mut self, spell: Spell) {
+        self.add_spell_with_cooldown(spell, Cooldown::DEFAULT_CAST_COOLDOWN, 1);
+    }
+
+    pub fn add_spell_with_cooldown(&mut self, spell: Spell, cast_cooldown: f32, max_charges: u8) {
+        self.spells.push((Rc::new(spell), Cooldown::new(cast_cooldown, max_charges)));
+    }
+
+    pub fn cooldown(&self, spell_index: usize) -> Option<&Cooldown> {
+        self.spells.get(spell_index).map(|x| &x.1)
+    }
+
+    pub fn casting(&self) -> Option<&Casting> {
+        self.casting.as_ref()
+    }
+
+    #[inline]
+    pub fn health(&self) -> i32 {
+        self.health
+    }
+
+    #[inline]
+    pub fn health_max(&self) -> u32 {
+        self.health_max
+    }
+
+    #[inline]
+    pub fn mana(&self) -> i32 {
+        self.mana
+    }
+
+    #[inline]
+    pub fn mana_max(&self) -> u32 {
+        self.mana_max
+    }
+
+    pub fn buffs(&self) -> &BuffSet {
+        &self.buffs
+    }
+
+    // returns None if unsuccessful, and then removes nothing. Otherwise mana and health are
+    // removed, and the stacks to remove per buff and tier are returned (negative to add), sorted.
+    // the Space applies those, so that the buffs' events run
+    pub fn try_remove_resources(&mut self, r_left: &[ConcreteResource]) -> Option<Vec<(Buff, Option<u8>, i32)>> {
+        let mut total_mana = 0;
+        let mut total_health = 0;
+        let mut total_buffs = HashMap::new();
+        //aggregate needed resources
+        for r in r_left {
+            use self::ConcreteResource::*;
+            match *r {
+                Mana(x) => total_mana = x.saturating_add(total_mana),
+                Health(x) => total_health = x.saturating_add(total_health),
+                BuffStacks(buff, tier, x) => {
+                    *total_buffs.entry((buff, tier)).or_insert(0i32) += x as i32;
+                },
+            };
+        };
+        //if player has aggregated resources
+        if self.mana >= total_mana
+        && self.health >= total_health
+        && total_buffs.iter()
+                .filter(|&(_, v)| *v >= 0)
+                .fold(true, |a, (&(b, t), v)| a && self.buffs.has_min_stacks(b, t, *v as u32)) {
+
+            // a negative cost gives back, but no more than add_resource would
+            self.add_resource(ConcreteResource::Mana(0i32.saturating_sub(total_mana)));
+            self.add_resource(ConcreteResource::Health(0i32.saturating_sub(total_health)));
+            let mut buffs = total_buffs.into_iter().map(|((b, t), v)| (b, t, v)).collect::<Vec<_>>();
+            buffs.sort();
+            Some(buffs)
+        } else { None }
+    }
+
+    // negative amounts remove resources. health and mana remain within [0, max]
+    pub fn add_resource(&mut self, resource: ConcreteResource) {
+        use self::ConcreteResource::*;
+        match resource {
+            Mana(x) => {
+                let mana_max = modifiers::mana_max(&self.buffs, self.mana_max);
+                self.mana = self.mana.saturating_add(x).max(0).min(mana_max as i32);
+            },
+            Health(x) => {
+                self.health = self.health.saturating_add(x).max(0).min(self.health_max as i32);
+            },
+            BuffStacks(..) => {
+                // the Space adds and removes buffs, since they have events to run.
+                // see Space::add_resource_to
+            },
+        }
+    }
+}
+
+// per-player state of one spell slot. see old_src/concept.md
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Cooldown {
+    pub cast_cooldown: f32, // remaining cooldown is set to this when a charge is spent
+    pub remaining: f32,     // counts down while charges < max_charges
+    pub max_charges: u8,
+    pub charges: u8,
+}
+
+impl Cooldown {
+    pub const DEFAULT_CAST_COOLDOWN: f32 = 1.0;
+
+    // a spell always has at least one charge; 0 is taken as 1
+    pub fn new(cast_cooldown: f32, max_charges: u8) -> Cooldown {
+        let max_charges = max_charges.max(1);
+        Cooldown {
+            cast_cooldown,
+            remaining: 0.,
+            max_charges,
+            charges: max_charges,
+        }
+    }
+
+    // returns false if there was no charge to spend
+    pub fn use_charge(&mut self) -> bool {
+        if self.charges == 0 { return false }
+        if self.charges == self.max_charges {
+            self.remaining = self.cast_cooldown;
+        }
+        self.charges -= 1;
+        true
+    }
+
+    // returns true iff a charge was regained
+    pub fn tick(&mut self, period: f32) -> bool {
+        if self.charges >= self.max_charges { return false }
+        self.remaining -= period;
+        if self.remaining > 0. { return false }
+        self.charges += 1;
+        self.remaining = if self.charges < self.max_charges {
+            self.remaining + self.cast_cooldown
+        } else { 0. };
+        true
+    }
+}
+
+// a cast that has started and not yet gone off. see Spell::cast_time
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Casting {
+    pub spell_index: usize,
+    pub cursor: Point,
+    pub sec_left: f32,
+    pub cast: u64, // which of its caster's casts this is, for its RNG stream
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ConcreteResource {
+    Mana(i32),
+    Health(i32),
+    BuffStacks(Buff, Option<u8>, i8), // no tier for magic's AnyTier
+}
+
+
+#[cfg(test)]
+mod tests {
+    use spell_text::Text;
+    use super::*;
+
+    // an instant spell that does `on_cast`
+    fn instant(on_cast: &str) -> Spell {
+        Spell::from_text(&format!("(Spell (on_cast {}) (requires Top) (on_cooldown) (consumes)
+            (cast_time (Const 0)) (on_cast_start) (on_interrupt))", on_cast)).unwrap()
+    }
+
+    // an instant spell that spawns `projectile` where its caster stands
+    fn shoot(projectile: &str) -> Spell {
+        instant(&format!("(SpawnProjectileAt {} (AtEntity (LoadEntity E_0)))", projectile))
+    }
+
+    // the caster's health and mana after casting `on_cast` at full health
+    // and mana, 100 each
+    fn health_and_mana_after(on_cast: &str) -> (i32, i32) {
+        let mut space = Space::new();
+        let mut me = Player::new(100, 100);
+        me.add_spell(instant(on_cast));
+        let me = space.player_enter(Point(0., 0.), me);
+        space.player_cast(me, 0, Point(0., 0.));
+        let p = space.player(me).unwrap();
+        (p.health(), p.mana())
+    }
+
+    #[test]
+    fn add_resource_stays_within_0_and_max() {
+        let add = |res: &str, x: i32| format!("(AddResource (LoadEntity E_0) ({} (Const {})))", res, x);
+        for &res in ["Health", "Mana"].iter() {
+            let after = |xs: &[i32]| {
+                let on_cast: Vec<String> = xs.iter().map(|&x| add(res, x)).collect();
+                let (health, mana) = health_and_mana_after(&on_cast.join(" "));
+                if res == "Health" { health } else { mana }
+            };
+            assert_eq!(after(&[-30]), 70);
+            assert_eq!(after(&[-30, 10]), 80);
+            assert_eq!(after(&[-30, 50]), 100);
+            assert_eq!(after(&[-130]), 0);
+            assert_eq!(after(&[-130, 10]), 10);
+            assert_eq!(after(&[-30, i32::MAX]), 100);
+            assert_eq!(after(&[i32::MAX, i32::MAX]), 100);
+            assert_eq!(after(&[i32::MIN]), 0);
+            assert_eq!(after(&[-30, i32::MIN, i32::MIN]), 0);
+        }
+    }
+
+    #[test]
+    fn removed_resources_stay_within_0_and_max() {
+        use self::ConcreteResource::*;
+        let after = |cost: &[ConcreteResource]| {
+            let mut p = Player::new(100, 100);
+            let paid = p.try_remove_resources(cost).is_some();
+            (paid, p.health(), p.mana())
+        };
+        assert_eq!(after(&[Mana(30), Health(20)]), (true, 80, 70));
+        assert_eq!(after(&[Mana(101)]), (false, 100, 100));
+        assert_eq!(after(&[Mana(-500)]), (true, 100, 100));
+        assert_eq!(after(&[Mana(30), Mana(-10)]), (true, 100, 80));
+        assert_eq!(after(&[Mana(i32::MIN), Health(i32::MIN)]), (true, 100, 100));
+        assert_eq!(after(&[Mana(i32::MAX), Mana(i32::MAX)]), (false, 100, 100));
+        assert_eq!(after(&[Mana(i32::MAX), Mana(i32::MIN), Mana(50)]), (true, 100, 51));
+    }
+
+    // where the caster ends up, and its health and mana, after an instant
+    // spell that spends 10 mana, runs `failing` and then spends 5 more.
+    // `before` runs first. an enemy stands by in E_1
+    fn after_failing(recovery: Recovery, before: &str, failing: &str) -> (Point, i32, i32) {
+        let mut space = Space::new();
+        space.set_recovery(recovery);
+        let mut me = Player::new(100, 100);
+        me.add_spell(instant(&format!("(Define (E_1 (FirstOf (EnemiesOf (LoadEntity E_0))))) {}
+            (AddResource (LoadEntity E_0) (Mana (Const -10)))
+            {}
+            (AddResource (LoadEntity E_0) (Mana (Const -5)))", before, failing)));
+        let me = space.player_enter(Point(50., 50.), me);
+        space.player_enter(Point(100., 0.), Player::new(100, 100));
+        space.player_cast(me, 0, Point(0., 0.));
+        let p = space.player(me).unwrap();
+        (space.pt_of_player(me).unwrap(), p.health(), p.mana())
+    }
+
+    #[test]
+    fn each_recovery_does_what_it_says() {
+        let to_origin = (Point(0., 0.), 100);
+        let cases = [
+            ("", "(MoveEntity (LoadEntity E_0) (LoadLocation L_3))", to_origin),
+            ("", "(MoveEntity (LoadEntity E_0) (AtEntity (FirstOf Empty)))", to_origin),
+            ("(Destroy (LoadEntity E_1))", "(MoveEntity (LoadEntity E_0) (AtEntity (LoadEntity E_1)))", to_origin),
+            ("", "(AddResource (LoadEntity E_0) (Health (Sum (Const -20) (Div (Const 7) (Const 0)))))", (Point(50., 50.), 80)),
+        ];
+        for &(before, failing, (pt, health)) in cases.iter() {
+            assert_eq!(after_failing(Recovery::AbortEvent, before, failing), (Point(50., 50.), 100, 90));
+            assert_eq!(after_failing(Recovery::SkipInstruction, before, failing), (Point(50., 50.), 100, 85));
+            assert_eq!(after_failing(Recovery::SubstituteDefault, before, failing), (pt, health, 85));
+        }
+    }
+
+    #[test]
+    fn wide_ranges_dont_overflow() {
+        for seed in 0..10 {
+            let mut space = Space::with_seed(seed);
+            let mut me = Player::new(100, 100);
+            me.add_spell(instant("(AddResource (LoadEntity E_0) (Health (Range -2000000000 2000000000)))"));
+            let me = space.player_enter(Point(0., 0.), me);
+            space.player_cast(me, 0, Point(0., 0.));
+            let health = space.player(me).unwrap().health();
+            assert!(health == 0 || health == 100);
+        }
+    }
+
+    const HURT_TARGET: &str = "(Spell
+        (on_cast
+            (SpawnProjectileAt
+                (Projectile (on_create)
+                    (on_collision (AddResource (LoadEntity E_2) (Health (Neg (Range 1 1000)))) (Destroy (LoadEntity E_1)))
+                    (collides_with (EnemiesOf (LoadEntity E_0)))
+                    (on_destroy)
+                    (lifetime (Const 5)))
+                (AtEntity (LoadEntity E_0))))
+        (requires Top)
+        (on_cooldown (AddResource (LoadEntity E_0) (Health (Neg (Range 1 1000)))))
+        (consumes) (cast_time (Const 0)) (on_cast_start) (on_interrupt))";
+
+    // draws when cast, and spawns a projectile that draws when made
+    const DRAW: &str = "(Spell
+        (on_cast
+            (AddResource (LoadEntity E_0) (Mana (Range 0 2)))
+            (SpawnProjectileAt
+                (Projectile (on_create (AddResource (LoadEntity E_0) (Mana (Range 0 2))))
+                    (on_collision) (collides_with Empty) (on_destroy) (lifetime (Const 1)))
+                (AtEntity (LoadEntity E_0))))
+        (requires Top) (on_cooldown) (consumes) (cast_time (Const 0)) (on_cast_start) (on_interrupt))";
+
+    // the caster's and target's health after the caster casts HURT_TARGET,
+    // with or without a bystander casting DRAW first
+    fn duel(seed: u64, draw_first: bool) -> (i32, i32) {
+        let mut space = Space::with_seed(seed);
+        let mut caster = Player::new(100000, 100);
+        caster.add_spell(Spell::from_text(HURT_TARGET).unwrap());
+        let caster = space.player_enter(Point(0., 0.), caster);
+        // projectiles start out where their caster is, so the target is in reach
+        let target = space.player_enter(Point(10., 0.), Player::new(100000, 100));
+        let mut bystander = Player::new(100, 100);
+        bystander.add_spell(Spell::from_text(DRAW).unwrap());
+        let bystander = space.player_enter(Point(500., 500.), bystander);
+        if draw_first {
+            space.player_cast(bystander, 0, Point(0., 0.));
+            assert_eq!(space.projectiles().len(), 1);
+        }
+        space.player_cast(caster, 0, Point(10., 0.));
+        for _ in 0..3 * UPDATES_PER_SEC {
+            space.tick();
+        }
+        (space.health_of(caster).unwrap(), space.health_of(target).unwrap())
+    }
+
+    #[test]
+    fn another_cast_doesnt_shift_the_draws() {
+        let mut seen = vec![];
+        for seed in 0..10 {
+            let (caster, target) = duel(seed, false);
+            assert!(caster < 100000 && target < 100000);
+            assert_eq!(duel(seed, true), (caster, target));
+            seen.push((caster, target));
+        }
+        // while different seeds draw differently
+        seen.sort();
+        seen.dedup();
+        assert!(seen.len() > 5);
+    }
+
+    #[test]
+    fn on_collision_fires_once_per_contact() {
+        let mut space = Space::new();
+        let mut caster = Player::new(100, 100);
+        caster.add_spell(shoot("(Projectile (on_create)
+            (on_collision (AddResource (LoadEntity E_2) (Health (Const -1))))
+            (collides_with (EnemiesOf (LoadEntity E_0))) (on_destroy) (lifetime (Const 10)))"));
+        let caster = space.player_enter(Point(0., 0.), caster);
+        let target = space.player_enter(Point(10., 0.), Player::new(100, 100));
+        space.player_cast(caster, 0, Point(0., 0.));
+        for _ in 0..UPDATES_PER_SEC {
+            space.tick();
+        }
+        assert_eq!(space.health_of(target), Some(99));
+        // stepping out and back in is a new contact
+        space.move_to(target, Point(100., 0.));
+        space.tick();
+        space.move_to(target, Point(10., 0.));
+        space.tick();
+        assert_eq!((space.health_of(caster), space.health_of(target)), (Some(100), Some(98)));
+    }
+
+    // the caster's health, and how many tokens and projectile tokens are left,
+    // a second after it shoots a projectile that hurts it on_destroy
+    fn after_destroying(on_create: &str, on_destroy: &str, lifetime: i32) -> (i32, usize, usize) {
+        let mut space = Space::new();
+        let mut caster = Player::new(100, 100);
+        caster.add_spell(shoot(&format!("(Projectile (on_create {}) (on_collision) (collides_with Empty)
+            (on_destroy (AddResource (LoadEntity E_0) (Health (Const -1))) {}) (lifetime (Const {})))",
+            on_create, on_destroy, lifetime)));
+        let caster = space.player_enter(Point(0., 0.), caster);
+        space.player_cast(caster, 0, Point(0., 0.));
+        for _ in 0..UPDATES_PER_SEC {
+            space.tick();
+        }
+        assert_eq!(space.token_projectiles.cardinality(), space.projectiles.len());
+        (space.health_of(caster).unwrap(), space.token_universe.cardinality(), space.token_projectiles.cardinality())
+    }
+
+    #[test]
+    fn on_destroy_runs_unless_destroyed_without_event() {
+        // still flying
+        assert_eq!(after_destroying("", "", 5), (100, 2, 1));
+        // expired
+        assert_eq!(after_destroying("", "", 0), (99, 1, 0));
+        assert_eq!(after_destroying("(Destroy (LoadEntity E_1))", "", 5), (99, 1, 0));
+        assert_eq!(after_destroying("(DestroyWithoutEvent (LoadEntity E_1))", "", 5), (100, 1, 0));
+        // on_destroy runs once, even when it destroys the projectile again
+        assert_eq!(after_destroying("(Destroy (LoadEntity E_1))", "(Destroy (LoadEntity E_1))", 5), (99, 1, 0));
+    }
+
+    #[test]
+    fn charges_come_back_one_by_one_and_run_on_cooldown() {
+        let mut space = Space::new();
+        let mut me = Player::new(100, 100);
+        me.add_spell_with_cooldown(Spell::from_text("(Spell
+            (on_cast (AddResource (LoadEntity E_0) (Mana (Const -10))))
+            (requires Top)
+            (on_cooldown (AddResource (LoadEntity E_0) (Health (Const -1))))
+            (consumes) (cast_time (Const 0)) (on_cast_start) (on_interrupt))").unwrap(), 1.0, 2);
+        let me = space.player_enter(Point(0., 0.), me);
+        for _ in 0..3 {
+            space.player_cast(me, 0, Point(0., 0.));
+        }
+        let state = |space: &Space| {
+            let p = space.player(me).unwrap();
+            (p.cooldown(0).unwrap().charges, p.health(), p.mana())
+        };
+        // the third cast had no charge left
+        assert_eq!(state(&space), (0, 100, 80));
+        let mut after_half_seconds = vec![];
+        for _ in 0..8 {
+            for _ in 0..UPDATES_PER_SEC / 2 {
+                space.tick();
+            }
+            after_half_seconds.push(state(&space));
+        }
+        assert_eq!(after_half_seconds, vec![
+            (0, 100, 80),
+            (1, 99, 80),
+            (1, 99, 80),
+            (2, 98, 80),
+            (2, 98, 80),
+            (2, 98, 80),
+            (2, 98, 80),
+            (2, 98, 80),
+        ]);
+        space.player_cast(me, 0, Point(0., 0.));
+        assert_eq!(state(&space), (1, 98, 70));
+    }
+
+    #[test]
+    fn tiers_stack_apart_and_any_tier_adds_them_up() {
+        let dizzy = |tier: &str, x: i32| format!("(AddResource (LoadEntity E_0) (BuffStacks Dizzy {} (Const {})))", tier, x);
+        let mut space = Space::new();
+        let mut me = Player::new(100, 100);
+        me.add_spell(instant(&[
+            dizzy("(Tier (Const 1))", 2),
+            dizzy("(Tier (Const 3))", 1),
+            "(AddResource (LoadEntity E_0) (Mana (Neg (CountStacks Dizzy AnyTier (LoadEntity E_0)))))".to_string(),
+            "(AddResource (LoadEntity E_0) (Health (Neg (CountStacks Dizzy (Tier (Const 3)) (LoadEntity E_0)))))".to_string(),
+        ].join(" ")));
+        // AnyTier adds to tier 1, and removes from the lowest tier first
+        me.add_spell(instant(&dizzy("AnyTier", 1)));
+        me.add_spell(instant(&dizzy("AnyTier", -3)));
+        let me = space.player_enter(Point(0., 0.), me);
+        let stacks = |space: &Space| {
+            let buffs = space.buffs_of(me).unwrap();
+            (buffs.stacks_of(Buff::Dizzy, Some(1)), buffs.stacks_of(Buff::Dizzy, Some(3)), buffs.stacks_of(Buff::Dizzy, None))
+        };
+        space.player_cast(me, 0, Point(0., 0.));
+        assert_eq!((space.health_of(me), space.player(me).map(|p| p.mana())), (Some(99), Some(97)));
+        assert_eq!(stacks(&space), (2, 1, 3));
+        assert_eq!(space.buffs_of(me).unwrap().tiers_of(Buff::Dizzy), vec![1, 3]);
+        space.player_cast(me, 1, Point(0., 0.));
+        assert_eq!(stacks(&space), (3, 1, 4));
+        space.player_cast(me, 2, Point(0., 0.));
+        assert_eq!(stacks(&space), (0, 1, 1));
+        assert_eq!(space.buffs_of(me).unwrap().tiers_of(Buff::Dizzy), vec![3]);
+    }
+
+    #[test]
+    fn projectiles_carry_buffs_and_can_be_shot_down() {
+        let projectile = |on_create: &str| format!("(Projectile (on_create {}) (on_collision) (collides_with Empty)
+            (on_destroy (AddResource (LoadEntity E_0) (Health (Const -1)))) (lifetime (Const 10)))", on_create);
+        let mut space = Space::new();
+        let mut caster = Player::new(100, 100);
+        caster.add_spell(shoot(&projectile("(AddResource (LoadEntity E_1) (Health (Const 10)))
+            (AddResource (LoadEntity E_1) (BuffStacks Dizzy (Tier (Const 2)) (Const 1)))")));
+        caster.add_spell(shoot(&projectile("")));
+        caster.add_spell_with_cooldown(instant("(ForEachAs E_3 IsProjectile (AddResource (LoadEntity E_3) (Health (Const -4))))"), 0., 3);
+        let caster = space.player_enter(Point(0., 0.), caster);
+        space.player_cast(caster, 0, Point(0., 0.));
+        space.player_cast(caster, 1, Point(0., 0.));
+        let (tough, plain) = {
+            let mut projectiles = space.projectiles().map(|(tok, x)| (x.1.health(), *tok)).collect::<Vec<_>>();
+            projectiles.sort();
+            assert_eq!(projectiles.iter().map(|x| x.0).collect::<Vec<_>>(), vec![None, Some(10)]);
+            (projectiles[1].1, projectiles[0].1)
+        };
+        let stacks = space.buffs_of(tough).map(|b| (b.stacks_of(Buff::Dizzy, Some(2)), b.stacks_of(Buff::Dizzy, None)));
+        assert_eq!(stacks, Some((1, 1)));
+        space.player_cast(caster, 2, Point(0., 0.));
+        space.player_cast(caster, 2, Point(0., 0.));
+        assert_eq!((space.health_of(tough), space.health_of(plain)), (Some(2), None));
+        space.player_cast(caster, 2, Point(0., 0.));
+        // shot down, and its on_destroy ran. the one without health can't be
+        assert!(!space.is_projectile(tough) && space.is_projectile(plain));
+        assert_eq!(space.health_of(caster), Some(99));
+        assert_eq!(space.token_projectiles.cardinality(), 1);
+    }
+
+    #[test]
+    fn mute_stops_casts_until_it_wears_off() {
+        let mut space = Space::new();
+        let mut me = Player::new(100, 100);
+        me.add_spell(instant("(AddResource (LoadEntity E_0) (Mana (Const -10)))"));
+        let me = space.player_enter(Point(0., 0.), me);
+        let mana_and_charges = |space: &Space| space.player(me).map(|p| (p.mana(), p.cooldown(0).unwrap().charges));
+        space.apply_buff(me, Buff::Mute, 1, 1);
+        space.player_cast(me, 0, Point(0., 0.));
+        assert_eq!(mana_and_charges(&space), Some((100, 1)));
+        // Mute lasts 3 seconds
+        for _ in 0..4 * UPDATES_PER_SEC {
+            space.tick();
+        }
+        space.player_cast(me, 0, Point(0., 0.));
+        assert_eq!(mana_and_charges(&space), Some((90, 0)));
+    }
+
+    // how fast a player with `buffs` goes after walking, or being pushed, at 10
+    fn speed_after(buffs: &[(Buff, u8, u8)], pushed: bool) -> f32 {
+        let mut space = Space::new();
+        let me = space.player_enter(Point(0., 0.), Player::new(100, 100));
+        for &(buff, tier, stacks) in buffs {
+            space.apply_buff(me, buff, tier, stacks);
+        }
+        if pushed {
+            space.add_velocity_to(me, Vector::new_from_directional(0., 10.));
+        } else {
+            space.walk(me, 0., 10.);
+        }
+        (space.players[&me].1.velocity.speed() * 1000.).round() / 1000.
+    }
+
+    #[test]
+    fn chilled_slows_walking_and_resolute_dampens_forces() {
+        use buffs::Buff::*;
+        assert_eq!(speed_after(&[], false), 10.);
+        assert_eq!(speed_after(&[(Chilled, 1, 2)], false), 9.);
+        assert_eq!(speed_after(&[(Chilled, 2, 1)], false), 9.);
+        assert_eq!(speed_after(&[(Chilled, 1, 2)], true), 10.);
+        // Resolute shrugs off Tired, but not Chilled
+        assert_eq!(speed_after(&[(Tired, 1, 2)], false), 9.);
+        assert_eq!(speed_after(&[(Tired, 1, 2), (Resolute, 1, 1)], false), 10.);
+        assert_eq!(speed_after(&[(Chilled, 1, 2), (Resolute, 1, 1)], false), 9.);
+        assert_eq!(speed_after(&[], true), 10.);
+        assert_eq!(speed_after(&[(Resolute, 1, 1)], true), 8.);
+        assert_eq!(speed_after(&[(Resolute, 2, 2)], true), 5.);
+    }
+
+    // the caster's health and mana, and whether it is still casting, each
+    // half second after it starts a one second cast. `meddle` runs once the
+    // cast has started
+    fn casting_for_a_second<F: Fn(&mut Space, Token)>(meddle: F) -> Vec<(i32, i32, bool)> {
+        let mut space = Space::new();
+        let mut me = Player::new(100, 100);
+        me.add_spell(Spell::from_text("(Spell
+            (on_cast (AddResource (LoadEntity E_0) (Mana (Const -10))))
+            (requires Top) (on_cooldown) (consumes) (cast_time (Const 1))
+            (on_cast_start (AddResource (LoadEntity E_0) (Mana (Const -1))))
+            (on_interrupt (AddResource (LoadEntity E_0) (Health (Const -5)))))").unwrap());
+        let me = space.player_enter(Point(0., 0.), me);
+        space.player_cast(me, 0, Point(0., 0.));
+        meddle(&mut space, me);
+        let mut states = vec![];
+        for _ in 0..4 {
+            for _ in 0..UPDATES_PER_SEC / 2 {
+                space.tick();
+            }
+            let p = space.player(me).unwrap();
+            states.push((p.health(), p.mana(), p.casting().is_some()));
+        }
+        states
+    }
+
+    #[test]
+    fn casts_go_off_after_their_cast_time_unless_interrupted() {
+        assert_eq!(casting_for_a_second(|_, _| ()), vec![
+            (100, 99, true),
+            (100, 89, false),
+            (100, 89, false),
+            (100, 89, false),
+        ]);
+        let interrupted = vec![
+            (95, 99, false),
+            (95, 99, false),
+            (95, 99, false),
+            (95, 99, false),
+        ];
+        assert_eq!(casting_for_a_second(|space, me| { space.walk(me, 0., 5.); }), interrupted);
+        assert_eq!(casting_for_a_second(|space, me| { space.apply_buff(me, Buff::Shivering, 1, 5); }), interrupted);
+        // a push moves the caster too
+        assert_eq!(casting_for_a_second(|space, me| { space.add_velocity_to(me, Vector::new_from_directional(0., 5.)); }), interrupted);
+    }
+}
@@ -0,0 +1,767 @@
+use std::fmt;
+use std::error::Error;
+use std::rc::Rc;
+use std::collections::HashMap;
+use magic::*;
+use buffs::*;
+
+// The compact binary form of magic, for the wire and for big spellbooks.
+//
+//   bytes   := "SPB" VERSION value
+//   enum    := tag:u8 field*           (tags follow declaration order in magic.rs)
+//   Vec     := len:varint elem*
+//   i32     := zigzag varint
+//   u8/slot := u8
+//   F32     := 4 bytes, little endian bits
+//   Buff    := u8 index into Buff::ALL
+//   Rc<ProjectileBlueprint> := 0 blueprint | 1 index:varint
+//
+// Every blueprint written out in full is numbered in the order it finishes
+// encoding, and any later Rc pointing to the same blueprint is written as a
+// reference to that number. Decoding restores the sharing.
+//
+// Decoding must never panic: the bytes may come from an untrusted peer.
+// Lengths are checked against the input left before anything is allocated,
+// and nesting is limited to MAX_DEPTH. A reference costs as many nodes as the
+// blueprint it points to, and a value may expand to at most MAX_NODES, so a
+// chain of blueprints that each use the last one twice can't blow up.
+
+const HEADER: &[u8] = b"SPB";
+pub const VERSION: u8 = 3; // 2: buff tiers, 3: cast times
+const MAX_DEPTH: u16 = 256;
+const MAX_LEN: u64 = 4096;
+const MAX_NODES: u64 = 1 << 20;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    BadHeader,
+    UnsupportedVersion(u8),
+    BadTag(&'static str, u8),
+    BadVarint,
+    TooLong(u64),
+    TooDeep,
+    TooBig,
+    BadBlueprintRef(u64),
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DecodeError::*;
+        match *self {
+            UnexpectedEnd => write!(f, "input ends too early"),
+            BadHeader => write!(f, "input is not an encoded spell"),
+            UnsupportedVersion(v) => write!(f, "unsupported version {} (expected {})", v, VERSION),
+            BadTag(what, tag) => write!(f, "{} is not a valid tag for {}", tag, what),
+            BadVarint => write!(f, "malformed integer"),
+            TooLong(len) => write!(f, "length {} is too long", len),
+            TooDeep => write!(f, "nested deeper than {}", MAX_DEPTH),
+            TooBig => write!(f, "expands to more than {} nodes", MAX_NODES),
+            BadBlueprintRef(i) => write!(f, "reference to unknown blueprint {}", i),
+            TrailingBytes(n) => write!(f, "{} unexpected bytes after the end", n),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+pub trait Binary: Sized {
+    fn encode(&self, e: &mut Encoder);
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError>;
+}
+
+pub fn to_bytes<T: Binary>(x: &T) -> Vec<u8> {
+    let mut out = HEADER.to_vec();
+    out.push(VERSION);
+    out.extend(value_to_bytes(x));
+    out
+}
+
+// just the value, without the header and version
+pub fn value_to_bytes<T: Binary>(x: &T) -> Vec<u8> {
+    let mut e = Encoder { out: vec![], blueprints: HashMap::new() };
+    x.encode(&mut e);
+    e.out
+}
+
+pub fn from_bytes<T: Binary>(bytes: &[u8]) -> Result<T, DecodeError> {
+    if bytes.len() < HEADER.len() + 1 || &bytes[..HEADER.len()] != HEADER {
+        return Err(DecodeError::BadHeader);
+    }
+    let version = bytes[HEADER.len()];
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let mut d = Decoder {
+        bytes: &bytes[HEADER.len() + 1..],
+        depth: 0,
+        nodes: 0,
+        blueprints: vec![],
+    };
+    let x = T::decode(&mut d)?;
+    if !d.bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes(d.bytes.len()));
+    }
+    Ok(x)
+}
+
+pub struct Encoder {
+    out: Vec<u8>,
+    blueprints: HashMap<*const ProjectileBlueprint, u64>,
+}
+
+impl Encoder {
+    fn byte(&mut self, b: u8) {
+        self.out.push(b);
+    }
+
+    fn varint(&mut self, mut x: u64) {
+        while x >= 0x80 {
+            self.out.push((x as u8) | 0x80);
+            x >>= 7;
+        }
+        self.out.push(x as u8);
+    }
+
+    fn vec<T: Binary>(&mut self, v: &[T]) {
+        self.varint(v.len() as u64);
+        for x in v {
+            x.encode(self);
+        }
+    }
+}
+
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    depth: u16,
+    nodes: u64, // decoded so far, counting each reference at its blueprint's size
+    blueprints: Vec<(Rc<ProjectileBlueprint>, u64)>,
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        match self.bytes.split_first() {
+            Some((&b, rest)) => {
+                self.bytes = rest;
+                Ok(b)
+            },
+            None => Err(DecodeError::UnexpectedEnd),
+        }
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut x: u64 = 0;
+        for shift in (0..10).map(|i| i * 7) {
+            let b = self.byte()?;
+            let bits = (b & 0x7f) as u64;
+            if shift == 63 && bits > 1 {
+                return Err(DecodeError::BadVarint);
+            }
+            x |= bits << shift;
+            if b & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(DecodeError::BadVarint)
+    }
+
+    // every recursive type goes through a Box, Vec or Rc, so these are
+    // the only places that need to count depth
+    fn nested<T, F>(&mut self, f: F) -> Result<T, DecodeError>
+    where F: FnOnce(&mut Self) -> Result<T, DecodeError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(DecodeError::TooDeep);
+        }
+        self.depth += 1;
+        let x = f(self);
+        self.depth -= 1;
+        x
+    }
+
+    fn vec<T: Binary>(&mut self) -> Result<Vec<T>, DecodeError> {
+        let len = self.varint()?;
+        // every element takes at least one byte
+        if len > MAX_LEN || len > self.bytes.len() as u64 {
+            return Err(DecodeError::TooLong(len));
+        }
+        self.nested(|d| (0..len).map(|_| T::decode(d)).collect())
+    }
+
+    fn boxed<T: Binary>(&mut self) -> Result<Box<T>, DecodeError> {
+        self.nested(|d| T::decode(d).map(Box::new))
+    }
+
+    fn spend(&mut self, nodes: u64) -> Result<(), DecodeError> {
+        self.nodes = self.nodes.saturating_add(nodes);
+        if self.nodes > MAX_NODES {
+            return Err(DecodeError::TooBig);
+        }
+        Ok(())
+    }
+
+    // every enum starts with a tag, so tags count the nodes
+    fn tag(&mut self, what: &'static str, n: u8) -> Result<u8, DecodeError> {
+        self.spend(1)?;
+        let t = self.byte()?;
+        if t < n { Ok(t) } else { Err(DecodeError::BadTag(what, t)) }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////
+
+impl Binary for u8 {
+    fn encode(&self, e: &mut Encoder) { e.byte(*self) }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> { d.byte() }
+}
+
+impl Binary for i32 {
+    fn encode(&self, e: &mut Encoder) {
+        e.varint((((*self << 1) ^ (*self >> 31)) as u32) as u64)
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        let x = d.varint()?;
+        if x > u32::MAX as u64 {
+            return Err(DecodeError::BadVarint);
+        }
+        let x = x as u32;
+        Ok(((x >> 1) as i32) ^ -((x & 1) as i32))
+    }
+}
+
+impl Binary for F32 {
+    fn encode(&self, e: &mut Encoder) {
+        let bits = self.0.to_bits();
+        for i in 0..4 {
+            e.byte((bits >> (8 * i)) as u8);
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        let mut bits: u32 = 0;
+        for i in 0..4 {
+            bits |= (d.byte()? as u32) << (8 * i);
+        }
+        Ok(F32(f32::from_bits(bits)))
+    }
+}
+
+impl Binary for ESlot {
+    fn encode(&self, e: &mut Encoder) { e.byte(self.0) }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> { d.byte().map(ESlot) }
+}
+
+impl Binary for ESetSlot {
+    fn encode(&self, e: &mut Encoder) { e.byte(self.0) }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> { d.byte().map(ESetSlot) }
+}
+
+impl Binary for DSlot {
+    fn encode(&self, e: &mut Encoder) { e.byte(self.0) }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> { d.byte().map(DSlot) }
+}
+
+impl Binary for LSlot {
+    fn encode(&self, e: &mut Encoder) { e.byte(self.0) }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> { d.byte().map(LSlot) }
+}
+
+impl Binary for Buff {
+    fn encode(&self, e: &mut Encoder) {
+        let i = Buff::ALL.iter().position(|b| b == self).expect("every buff is in Buff::ALL");
+        e.byte(i as u8)
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        let t = d.tag("Buff", Buff::ALL.len() as u8)?;
+        Ok(Buff::ALL[t as usize])
+    }
+}
+
+impl<T: Binary> Binary for Vec<T> {
+    fn encode(&self, e: &mut Encoder) { e.vec(self) }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> { d.vec() }
+}
+
+impl Binary for Rc<ProjectileBlueprint> {
+    fn encode(&self, e: &mut Encoder) {
+        let key = &**self as *const ProjectileBlueprint;
+        if let Some(&i) = e.blueprints.get(&key) {
+            e.byte(1);
+            e.varint(i);
+        } else {
+            e.byte(0);
+            (**self).encode(e);
+            let i = e.blueprints.len() as u64;
+            e.blueprints.insert(key, i);
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        match d.tag("Rc<ProjectileBlueprint>", 2)? {
+            0 => {
+                let before = d.nodes;
+                let bp = Rc::new(d.nested(ProjectileBlueprint::decode)?);
+                d.blueprints.push((bp.clone(), d.nodes - before));
+                Ok(bp)
+            },
+            _ => {
+                let i = d.varint()?;
+                let (bp, nodes) = d.blueprints.get(i as usize)
+                    .cloned()
+                    .ok_or(DecodeError::BadBlueprintRef(i))?;
+                d.spend(nodes)?;
+                Ok(bp)
+            },
+        }
+    }
+}
+
+impl Binary for Spell {
+    fn encode(&self, e: &mut Encoder) {
+        e.vec(&self.on_cast);
+        self.requires.encode(e);
+        e.vec(&self.on_cooldown);
+        e.vec(&self.consumes);
+        self.cast_time.encode(e);
+        e.vec(&self.on_cast_start);
+        e.vec(&self.on_interrupt);
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Spell {
+            on_cast: d.vec()?,
+            requires: d.boxed()?,
+            on_cooldown: d.vec()?,
+            consumes: d.vec()?,
+            cast_time: Discrete::decode(d)?,
+            on_cast_start: d.vec()?,
+            on_interrupt: d.vec()?,
+        })
+    }
+}
+
+impl Binary for ProjectileBlueprint {
+    fn encode(&self, e: &mut Encoder) {
+        e.vec(&self.on_create);
+        e.vec(&self.on_collision);
+        self.collides_with.encode(e);
+        e.vec(&self.on_destroy);
+        self.lifetime.encode(e);
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(ProjectileBlueprint {
+            on_create: d.vec()?,
+            on_collision: d.vec()?,
+            collides_with: EntitySet::decode(d)?,
+            on_destroy: d.vec()?,
+            lifetime: Discrete::decode(d)?,
+        })
+    }
+}
+
+impl<T: Binary> Binary for Box<T> {
+    fn encode(&self, e: &mut Encoder) { (**self).encode(e) }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> { d.boxed() }
+}
+
+impl Binary for Instruction {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::Instruction::*;
+        match *self {
+            Define(ref x) => { e.byte(0); x.encode(e); },
+            ITE(ref c, ref t, ref f) => { e.byte(1); c.encode(e); e.vec(t); e.vec(f); },
+            CallWith(ref x, ref v) => { e.byte(2); x.encode(e); e.vec(v); },
+            ForEachAs(s, ref set, ref v) => { e.byte(3); s.encode(e); set.encode(e); e.vec(v); },
+            DestroyWithoutEvent(ref x) => { e.byte(4); x.encode(e); },
+            Destroy(ref x) => { e.byte(5); x.encode(e); },
+            MoveEntity(ref x, ref l) => { e.byte(6); x.encode(e); l.encode(e); },
+            AddResource(ref x, ref r) => { e.byte(7); x.encode(e); r.encode(e); },
+            AddVelocity(ref x, ref dir, ref s) => { e.byte(8); x.encode(e); dir.encode(e); s.encode(e); },
+            SpawnProjectileAt(ref bp, ref l) => { e.byte(9); bp.encode(e); l.encode(e); },
+            Nothing => e.byte(10),
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::Instruction::*;
+        Ok(match d.tag("Instruction", 11)? {
+            0 => Define(Definition::decode(d)?),
+            1 => ITE(Condition::decode(d)?, d.vec()?, d.vec()?),
+            2 => CallWith(Definition::decode(d)?, d.vec()?),
+            3 => ForEachAs(ESlot::decode(d)?, EntitySet::decode(d)?, d.vec()?),
+            4 => DestroyWithoutEvent(Entity::decode(d)?),
+            5 => Destroy(Entity::decode(d)?),
+            6 => MoveEntity(Entity::decode(d)?, Location::decode(d)?),
+            7 => AddResource(Entity::decode(d)?, Resource::decode(d)?),
+            8 => AddVelocity(Entity::decode(d)?, Direction::decode(d)?, Discrete::decode(d)?),
+            9 => SpawnProjectileAt(Rc::decode(d)?, Location::decode(d)?),
+            _ => Nothing,
+        })
+    }
+}
+
+impl Binary for Definition {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::Definition::*;
+        match *self {
+            ESet(s, ref x) => { e.byte(0); s.encode(e); x.encode(e); },
+            E(s, ref x) => { e.byte(1); s.encode(e); x.encode(e); },
+            D(s, ref x) => { e.byte(2); s.encode(e); x.encode(e); },
+            L(s, ref x) => { e.byte(3); s.encode(e); x.encode(e); },
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::Definition::*;
+        Ok(match d.tag("Definition", 4)? {
+            0 => ESet(ESetSlot::decode(d)?, EntitySet::decode(d)?),
+            1 => E(ESlot::decode(d)?, Entity::decode(d)?),
+            2 => D(DSlot::decode(d)?, Discrete::decode(d)?),
+            _ => L(LSlot::decode(d)?, Location::decode(d)?),
+        })
+    }
+}
+
+impl Binary for Direction {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::Direction::*;
+        match *self {
+            TowardLocation(ref a, ref b) => { e.byte(0); a.encode(e); b.encode(e); },
+            ConstRad(f) => { e.byte(1); f.encode(e); },
+            BetweenRad(a, b) => { e.byte(2); a.encode(e); b.encode(e); },
+            Choose(ref v) => { e.byte(3); e.vec(v); },
+            ChooseWithinRadOf(ref x, f) => { e.byte(4); x.encode(e); f.encode(e); },
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::Direction::*;
+        Ok(match d.tag("Direction", 5)? {
+            0 => TowardLocation(Location::decode(d)?, Location::decode(d)?),
+            1 => ConstRad(F32::decode(d)?),
+            2 => BetweenRad(F32::decode(d)?, F32::decode(d)?),
+            3 => Choose(d.vec()?),
+            _ => ChooseWithinRadOf(d.boxed()?, F32::decode(d)?),
+        })
+    }
+}
+
+impl Binary for Location {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::Location::*;
+        match *self {
+            AtEntity(ref x) => { e.byte(0); x.encode(e); },
+            Midpoint(ref v) => { e.byte(1); e.vec(v); },
+            Choose(ref v) => { e.byte(2); e.vec(v); },
+            LoadLocation(s) => { e.byte(3); s.encode(e); },
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::Location::*;
+        Ok(match d.tag("Location", 4)? {
+            0 => AtEntity(Entity::decode(d)?),
+            1 => Midpoint(d.vec()?),
+            2 => Choose(d.vec()?),
+            _ => LoadLocation(LSlot::decode(d)?),
+        })
+    }
+}
+
+impl Binary for Condition {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::Condition::*;
+        match *self {
+            Nand(ref v) => { e.byte(0); e.vec(v); },
+            And(ref v) => { e.byte(1); e.vec(v); },
+            Or(ref v) => { e.byte(2); e.vec(v); },
+            Top => e.byte(3),
+            Bottom => e.byte(4),
+            Equals(ref a, ref b) => { e.byte(5); a.encode(e); b.encode(e); },
+            LessThan(ref a, ref b) => { e.byte(6); a.encode(e); b.encode(e); },
+            MoreThan(ref a, ref b) => { e.byte(7); a.encode(e); b.encode(e); },
+            EntitySetCmp(ref x) => { e.byte(8); x.encode(e); },
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::Condition::*;
+        Ok(match d.tag("Condition", 9)? {
+            0 => Nand(d.vec()?),
+            1 => And(d.vec()?),
+            2 => Or(d.vec()?),
+            3 => Top,
+            4 => Bottom,
+            5 => Equals(Discrete::decode(d)?, Discrete::decode(d)?),
+            6 => LessThan(Discrete::decode(d)?, Discrete::decode(d)?),
+            7 => MoreThan(Discrete::decode(d)?, Discrete::decode(d)?),
+            _ => EntitySetCmp(::magic::EntitySetCmp::decode(d)?),
+        })
+    }
+}
+
+impl Binary for EntitySetCmp {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::EntitySetCmp::*;
+        match *self {
+            Nand(ref v) => { e.byte(0); e.vec(v); },
+            And(ref v) => { e.byte(1); e.vec(v); },
+            Or(ref v) => { e.byte(2); e.vec(v); },
+            Subset(ref a, ref b) => { e.byte(3); a.encode(e); b.encode(e); },
+            Superset(ref a, ref b) => { e.byte(4); a.encode(e); b.encode(e); },
+            Equal(ref a, ref b) => { e.byte(5); a.encode(e); b.encode(e); },
+            Contains(ref a, ref b) => { e.byte(6); a.encode(e); b.encode(e); },
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::EntitySetCmp::*;
+        Ok(match d.tag("EntitySetCmp", 7)? {
+            0 => Nand(d.vec()?),
+            1 => And(d.vec()?),
+            2 => Or(d.vec()?),
+            3 => Subset(EntitySet::decode(d)?, EntitySet::decode(d)?),
+            4 => Superset(EntitySet::decode(d)?, EntitySet::decode(d)?),
+            5 => Equal(EntitySet::decode(d)?, EntitySet::decode(d)?),
+            _ => Contains(EntitySet::decode(d)?, Entity::decode(d)?),
+        })
+    }
+}
+
+impl Binary for EntitySet {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::EntitySet::*;
+        match *self {
+            None(ref v) => { e.byte(0); e.vec(v); },
+            And(ref v) => { e.byte(1); e.vec(v); },
+            Or(ref v) => { e.byte(2); e.vec(v); },
+            Only(ref x) => { e.byte(3); x.encode(e); },
+            IsInSlot(s) => { e.byte(4); s.encode(e); },
+            WithinRangeOf(ref x, ref r) => { e.byte(5); x.encode(e); r.encode(e); },
+            HasMinResource(ref r) => { e.byte(6); r.encode(e); },
+            EnemiesOf(ref x) => { e.byte(7); x.encode(e); },
+            AllBut(ref x) => { e.byte(8); x.encode(e); },
+            IsHuman => e.byte(9),
+            IsProjectile => e.byte(10),
+            Empty => e.byte(11),
+            Universe => e.byte(12),
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::EntitySet::*;
+        Ok(match d.tag("EntitySet", 13)? {
+            0 => None(d.vec()?),
+            1 => And(d.vec()?),
+            2 => Or(d.vec()?),
+            3 => Only(Entity::decode(d)?),
+            4 => IsInSlot(ESetSlot::decode(d)?),
+            5 => WithinRangeOf(Entity::decode(d)?, Discrete::decode(d)?),
+            6 => HasMinResource(Resource::decode(d)?),
+            7 => EnemiesOf(Entity::decode(d)?),
+            8 => AllBut(Entity::decode(d)?),
+            9 => IsHuman,
+            10 => IsProjectile,
+            11 => Empty,
+            _ => Universe,
+        })
+    }
+}
+
+impl Binary for Discrete {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::Discrete::*;
+        match *self {
+            Const(c) => { e.byte(0); c.encode(e); },
+            Range(a, b) => { e.byte(1); a.encode(e); b.encode(e); },
+            WithinPercent(a, f) => { e.byte(2); a.encode(e); f.encode(e); },
+            Div(ref a, ref b) => { e.byte(3); a.encode(e); b.encode(e); },
+            Sum(ref v) => { e.byte(4); e.vec(v); },
+            Neg(ref a) => { e.byte(5); a.encode(e); },
+            Mult(ref v) => { e.byte(6); e.vec(v); },
+            Max(ref v) => { e.byte(7); e.vec(v); },
+            Min(ref v) => { e.byte(8); e.vec(v); },
+            CountStacks(b, ref t, ref x) => { e.byte(9); b.encode(e); t.encode(e); x.encode(e); },
+            CountDur(b, ref t, ref x) => { e.byte(10); b.encode(e); t.encode(e); x.encode(e); },
+            Choose(ref v) => { e.byte(11); e.vec(v); },
+            Cardinality(ref s) => { e.byte(12); s.encode(e); },
+            LoadFrom(s) => { e.byte(13); s.encode(e); },
+            CooldownLeft(i, ref x) => { e.byte(14); i.encode(e); x.encode(e); },
+            Charges(i, ref x) => { e.byte(15); i.encode(e); x.encode(e); },
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::Discrete::*;
+        Ok(match d.tag("Discrete", 16)? {
+            0 => Const(i32::decode(d)?),
+            1 => Range(i32::decode(d)?, i32::decode(d)?),
+            2 => WithinPercent(i32::decode(d)?, F32::decode(d)?),
+            3 => Div(d.boxed()?, d.boxed()?),
+            4 => Sum(d.vec()?),
+            5 => Neg(d.boxed()?),
+            6 => Mult(d.vec()?),
+            7 => Max(d.vec()?),
+            8 => Min(d.vec()?),
+            9 => CountStacks(Buff::decode(d)?, BuffTier::decode(d)?, Entity::decode(d)?),
+            10 => CountDur(Buff::decode(d)?, BuffTier::decode(d)?, Entity::decode(d)?),
+            11 => Choose(d.vec()?),
+            12 => Cardinality(d.boxed()?),
+            13 => LoadFrom(DSlot::decode(d)?),
+            14 => CooldownLeft(u8::decode(d)?, Entity::decode(d)?),
+            _ => Charges(u8::decode(d)?, Entity::decode(d)?),
+        })
+    }
+}
+
+impl Binary for Resource {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::Resource::*;
+        match *self {
+            Mana(ref x) => { e.byte(0); x.encode(e); },
+            Health(ref x) => { e.byte(1); x.encode(e); },
+            BuffStacks(b, ref t, ref x) => { e.byte(2); b.encode(e); t.encode(e); x.encode(e); },
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::Resource::*;
+        Ok(match d.tag("Resource", 3)? {
+            0 => Mana(Discrete::decode(d)?),
+            1 => Health(Discrete::decode(d)?),
+            _ => BuffStacks(Buff::decode(d)?, BuffTier::decode(d)?, Discrete::decode(d)?),
+        })
+    }
+}
+
+impl Binary for BuffTier {
+    fn encode(&self, e: &mut Encoder) {
+        match *self {
+            BuffTier::Tier(ref x) => { e.byte(0); x.encode(e); },
+            BuffTier::AnyTier => e.byte(1),
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(match d.tag("BuffTier", 2)? {
+            0 => BuffTier::Tier(d.boxed()?),
+            _ => BuffTier::AnyTier,
+        })
+    }
+}
+
+impl Binary for Entity {
+    fn encode(&self, e: &mut Encoder) {
+        use magic::Entity::*;
+        match *self {
+            LoadEntity(s) => { e.byte(0); s.encode(e); },
+            FirstOf(ref s) => { e.byte(1); s.encode(e); },
+            Choose(ref s) => { e.byte(2); s.encode(e); },
+            ClosestFrom(ref s, ref l) => { e.byte(3); s.encode(e); l.encode(e); },
+        }
+    }
+    fn decode(d: &mut Decoder) -> Result<Self, DecodeError> {
+        use magic::Entity::*;
+        Ok(match d.tag("Entity", 4)? {
+            0 => LoadEntity(ESlot::decode(d)?),
+            1 => FirstOf(d.boxed()?),
+            2 => Choose(d.boxed()?),
+            _ => ClosestFrom(d.boxed()?, d.boxed()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, Isaac64Rng, SeedableRng};
+    use generate;
+    use super::*;
+
+    #[test]
+    fn codec() {
+        generate::check_codec(2, to_bytes, from_bytes::<Spell>);
+    }
+
+    // another version may mean the same bytes differently, so none is read
+    #[test]
+    fn only_this_version_is_read() {
+        let s = &generate::samples(1)[0];
+        let bytes = to_bytes(s);
+        for v in [0, VERSION - 1, VERSION + 1, u8::MAX].iter() {
+            let mut other = bytes.clone();
+            other[HEADER.len()] = *v;
+            assert_eq!(from_bytes::<Spell>(&other), Err(DecodeError::UnsupportedVersion(*v)));
+        }
+        for i in 0..HEADER.len() {
+            for bit in 0..8 {
+                let mut other = bytes.clone();
+                other[i] ^= 1 << bit;
+                assert_eq!(from_bytes::<Spell>(&other), Err(DecodeError::BadHeader));
+            }
+        }
+        assert_eq!(from_bytes::<Spell>(&value_to_bytes(s)), Err(DecodeError::BadHeader));
+    }
+
+    #[test]
+    fn shared_blueprints_stay_shared() {
+        let s = generate::samples(40).into_iter()
+            .find(|s| s.on_cast.iter().any(|i| matches!(*i, Instruction::SpawnProjectileAt(..))))
+            .unwrap();
+        let bp = s.on_cast.iter().filter_map(|i| match *i {
+            Instruction::SpawnProjectileAt(ref bp, _) => Some(bp.clone()),
+            _ => None,
+        }).next().unwrap();
+        let twice = vec![
+            Instruction::SpawnProjectileAt(bp.clone(), Location::LoadLocation(LSlot(0))),
+            Instruction::SpawnProjectileAt(bp, Location::LoadLocation(LSlot(0))),
+        ];
+        let back: Vec<Instruction> = from_bytes(&to_bytes(&twice)).unwrap();
+        assert_eq!(back, twice);
+        match (&back[0], &back[1]) {
+            (Instruction::SpawnProjectileAt(a, _), Instruction::SpawnProjectileAt(b, _)) => assert!(Rc::ptr_eq(a, b)),
+            _ => unreachable!(),
+        }
+    }
+
+    // a reference can only point to a blueprint that was finished before it,
+    // so blueprints can't contain themselves
+    #[test]
+    fn references_only_point_back() {
+        let with = |value: &[u8]| HEADER.iter().chain(&[VERSION]).chain(value).cloned().collect::<Vec<u8>>();
+        // [SpawnProjectileAt(blueprint 0, ..
+        assert_eq!(from_bytes::<Vec<Instruction>>(&with(&[1, 9, 1, 0])), Err(DecodeError::BadBlueprintRef(0)));
+        // [SpawnProjectileAt(Projectile (on_create SpawnProjectileAt(blueprint 0, ..
+        assert_eq!(from_bytes::<Vec<Instruction>>(&with(&[1, 9, 0, 1, 9, 1, 0])), Err(DecodeError::BadBlueprintRef(0)));
+    }
+
+    // each blueprint spawns the last one twice, so the spell doubles in size
+    // with every blueprint, while its encoding grows by a few bytes
+    fn doubling_chain(n: usize) -> Vec<Instruction> {
+        use magic::Instruction::*;
+        let spawn = |bp: &Rc<ProjectileBlueprint>| SpawnProjectileAt(bp.clone(), Location::LoadLocation(LSlot(0)));
+        let mut bp = Rc::new(ProjectileBlueprint {
+            on_create: vec![],
+            on_collision: vec![],
+            collides_with: EntitySet::Empty,
+            on_destroy: vec![],
+            lifetime: Discrete::Const(1),
+        });
+        for _ in 0..n {
+            bp = Rc::new(ProjectileBlueprint { on_create: vec![spawn(&bp), spawn(&bp)], ..(*bp).clone() });
+        }
+        vec![spawn(&bp)]
+    }
+
+    #[test]
+    fn references_count_at_their_size() {
+        let small = doubling_chain(10);
+        assert_eq!(from_bytes(&to_bytes(&small)), Ok(small));
+        let bytes = to_bytes(&doubling_chain(40));
+        assert!(bytes.len() < 1000);
+        assert_eq!(from_bytes::<Vec<Instruction>>(&bytes), Err(DecodeError::TooBig));
+    }
+
+    #[test]
+    fn random() {
+        let mut rng = Isaac64Rng::from_seed(&[4]);
+        for _ in 0..2000 {
+            let len = rng.gen_range(0, 100);
+            let mut bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            if rng.gen() {
+                bytes.splice(..0, HEADER.iter().cloned().chain(Some(VERSION)));
+            }
+            assert!(from_bytes::<Spell>(&bytes).is_err());
+        }
+        let mut deep = to_bytes(&Discrete::Const(0));
+        deep.truncate(HEADER.len() + 1);
+        deep.extend((0..10000).map(|_| 5)); // Neg(Neg(...
+        assert_eq!(from_bytes::<Discrete>(&deep), Err(DecodeError::TooDeep));
+    }
+}
@@ -0,0 +1,760 @@
+use std::rc::Rc;
+use magic::*;
+use buffs::*;
+use sexp::{Sexp,ParseError};
+
+// The textual form of magic, for spellbooks kept and edited by hand.
+// It is built on sexp.rs. Names are exactly those of the variants in magic.rs,
+// so the two can be read side by side:
+//
+//   spell        := (Spell (on_cast instr*) (requires cond)
+//                          (on_cooldown instr*) (consumes resource*)
+//                          (cast_time disc) (on_cast_start instr*) (on_interrupt instr*))
+//   blueprint    := (Projectile (on_create instr*) (on_collision instr*)
+//                          (collides_with set) (on_destroy instr*) (lifetime disc))
+//   instr        := (Define def) | (ITE cond (then instr*) (else instr*))
+//                 | (CallWith def instr*) | (ForEachAs eslot set instr*)
+//                 | (DestroyWithoutEvent ent) | (Destroy ent) | (MoveEntity ent loc)
+//                 | (AddResource ent resource) | (AddVelocity ent dir disc)
+//                 | (SpawnProjectileAt blueprint loc) | Nothing
+//   def          := (eset_slot set) | (eslot ent) | (dslot disc) | (lslot loc)
+//   eslot        := E_0 | E_1 ...     eset_slot := Eset_0 ...
+//   dslot        := D_0 ...           lslot     := L_0 ...
+//   resource     := (Mana disc) | (Health disc) | (BuffStacks buff tier disc)
+//   buff         := Swarm | Mute | ... as in buffs.rs
+//   tier         := (Tier disc) | AnyTier
+//
+// and so on for Condition, EntitySetCmp, EntitySet, Entity, Discrete,
+// Location and Direction: a variant without fields is a bare name, any
+// other is a list of its name followed by its fields in order. Vec fields
+// are spread out as the trailing elements, eg. (Sum (Const 1) (Const 2)).
+// Integers are written in decimal and F32s in Rust's shortest round-trip
+// form, so printing and parsing again gives back the exact same magic.
+// `;` starts a comment.
+//
+// Example:
+//   (Spell
+//     (on_cast
+//       (AddResource (FirstOf (EnemiesOf (LoadEntity E_0))) (Health (Const -10))))
+//     (requires Top)
+//     (on_cooldown)
+//     (consumes (Mana (Const 15)))
+//     (cast_time (Const 0))
+//     (on_cast_start)
+//     (on_interrupt))
+
+const WIDTH: usize = 80;
+
+pub trait Text: Sized {
+    fn to_sexp(&self) -> Sexp;
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError>;
+
+    fn to_text(&self) -> String {
+        self.to_sexp().pretty(WIDTH)
+    }
+
+    fn from_text(src: &str) -> Result<Self, ParseError> {
+        Self::from_sexp(&Sexp::parse(src)?)
+    }
+}
+
+pub fn spellbook_to_string(spells: &[Spell]) -> String {
+    let mut s = String::new();
+    for spell in spells {
+        s.push_str(&spell.to_text());
+        s.push_str("\n\n");
+    }
+    s
+}
+
+pub fn parse_spellbook(src: &str) -> Result<Vec<Spell>, ParseError> {
+    Sexp::parse_all(src)?.iter().map(Spell::from_sexp).collect()
+}
+
+/////////////////////////////////////////////////////////////////////////
+
+fn call(head: &str, mut args: Vec<Sexp>) -> Sexp {
+    args.insert(0, Sexp::atom(head));
+    Sexp::list(args)
+}
+
+fn call_vec<T: Text>(head: &str, v: &[T]) -> Sexp {
+    call(head, v.iter().map(Text::to_sexp).collect())
+}
+
+fn arity(x: &Sexp, head: &str, args: &[Sexp], n: usize) -> Result<(), ParseError> {
+    if args.len() == n {
+        Ok(())
+    } else {
+        Err(ParseError::new(x.pos(), format!("`{}` takes {} argument(s), found {}", head, n, args.len())))
+    }
+}
+
+fn min_arity(x: &Sexp, head: &str, args: &[Sexp], n: usize) -> Result<(), ParseError> {
+    if args.len() >= n {
+        Ok(())
+    } else {
+        Err(ParseError::new(x.pos(), format!("`{}` takes at least {} argument(s), found {}", head, n, args.len())))
+    }
+}
+
+fn unknown(x: &Sexp, what: &str) -> ParseError {
+    ParseError::new(x.pos(), format!("expected {}, found {}", what, x.describe()))
+}
+
+fn vec_from<T: Text>(args: &[Sexp]) -> Result<Vec<T>, ParseError> {
+    args.iter().map(T::from_sexp).collect()
+}
+
+fn boxed<T: Text>(x: &Sexp) -> Result<Box<T>, ParseError> {
+    T::from_sexp(x).map(Box::new)
+}
+
+fn number<T: ::std::str::FromStr>(x: &Sexp, what: &str) -> Result<T, ParseError> {
+    let s = x.as_atom()?;
+    s.parse().map_err(|_| ParseError::new(x.pos(), format!("expected {}, found `{}`", what, s)))
+}
+
+// (name field...) with every named field present exactly once, in any order
+fn fields<'a>(x: &'a Sexp, head: &str, names: &[&str]) -> Result<Vec<&'a [Sexp]>, ParseError> {
+    let (h, args) = x.as_call()?;
+    if h != head {
+        return Err(unknown(x, &format!("`({} ...)`", head)));
+    }
+    let mut found: Vec<Option<&[Sexp]>> = vec![None; names.len()];
+    for arg in args {
+        let (name, rest) = arg.as_call()?;
+        match names.iter().position(|n| *n == name) {
+            Some(i) if found[i].is_none() => found[i] = Some(rest),
+            Some(_) => return Err(ParseError::new(arg.pos(), format!("field `{}` is given twice", name))),
+            None => return Err(ParseError::new(arg.pos(), format!("`{}` has no field `{}`", head, name))),
+        }
+    }
+    let mut ret = vec![];
+    for (i, f) in found.into_iter().enumerate() {
+        match f {
+            Some(f) => ret.push(f),
+            None => return Err(ParseError::new(x.pos(), format!("`{}` is missing field `{}`", head, names[i]))),
+        }
+    }
+    Ok(ret)
+}
+
+// (name instr...)
+fn block<'a>(x: &'a Sexp, name: &str) -> Result<&'a [Sexp], ParseError> {
+    match x.as_call()? {
+        (h, args) if h == name => Ok(args),
+        _ => Err(unknown(x, &format!("`({} ...)`", name))),
+    }
+}
+
+fn single<'a>(x: &Sexp, name: &str, args: &'a [Sexp]) -> Result<&'a Sexp, ParseError> {
+    arity(x, name, args, 1)?;
+    Ok(&args[0])
+}
+
+fn slot_number(x: &Sexp, prefix: &str) -> Result<u8, ParseError> {
+    let s = x.as_atom()?;
+    if let Some(Ok(n)) = s.strip_prefix(prefix).map(str::parse) {
+        return Ok(n);
+    }
+    Err(ParseError::new(x.pos(), format!("expected a slot like `{}0`, found `{}`", prefix, s)))
+}
+
+/////////////////////////////////////////////////////////////////////////
+
+impl Text for ESlot {
+    fn to_sexp(&self) -> Sexp { Sexp::atom(format!("{:?}", self)) }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> { slot_number(x, "E_").map(ESlot) }
+}
+
+impl Text for ESetSlot {
+    fn to_sexp(&self) -> Sexp { Sexp::atom(format!("{:?}", self)) }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> { slot_number(x, "Eset_").map(ESetSlot) }
+}
+
+impl Text for DSlot {
+    fn to_sexp(&self) -> Sexp { Sexp::atom(format!("{:?}", self)) }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> { slot_number(x, "D_").map(DSlot) }
+}
+
+impl Text for LSlot {
+    fn to_sexp(&self) -> Sexp { Sexp::atom(format!("{:?}", self)) }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> { slot_number(x, "L_").map(LSlot) }
+}
+
+impl Text for F32 {
+    fn to_sexp(&self) -> Sexp { Sexp::atom(format!("{:?}", self.0)) }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> { number(x, "a decimal number").map(F32) }
+}
+
+impl Text for i32 {
+    fn to_sexp(&self) -> Sexp { Sexp::atom(self.to_string()) }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> { number(x, "an integer") }
+}
+
+impl Text for u8 {
+    fn to_sexp(&self) -> Sexp { Sexp::atom(self.to_string()) }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> { number(x, "an integer in 0..255") }
+}
+
+impl Text for Buff {
+    fn to_sexp(&self) -> Sexp { Sexp::atom(format!("{:?}", self)) }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        let s = x.as_atom()?;
+        Buff::ALL.iter()
+        .find(|b| format!("{:?}", b) == s)
+        .cloned()
+        .ok_or_else(|| unknown(x, "a buff"))
+    }
+}
+
+impl<T: Text> Text for Rc<T> {
+    fn to_sexp(&self) -> Sexp { (**self).to_sexp() }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> { T::from_sexp(x).map(Rc::new) }
+}
+
+impl Text for Spell {
+    fn to_sexp(&self) -> Sexp {
+        call("Spell", vec![
+            call_vec("on_cast", &self.on_cast),
+            call("requires", vec![self.requires.to_sexp()]),
+            call_vec("on_cooldown", &self.on_cooldown),
+            call_vec("consumes", &self.consumes),
+            call("cast_time", vec![self.cast_time.to_sexp()]),
+            call_vec("on_cast_start", &self.on_cast_start),
+            call_vec("on_interrupt", &self.on_interrupt),
+        ])
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        let f = fields(x, "Spell", &["on_cast", "requires", "on_cooldown", "consumes",
+                                     "cast_time", "on_cast_start", "on_interrupt"])?;
+        Ok(Spell {
+            on_cast: vec_from(f[0])?,
+            requires: boxed(single(x, "requires", f[1])?)?,
+            on_cooldown: vec_from(f[2])?,
+            consumes: vec_from(f[3])?,
+            cast_time: Discrete::from_sexp(single(x, "cast_time", f[4])?)?,
+            on_cast_start: vec_from(f[5])?,
+            on_interrupt: vec_from(f[6])?,
+        })
+    }
+}
+
+impl Text for ProjectileBlueprint {
+    fn to_sexp(&self) -> Sexp {
+        call("Projectile", vec![
+            call_vec("on_create", &self.on_create),
+            call_vec("on_collision", &self.on_collision),
+            call("collides_with", vec![self.collides_with.to_sexp()]),
+            call_vec("on_destroy", &self.on_destroy),
+            call("lifetime", vec![self.lifetime.to_sexp()]),
+        ])
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        let f = fields(x, "Projectile", &["on_create", "on_collision", "collides_with", "on_destroy", "lifetime"])?;
+        Ok(ProjectileBlueprint {
+            on_create: vec_from(f[0])?,
+            on_collision: vec_from(f[1])?,
+            collides_with: EntitySet::from_sexp(single(x, "collides_with", f[2])?)?,
+            on_destroy: vec_from(f[3])?,
+            lifetime: Discrete::from_sexp(single(x, "lifetime", f[4])?)?,
+        })
+    }
+}
+
+impl Text for Instruction {
+    fn to_sexp(&self) -> Sexp {
+        use magic::Instruction::*;
+        match *self {
+            Define(ref d) => call("Define", vec![d.to_sexp()]),
+            ITE(ref c, ref t, ref e) => call("ITE", vec![
+                c.to_sexp(),
+                call_vec("then", t),
+                call_vec("else", e),
+            ]),
+            CallWith(ref d, ref v) => {
+                let mut args = vec![d.to_sexp()];
+                args.extend(v.iter().map(Text::to_sexp));
+                call("CallWith", args)
+            },
+            ForEachAs(s, ref set, ref v) => {
+                let mut args = vec![s.to_sexp(), set.to_sexp()];
+                args.extend(v.iter().map(Text::to_sexp));
+                call("ForEachAs", args)
+            },
+            DestroyWithoutEvent(ref e) => call("DestroyWithoutEvent", vec![e.to_sexp()]),
+            Destroy(ref e) => call("Destroy", vec![e.to_sexp()]),
+            MoveEntity(ref e, ref l) => call("MoveEntity", vec![e.to_sexp(), l.to_sexp()]),
+            AddResource(ref e, ref r) => call("AddResource", vec![e.to_sexp(), r.to_sexp()]),
+            AddVelocity(ref e, ref dir, ref d) => call("AddVelocity", vec![e.to_sexp(), dir.to_sexp(), d.to_sexp()]),
+            SpawnProjectileAt(ref bp, ref l) => call("SpawnProjectileAt", vec![bp.to_sexp(), l.to_sexp()]),
+            Nothing => Sexp::atom("Nothing"),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::Instruction::*;
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "Define" => {
+                arity(x, head, a, 1)?;
+                Define(Definition::from_sexp(&a[0])?)
+            },
+            "ITE" => {
+                arity(x, head, a, 3)?;
+                ITE(Condition::from_sexp(&a[0])?, vec_from(block(&a[1], "then")?)?, vec_from(block(&a[2], "else")?)?)
+            },
+            "CallWith" => {
+                min_arity(x, head, a, 1)?;
+                CallWith(Definition::from_sexp(&a[0])?, vec_from(&a[1..])?)
+            },
+            "ForEachAs" => {
+                min_arity(x, head, a, 2)?;
+                ForEachAs(ESlot::from_sexp(&a[0])?, EntitySet::from_sexp(&a[1])?, vec_from(&a[2..])?)
+            },
+            "DestroyWithoutEvent" => {
+                arity(x, head, a, 1)?;
+                DestroyWithoutEvent(Entity::from_sexp(&a[0])?)
+            },
+            "Destroy" => {
+                arity(x, head, a, 1)?;
+                Destroy(Entity::from_sexp(&a[0])?)
+            },
+            "MoveEntity" => {
+                arity(x, head, a, 2)?;
+                MoveEntity(Entity::from_sexp(&a[0])?, Location::from_sexp(&a[1])?)
+            },
+            "AddResource" => {
+                arity(x, head, a, 2)?;
+                AddResource(Entity::from_sexp(&a[0])?, Resource::from_sexp(&a[1])?)
+            },
+            "AddVelocity" => {
+                arity(x, head, a, 3)?;
+                AddVelocity(Entity::from_sexp(&a[0])?, Direction::from_sexp(&a[1])?, Discrete::from_sexp(&a[2])?)
+            },
+            "SpawnProjectileAt" => {
+                arity(x, head, a, 2)?;
+                SpawnProjectileAt(Rc::from_sexp(&a[0])?, Location::from_sexp(&a[1])?)
+            },
+            "Nothing" => {
+                arity(x, head, a, 0)?;
+                Nothing
+            },
+            _ => return Err(unknown(x, "an Instruction")),
+        })
+    }
+}
+
+impl Text for Definition {
+    fn to_sexp(&self) -> Sexp {
+        use magic::Definition::*;
+        match *self {
+            ESet(s, ref x) => Sexp::list(vec![s.to_sexp(), x.to_sexp()]),
+            E(s, ref x) => Sexp::list(vec![s.to_sexp(), x.to_sexp()]),
+            D(s, ref x) => Sexp::list(vec![s.to_sexp(), x.to_sexp()]),
+            L(s, ref x) => Sexp::list(vec![s.to_sexp(), x.to_sexp()]),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::Definition::*;
+        let (head, a) = x.as_call()?;
+        arity(x, head, a, 1)?;
+        let slot = &x.as_list()?[0];
+        Ok(if head.starts_with("Eset_") {
+            ESet(ESetSlot::from_sexp(slot)?, EntitySet::from_sexp(&a[0])?)
+        } else if head.starts_with("E_") {
+            E(ESlot::from_sexp(slot)?, Entity::from_sexp(&a[0])?)
+        } else if head.starts_with("D_") {
+            D(DSlot::from_sexp(slot)?, Discrete::from_sexp(&a[0])?)
+        } else if head.starts_with("L_") {
+            L(LSlot::from_sexp(slot)?, Location::from_sexp(&a[0])?)
+        } else {
+            return Err(unknown(slot, "a slot to define"));
+        })
+    }
+}
+
+impl Text for Direction {
+    fn to_sexp(&self) -> Sexp {
+        use magic::Direction::*;
+        match *self {
+            TowardLocation(ref a, ref b) => call("TowardLocation", vec![a.to_sexp(), b.to_sexp()]),
+            ConstRad(f) => call("ConstRad", vec![f.to_sexp()]),
+            BetweenRad(a, b) => call("BetweenRad", vec![a.to_sexp(), b.to_sexp()]),
+            Choose(ref v) => call_vec("Choose", v),
+            ChooseWithinRadOf(ref d, f) => call("ChooseWithinRadOf", vec![d.to_sexp(), f.to_sexp()]),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::Direction::*;
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "TowardLocation" => {
+                arity(x, head, a, 2)?;
+                TowardLocation(Location::from_sexp(&a[0])?, Location::from_sexp(&a[1])?)
+            },
+            "ConstRad" => {
+                arity(x, head, a, 1)?;
+                ConstRad(F32::from_sexp(&a[0])?)
+            },
+            "BetweenRad" => {
+                arity(x, head, a, 2)?;
+                BetweenRad(F32::from_sexp(&a[0])?, F32::from_sexp(&a[1])?)
+            },
+            "Choose" => Choose(vec_from(a)?),
+            "ChooseWithinRadOf" => {
+                arity(x, head, a, 2)?;
+                ChooseWithinRadOf(boxed(&a[0])?, F32::from_sexp(&a[1])?)
+            },
+            _ => return Err(unknown(x, "a Direction")),
+        })
+    }
+}
+
+impl Text for Location {
+    fn to_sexp(&self) -> Sexp {
+        use magic::Location::*;
+        match *self {
+            AtEntity(ref e) => call("AtEntity", vec![e.to_sexp()]),
+            Midpoint(ref v) => call_vec("Midpoint", v),
+            Choose(ref v) => call_vec("Choose", v),
+            LoadLocation(s) => call("LoadLocation", vec![s.to_sexp()]),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::Location::*;
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "AtEntity" => {
+                arity(x, head, a, 1)?;
+                AtEntity(Entity::from_sexp(&a[0])?)
+            },
+            "Midpoint" => Midpoint(vec_from(a)?),
+            "Choose" => Choose(vec_from(a)?),
+            "LoadLocation" => {
+                arity(x, head, a, 1)?;
+                LoadLocation(LSlot::from_sexp(&a[0])?)
+            },
+            _ => return Err(unknown(x, "a Location")),
+        })
+    }
+}
+
+impl Text for Condition {
+    fn to_sexp(&self) -> Sexp {
+        use magic::Condition::*;
+        match *self {
+            Nand(ref v) => call_vec("Nand", v),
+            And(ref v) => call_vec("And", v),
+            Or(ref v) => call_vec("Or", v),
+            Top => Sexp::atom("Top"),
+            Bottom => Sexp::atom("Bottom"),
+            Equals(ref a, ref b) => call("Equals", vec![a.to_sexp(), b.to_sexp()]),
+            LessThan(ref a, ref b) => call("LessThan", vec![a.to_sexp(), b.to_sexp()]),
+            MoreThan(ref a, ref b) => call("MoreThan", vec![a.to_sexp(), b.to_sexp()]),
+            EntitySetCmp(ref c) => call("EntitySetCmp", vec![c.to_sexp()]),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::Condition::*;
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "Nand" => Nand(vec_from(a)?),
+            "And" => And(vec_from(a)?),
+            "Or" => Or(vec_from(a)?),
+            "Top" => {
+                arity(x, head, a, 0)?;
+                Top
+            },
+            "Bottom" => {
+                arity(x, head, a, 0)?;
+                Bottom
+            },
+            "Equals" | "LessThan" | "MoreThan" => {
+                arity(x, head, a, 2)?;
+                let l = Discrete::from_sexp(&a[0])?;
+                let r = Discrete::from_sexp(&a[1])?;
+                match head {
+                    "Equals" => Equals(l, r),
+                    "LessThan" => LessThan(l, r),
+                    _ => MoreThan(l, r),
+                }
+            },
+            "EntitySetCmp" => {
+                arity(x, head, a, 1)?;
+                EntitySetCmp(::magic::EntitySetCmp::from_sexp(&a[0])?)
+            },
+            _ => return Err(unknown(x, "a Condition")),
+        })
+    }
+}
+
+impl Text for EntitySetCmp {
+    fn to_sexp(&self) -> Sexp {
+        use magic::EntitySetCmp::*;
+        match *self {
+            Nand(ref v) => call_vec("Nand", v),
+            And(ref v) => call_vec("And", v),
+            Or(ref v) => call_vec("Or", v),
+            Subset(ref a, ref b) => call("Subset", vec![a.to_sexp(), b.to_sexp()]),
+            Superset(ref a, ref b) => call("Superset", vec![a.to_sexp(), b.to_sexp()]),
+            Equal(ref a, ref b) => call("Equal", vec![a.to_sexp(), b.to_sexp()]),
+            Contains(ref a, ref b) => call("Contains", vec![a.to_sexp(), b.to_sexp()]),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::EntitySetCmp::*;
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "Nand" => Nand(vec_from(a)?),
+            "And" => And(vec_from(a)?),
+            "Or" => Or(vec_from(a)?),
+            "Subset" | "Superset" | "Equal" => {
+                arity(x, head, a, 2)?;
+                let l = EntitySet::from_sexp(&a[0])?;
+                let r = EntitySet::from_sexp(&a[1])?;
+                match head {
+                    "Subset" => Subset(l, r),
+                    "Superset" => Superset(l, r),
+                    _ => Equal(l, r),
+                }
+            },
+            "Contains" => {
+                arity(x, head, a, 2)?;
+                Contains(EntitySet::from_sexp(&a[0])?, Entity::from_sexp(&a[1])?)
+            },
+            _ => return Err(unknown(x, "an EntitySetCmp")),
+        })
+    }
+}
+
+impl Text for EntitySet {
+    fn to_sexp(&self) -> Sexp {
+        use magic::EntitySet::*;
+        match *self {
+            None(ref v) => call_vec("None", v),
+            And(ref v) => call_vec("And", v),
+            Or(ref v) => call_vec("Or", v),
+            Only(ref e) => call("Only", vec![e.to_sexp()]),
+            IsInSlot(s) => call("IsInSlot", vec![s.to_sexp()]),
+            WithinRangeOf(ref e, ref d) => call("WithinRangeOf", vec![e.to_sexp(), d.to_sexp()]),
+            HasMinResource(ref r) => call("HasMinResource", vec![r.to_sexp()]),
+            EnemiesOf(ref e) => call("EnemiesOf", vec![e.to_sexp()]),
+            AllBut(ref e) => call("AllBut", vec![e.to_sexp()]),
+            IsHuman => Sexp::atom("IsHuman"),
+            IsProjectile => Sexp::atom("IsProjectile"),
+            Empty => Sexp::atom("Empty"),
+            Universe => Sexp::atom("Universe"),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::EntitySet::*;
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "None" => None(vec_from(a)?),
+            "And" => And(vec_from(a)?),
+            "Or" => Or(vec_from(a)?),
+            "Only" | "EnemiesOf" | "AllBut" => {
+                let e = Entity::from_sexp(single(x, head, a)?)?;
+                match head {
+                    "Only" => Only(e),
+                    "EnemiesOf" => EnemiesOf(e),
+                    _ => AllBut(e),
+                }
+            },
+            "IsInSlot" => IsInSlot(ESetSlot::from_sexp(single(x, head, a)?)?),
+            "WithinRangeOf" => {
+                arity(x, head, a, 2)?;
+                WithinRangeOf(Entity::from_sexp(&a[0])?, Discrete::from_sexp(&a[1])?)
+            },
+            "HasMinResource" => HasMinResource(Resource::from_sexp(single(x, head, a)?)?),
+            "IsHuman" | "IsProjectile" | "Empty" | "Universe" => {
+                arity(x, head, a, 0)?;
+                match head {
+                    "IsHuman" => IsHuman,
+                    "IsProjectile" => IsProjectile,
+                    "Empty" => Empty,
+                    _ => Universe,
+                }
+            },
+            _ => return Err(unknown(x, "an EntitySet")),
+        })
+    }
+}
+
+impl Text for Discrete {
+    fn to_sexp(&self) -> Sexp {
+        use magic::Discrete::*;
+        match *self {
+            Const(c) => call("Const", vec![c.to_sexp()]),
+            Range(a, b) => call("Range", vec![a.to_sexp(), b.to_sexp()]),
+            WithinPercent(a, f) => call("WithinPercent", vec![a.to_sexp(), f.to_sexp()]),
+            Div(ref a, ref b) => call("Div", vec![a.to_sexp(), b.to_sexp()]),
+            Sum(ref v) => call_vec("Sum", v),
+            Neg(ref a) => call("Neg", vec![a.to_sexp()]),
+            Mult(ref v) => call_vec("Mult", v),
+            Max(ref v) => call_vec("Max", v),
+            Min(ref v) => call_vec("Min", v),
+            CountStacks(b, ref t, ref e) => call("CountStacks", vec![b.to_sexp(), t.to_sexp(), e.to_sexp()]),
+            CountDur(b, ref t, ref e) => call("CountDur", vec![b.to_sexp(), t.to_sexp(), e.to_sexp()]),
+            Choose(ref v) => call_vec("Choose", v),
+            Cardinality(ref s) => call("Cardinality", vec![s.to_sexp()]),
+            LoadFrom(s) => call("LoadFrom", vec![s.to_sexp()]),
+            CooldownLeft(i, ref e) => call("CooldownLeft", vec![i.to_sexp(), e.to_sexp()]),
+            Charges(i, ref e) => call("Charges", vec![i.to_sexp(), e.to_sexp()]),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::Discrete::*;
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "Const" => Const(i32::from_sexp(single(x, head, a)?)?),
+            "Range" => {
+                arity(x, head, a, 2)?;
+                Range(i32::from_sexp(&a[0])?, i32::from_sexp(&a[1])?)
+            },
+            "WithinPercent" => {
+                arity(x, head, a, 2)?;
+                WithinPercent(i32::from_sexp(&a[0])?, F32::from_sexp(&a[1])?)
+            },
+            "Div" => {
+                arity(x, head, a, 2)?;
+                Div(boxed(&a[0])?, boxed(&a[1])?)
+            },
+            "Sum" => Sum(vec_from(a)?),
+            "Neg" => Neg(boxed(single(x, head, a)?)?),
+            "Mult" => Mult(vec_from(a)?),
+            "Max" => Max(vec_from(a)?),
+            "Min" => Min(vec_from(a)?),
+            "CountStacks" | "CountDur" => {
+                arity(x, head, a, 3)?;
+                let b = Buff::from_sexp(&a[0])?;
+                let t = BuffTier::from_sexp(&a[1])?;
+                let e = Entity::from_sexp(&a[2])?;
+                if head == "CountStacks" { CountStacks(b, t, e) } else { CountDur(b, t, e) }
+            },
+            "Choose" => Choose(vec_from(a)?),
+            "Cardinality" => Cardinality(boxed(single(x, head, a)?)?),
+            "LoadFrom" => LoadFrom(DSlot::from_sexp(single(x, head, a)?)?),
+            "CooldownLeft" | "Charges" => {
+                arity(x, head, a, 2)?;
+                let i = u8::from_sexp(&a[0])?;
+                let e = Entity::from_sexp(&a[1])?;
+                if head == "CooldownLeft" { CooldownLeft(i, e) } else { Charges(i, e) }
+            },
+            _ => return Err(unknown(x, "a Discrete")),
+        })
+    }
+}
+
+impl Text for Resource {
+    fn to_sexp(&self) -> Sexp {
+        use magic::Resource::*;
+        match *self {
+            Mana(ref d) => call("Mana", vec![d.to_sexp()]),
+            Health(ref d) => call("Health", vec![d.to_sexp()]),
+            BuffStacks(b, ref t, ref d) => call("BuffStacks", vec![b.to_sexp(), t.to_sexp(), d.to_sexp()]),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::Resource::*;
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "Mana" => Mana(Discrete::from_sexp(single(x, head, a)?)?),
+            "Health" => Health(Discrete::from_sexp(single(x, head, a)?)?),
+            "BuffStacks" => {
+                arity(x, head, a, 3)?;
+                BuffStacks(Buff::from_sexp(&a[0])?, BuffTier::from_sexp(&a[1])?, Discrete::from_sexp(&a[2])?)
+            },
+            _ => return Err(unknown(x, "a Resource")),
+        })
+    }
+}
+
+impl Text for BuffTier {
+    fn to_sexp(&self) -> Sexp {
+        match *self {
+            BuffTier::Tier(ref d) => call("Tier", vec![d.to_sexp()]),
+            BuffTier::AnyTier => Sexp::atom("AnyTier"),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "Tier" => BuffTier::Tier(boxed(single(x, head, a)?)?),
+            "AnyTier" => {
+                arity(x, head, a, 0)?;
+                BuffTier::AnyTier
+            },
+            _ => return Err(unknown(x, "a BuffTier")),
+        })
+    }
+}
+
+impl Text for Entity {
+    fn to_sexp(&self) -> Sexp {
+        use magic::Entity::*;
+        match *self {
+            LoadEntity(s) => call("LoadEntity", vec![s.to_sexp()]),
+            FirstOf(ref s) => call("FirstOf", vec![s.to_sexp()]),
+            Choose(ref s) => call("Choose", vec![s.to_sexp()]),
+            ClosestFrom(ref s, ref l) => call("ClosestFrom", vec![s.to_sexp(), l.to_sexp()]),
+        }
+    }
+    fn from_sexp(x: &Sexp) -> Result<Self, ParseError> {
+        use magic::Entity::*;
+        let (head, a) = x.as_call()?;
+        Ok(match head {
+            "LoadEntity" => LoadEntity(ESlot::from_sexp(single(x, head, a)?)?),
+            "FirstOf" => FirstOf(boxed(single(x, head, a)?)?),
+            "Choose" => Choose(boxed(single(x, head, a)?)?),
+            "ClosestFrom" => {
+                arity(x, head, a, 2)?;
+                ClosestFrom(boxed(&a[0])?, boxed(&a[1])?)
+            },
+            _ => return Err(unknown(x, "an Entity")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, Isaac64Rng, SeedableRng};
+    use generate;
+    use super::*;
+
+    #[test]
+    fn codec() {
+        generate::check_codec(1, |s| s.to_text().into_bytes(), |b| Spell::from_text(&String::from_utf8_lossy(b)));
+        let spells = generate::samples(40);
+        assert_eq!(parse_spellbook(&spellbook_to_string(&spells)), Ok(spells));
+    }
+
+    #[test]
+    fn random() {
+        let mut rng = Isaac64Rng::from_seed(&[3]);
+        let chars: Vec<char> = "()Spel on_cast requires E_0 D_1 Const -12.5 ;\n".chars().collect();
+        for _ in 0..500 {
+            let len = rng.gen_range(0, 200);
+            let src: String = (0..len).map(|_| *rng.choose(&chars).unwrap()).collect();
+            assert!(Spell::from_text(&src).is_err(), "{}", src);
+        }
+    }
+
+    // a spell whose cast_time is `n` Negs deep
+    fn negated(n: usize) -> String {
+        format!("(Spell (on_cast) (requires Top) (on_cooldown) (consumes)
+            (cast_time {}(Const 1){}) (on_cast_start) (on_interrupt))", "(Neg ".repeat(n), ")".repeat(n))
+    }
+
+    // the reader stops at a depth of 256, before parsing recurses that deep
+    #[test]
+    fn nesting_past_max_depth() {
+        let s = Spell::from_text(&negated(250)).unwrap();
+        assert_eq!(Spell::from_text(&s.to_text()), Ok(s));
+        let e = Spell::from_text(&negated(260)).unwrap_err();
+        assert_eq!((e.pos.line, e.msg.as_str()), (2, "expressions are nested too deeply"));
+        let e = Spell::from_text(&"(".repeat(100000)).unwrap_err();
+        assert_eq!((e.pos.col, e.msg.as_str()), (257, "expressions are nested too deeply"));
+    }
+}
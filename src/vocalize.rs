@@ -1,9 +1,43 @@
-use std::fmt
+use std::fmt;
+use std::error::Error;
+use std::rc::Rc;
+use magic::*;
+use buffs::*;
 
-pub trait Vocalize {
-	fn vocalize()
-}
+// Spells spoken aloud. A spell becomes a paragraph of seven sentences, one for
+// each of on_cast, requires, on_cooldown, consumes, cast_time, on_cast_start
+// and on_interrupt, eg:
+//
+//   dona hostes ille za um vita num nesese. ver. . mana num suzi. num za. . .
+//
+// Every variant of every magic type has its own word (see the tables below),
+// and a value is spoken as its variant's word followed by its fields, in order.
+// Lists are closed with `um`, except at the top level of a sentence where the
+// `.` closes them. Numbers are spoken one digit at a time (za ze zi zo zu sa
+// se si so su for 0 to 9, `ne` for minus, `ka` for the decimal point, `ke` for
+// the exponent). Buffs are spoken by name. The same spell always gives the
+// same incantation, and since the words only depend on structure, spells that
+// look alike sound alike.
+//
+// Listening is the inverse. Which word is expected is always known from the
+// type being read, so a word like `fors` (Choose) is free to mean
+// a different variant in each type.
+
+const END: &str = "um";
+const STOP: &str = ".";
+const MAX_DEPTH: usize = 256;
+
+const DIGITS: [&str; 10] = ["za", "ze", "zi", "zo", "zu", "sa", "se", "si", "so", "su"];
+const MINUS: &str = "ne";
+const POINT: &str = "ka";
+const EXPONENT: &str = "ke";
+const INFINITY: &str = "ru";
+const NAN: &str = "ra";
 
+pub trait Vocalize: Sized {
+	fn vocalize(&self, v: &mut Vocalizer);
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError>;
+}
 
 pub struct Phoneme(String);
 pub struct Word(Vec<Phoneme>);
@@ -23,21 +57,717 @@ impl Paragraph {
 			}
 			s.push('.');
 		}
-		if s.len() > 0 {
+		if s.starts_with(' ') {
 			s.remove(0); //remove leading space
 		}
 		s
 	}
 }
 
+impl fmt::Display for Paragraph {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.serialize())
+	}
+}
 
-pub struct Vocalizer {
+pub fn incantation(spell: &Spell) -> Paragraph {
+	let mut v = Vocalizer { sentences: vec![], words: vec![] };
+	spell.vocalize(&mut v);
+	Paragraph(v.sentences)
+}
+
+pub fn parse_incantation(src: &str) -> Result<Spell, IncantationError> {
+	let mut l = Listener { words: vec![], next: 0, depth: 0 };
+	for (i, sentence) in src.split('.').enumerate() {
+		let mut j = 0;
+		for w in sentence.split_whitespace() {
+			l.words.push((i, j, w));
+			j += 1;
+		}
+		l.words.push((i, j, STOP));
+	}
+	// split leaves what follows the last `.`, which must be blank
+	l.words.pop();
+	let spell = Spell::listen(&mut l)?;
+	match l.peek() {
+		Some(w) => Err(l.error(format!("unexpected `{}` after the spell", w))),
+		None => Ok(spell),
+	}
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct IncantationError {
+	pub sentence: usize, // 0-based
+	pub word: usize, // 0-based, within the sentence
+	pub msg: String,
+}
+
+impl IncantationError {
+	fn new<S: Into<String>>(sentence: usize, word: usize, msg: S) -> IncantationError {
+		IncantationError { sentence, word, msg: msg.into() }
+	}
+}
+
+impl fmt::Display for IncantationError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "sentence {}, word {}: {}", self.sentence + 1, self.word + 1, self.msg)
+	}
+}
+
+impl Error for IncantationError {}
 
+/////////////////////////////////////////////////////////////////////////
+
+pub struct Vocalizer {
+	sentences: Vec<Sentence>,
+	words: Vec<Word>,
 }
 
 impl Vocalizer {
-	pub fn utter(&mut self, Phoneme) {
+	pub fn utter(&mut self, word: &str) {
+		self.words.push(Word(vec![Phoneme(word.to_owned())]));
+	}
+
+	pub fn full_stop(&mut self) {
+		let words = ::std::mem::take(&mut self.words);
+		self.sentences.push(Sentence(words));
+	}
+
+	fn number(&mut self, s: &str) {
+		let mut phonemes = vec![];
+		let mut rest = s;
+		while let Some(c) = rest.chars().next() {
+			let (p, len) = match c {
+				'-' => (MINUS, 1),
+				'.' => (POINT, 1),
+				'e' => (EXPONENT, 1),
+				'i' => (INFINITY, 3), // inf
+				'N' => (NAN, 3),
+				d => (DIGITS[d.to_digit(10).expect("numbers only print digits") as usize], 1),
+			};
+			phonemes.push(Phoneme(p.to_owned()));
+			rest = &rest[len..];
+		}
+		self.words.push(Word(phonemes));
+	}
+
+	fn list<T: Vocalize>(&mut self, v: &[T]) {
+		for x in v {
+			x.vocalize(self);
+		}
+		self.utter(END);
+	}
+}
+
+pub struct Listener<'a> {
+	words: Vec<(usize, usize, &'a str)>, // sentence, word, the word itself
+	next: usize,
+	depth: usize,
+}
+
+impl<'a> Listener<'a> {
+	fn peek(&self) -> Option<&'a str> {
+		self.words.get(self.next).map(|w| w.2)
+	}
+
+	fn error<S: Into<String>>(&self, msg: S) -> IncantationError {
+		match self.words.get(self.next).or(self.words.last()) {
+			Some(&(sentence, word, _)) => IncantationError::new(sentence, word, msg),
+			None => IncantationError::new(0, 0, msg),
+		}
+	}
+
+	fn hear(&mut self, what: &str) -> Result<&'a str, IncantationError> {
+		match self.peek() {
+			Some(w) if w != STOP => {
+				self.next += 1;
+				Ok(w)
+			},
+			_ => Err(self.error(format!("expected {}, but the sentence ends", what))),
+		}
+	}
 
+	fn unknown(&mut self, what: &str, w: &str) -> IncantationError {
+		self.next -= 1;
+		self.error(format!("`{}` does not name {}", w, what))
+	}
+
+	fn nested<T, F>(&mut self, f: F) -> Result<T, IncantationError>
+	where F: FnOnce(&mut Self) -> Result<T, IncantationError> {
+		if self.depth >= MAX_DEPTH {
+			return Err(self.error("the incantation is nested too deeply"));
+		}
+		self.depth += 1;
+		let x = f(self);
+		self.depth -= 1;
+		x
+	}
+
+	fn boxed<T: Vocalize>(&mut self) -> Result<Box<T>, IncantationError> {
+		self.nested(|l| T::listen(l).map(Box::new))
+	}
+
+	fn list<T: Vocalize>(&mut self) -> Result<Vec<T>, IncantationError> {
+		self.nested(|l| {
+			let mut v = vec![];
+			while l.peek() != Some(END) {
+				if l.peek() == Some(STOP) {
+					return Err(l.error(format!("expected `{}` before the end of the sentence", END)));
+				}
+				v.push(T::listen(l)?);
+			}
+			l.next += 1;
+			Ok(v)
+		})
+	}
+
+	// the top level of a sentence, which the `.` closes
+	fn sentence<T: Vocalize>(&mut self) -> Result<Vec<T>, IncantationError> {
+		let mut v = vec![];
+		while self.peek() != Some(STOP) {
+			v.push(T::listen(self)?);
+		}
+		self.next += 1;
+		Ok(v)
+	}
+
+	fn full_stop(&mut self) -> Result<(), IncantationError> {
+		match self.peek() {
+			Some(STOP) => {
+				self.next += 1;
+				Ok(())
+			},
+			Some(w) => Err(self.error(format!("expected `.`, found `{}`", w))),
+			None => Err(self.error("expected `.`")),
+		}
+	}
+
+	fn number(&mut self, what: &str) -> Result<String, IncantationError> {
+		let w = self.hear(what)?;
+		let mut s = String::new();
+		let mut rest = w;
+		while rest.len() >= 2 && rest.is_char_boundary(2) {
+			let p = &rest[..2];
+			match DIGITS.iter().position(|d| *d == p) {
+				Some(d) => s.push_str(&d.to_string()),
+				None if p == MINUS => s.push('-'),
+				None if p == POINT => s.push('.'),
+				None if p == EXPONENT => s.push('e'),
+				None if p == INFINITY => s.push_str("inf"),
+				None if p == NAN => s.push_str("NaN"),
+				None => break,
+			}
+			rest = &rest[2..];
+		}
+		if rest.is_empty() && !s.is_empty() {
+			Ok(s)
+		} else {
+			Err(self.unknown(what, w))
+		}
+	}
+}
+
+/////////////////////////////////////////////////////////////////////////
+
+impl Vocalize for i32 {
+	fn vocalize(&self, v: &mut Vocalizer) { v.number(&self.to_string()) }
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		let s = l.number("an integer")?;
+		s.parse().map_err(|_| { l.next -= 1; l.error(format!("{} is not an integer", s)) })
+	}
+}
+
+impl Vocalize for u8 {
+	fn vocalize(&self, v: &mut Vocalizer) { v.number(&self.to_string()) }
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		let s = l.number("a small number")?;
+		s.parse().map_err(|_| { l.next -= 1; l.error(format!("{} is not in 0..255", s)) })
+	}
+}
+
+impl Vocalize for F32 {
+	fn vocalize(&self, v: &mut Vocalizer) { v.number(&format!("{:?}", self.0)) }
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		let s = l.number("a decimal number")?;
+		s.parse().map(F32).map_err(|_| { l.next -= 1; l.error(format!("{} is not a decimal number", s)) })
+	}
+}
+
+impl Vocalize for ESlot {
+	fn vocalize(&self, v: &mut Vocalizer) { self.0.vocalize(v) }
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> { u8::listen(l).map(ESlot) }
+}
+
+impl Vocalize for ESetSlot {
+	fn vocalize(&self, v: &mut Vocalizer) { self.0.vocalize(v) }
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> { u8::listen(l).map(ESetSlot) }
+}
+
+impl Vocalize for DSlot {
+	fn vocalize(&self, v: &mut Vocalizer) { self.0.vocalize(v) }
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> { u8::listen(l).map(DSlot) }
+}
+
+impl Vocalize for LSlot {
+	fn vocalize(&self, v: &mut Vocalizer) { self.0.vocalize(v) }
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> { u8::listen(l).map(LSlot) }
+}
+
+impl Vocalize for Buff {
+	fn vocalize(&self, v: &mut Vocalizer) { v.utter(&format!("{:?}", self).to_lowercase()) }
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		let w = l.hear("a buff")?;
+		match Buff::ALL.iter().find(|b| format!("{:?}", b).to_lowercase() == w) {
+			Some(&b) => Ok(b),
+			None => Err(l.unknown("a buff", w)),
+		}
 	}
-	pub fn 
-}
\ No newline at end of file
+}
+
+impl Vocalize for Spell {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		for i in self.on_cast.iter() {
+			i.vocalize(v);
+		}
+		v.full_stop();
+		self.requires.vocalize(v);
+		v.full_stop();
+		for i in self.on_cooldown.iter() {
+			i.vocalize(v);
+		}
+		v.full_stop();
+		for r in self.consumes.iter() {
+			r.vocalize(v);
+		}
+		v.full_stop();
+		self.cast_time.vocalize(v);
+		v.full_stop();
+		for i in self.on_cast_start.iter() {
+			i.vocalize(v);
+		}
+		v.full_stop();
+		for i in self.on_interrupt.iter() {
+			i.vocalize(v);
+		}
+		v.full_stop();
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		let on_cast = l.sentence()?;
+		let requires = l.boxed()?;
+		l.full_stop()?;
+		let on_cooldown = l.sentence()?;
+		let consumes = l.sentence()?;
+		let cast_time = Discrete::listen(l)?;
+		l.full_stop()?;
+		Ok(Spell {
+			on_cast,
+			requires,
+			on_cooldown,
+			consumes,
+			cast_time,
+			on_cast_start: l.sentence()?,
+			on_interrupt: l.sentence()?,
+		})
+	}
+}
+
+impl Vocalize for ProjectileBlueprint {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		v.list(&self.on_create);
+		v.list(&self.on_collision);
+		self.collides_with.vocalize(v);
+		v.list(&self.on_destroy);
+		self.lifetime.vocalize(v);
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		Ok(ProjectileBlueprint {
+			on_create: l.list()?,
+			on_collision: l.list()?,
+			collides_with: EntitySet::listen(l)?,
+			on_destroy: l.list()?,
+			lifetime: Discrete::listen(l)?,
+		})
+	}
+}
+
+impl Vocalize for Instruction {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::Instruction::*;
+		match *self {
+			Define(ref d) => { v.utter("vel"); d.vocalize(v) },
+			ITE(ref c, ref t, ref e) => { v.utter("sif"); c.vocalize(v); v.list(t); v.list(e) },
+			CallWith(ref d, ref i) => { v.utter("kal"); d.vocalize(v); v.list(i) },
+			ForEachAs(s, ref set, ref i) => { v.utter("omni"); s.vocalize(v); set.vocalize(v); v.list(i) },
+			DestroyWithoutEvent(ref e) => { v.utter("nix"); e.vocalize(v) },
+			Destroy(ref e) => { v.utter("mort"); e.vocalize(v) },
+			MoveEntity(ref e, ref loc) => { v.utter("trans"); e.vocalize(v); loc.vocalize(v) },
+			AddResource(ref e, ref r) => { v.utter("dona"); e.vocalize(v); r.vocalize(v) },
+			AddVelocity(ref e, ref dir, ref d) => { v.utter("impel"); e.vocalize(v); dir.vocalize(v); d.vocalize(v) },
+			SpawnProjectileAt(ref bp, ref loc) => { v.utter("jacta"); bp.vocalize(v); loc.vocalize(v) },
+			Nothing => v.utter("nil"),
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::Instruction::*;
+		let w = l.hear("an instruction")?;
+		Ok(match w {
+			"vel" => Define(Definition::listen(l)?),
+			"sif" => ITE(Condition::listen(l)?, l.list()?, l.list()?),
+			"kal" => CallWith(Definition::listen(l)?, l.list()?),
+			"omni" => ForEachAs(ESlot::listen(l)?, EntitySet::listen(l)?, l.list()?),
+			"nix" => DestroyWithoutEvent(Entity::listen(l)?),
+			"mort" => Destroy(Entity::listen(l)?),
+			"trans" => MoveEntity(Entity::listen(l)?, Location::listen(l)?),
+			"dona" => AddResource(Entity::listen(l)?, Resource::listen(l)?),
+			"impel" => AddVelocity(Entity::listen(l)?, Direction::listen(l)?, Discrete::listen(l)?),
+			"jacta" => SpawnProjectileAt(Rc::new(l.nested(ProjectileBlueprint::listen)?), Location::listen(l)?),
+			"nil" => Nothing,
+			_ => return Err(l.unknown("an instruction", w)),
+		})
+	}
+}
+
+impl Vocalize for Definition {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::Definition::*;
+		match *self {
+			ESet(s, ref x) => { v.utter("grex"); s.vocalize(v); x.vocalize(v) },
+			E(s, ref x) => { v.utter("ille"); s.vocalize(v); x.vocalize(v) },
+			D(s, ref x) => { v.utter("quot"); s.vocalize(v); x.vocalize(v) },
+			L(s, ref x) => { v.utter("ubi"); s.vocalize(v); x.vocalize(v) },
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::Definition::*;
+		let w = l.hear("a definition")?;
+		Ok(match w {
+			"grex" => ESet(ESetSlot::listen(l)?, EntitySet::listen(l)?),
+			"ille" => E(ESlot::listen(l)?, Entity::listen(l)?),
+			"quot" => D(DSlot::listen(l)?, Discrete::listen(l)?),
+			"ubi" => L(LSlot::listen(l)?, Location::listen(l)?),
+			_ => return Err(l.unknown("a definition", w)),
+		})
+	}
+}
+
+impl Vocalize for Direction {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::Direction::*;
+		match *self {
+			TowardLocation(ref a, ref b) => { v.utter("versus"); a.vocalize(v); b.vocalize(v) },
+			ConstRad(f) => { v.utter("ang"); f.vocalize(v) },
+			BetweenRad(a, b) => { v.utter("intang"); a.vocalize(v); b.vocalize(v) },
+			Choose(ref x) => { v.utter("fors"); v.list(x) },
+			ChooseWithinRadOf(ref d, f) => { v.utter("circang"); d.vocalize(v); f.vocalize(v) },
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::Direction::*;
+		let w = l.hear("a direction")?;
+		Ok(match w {
+			"versus" => TowardLocation(Location::listen(l)?, Location::listen(l)?),
+			"ang" => ConstRad(F32::listen(l)?),
+			"intang" => BetweenRad(F32::listen(l)?, F32::listen(l)?),
+			"fors" => Choose(l.list()?),
+			"circang" => ChooseWithinRadOf(l.boxed()?, F32::listen(l)?),
+			_ => return Err(l.unknown("a direction", w)),
+		})
+	}
+}
+
+impl Vocalize for Location {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::Location::*;
+		match *self {
+			AtEntity(ref e) => { v.utter("apud"); e.vocalize(v) },
+			Midpoint(ref x) => { v.utter("medi"); v.list(x) },
+			Choose(ref x) => { v.utter("fors"); v.list(x) },
+			LoadLocation(s) => { v.utter("ubi"); s.vocalize(v) },
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::Location::*;
+		let w = l.hear("a location")?;
+		Ok(match w {
+			"apud" => AtEntity(Entity::listen(l)?),
+			"medi" => Midpoint(l.list()?),
+			"fors" => Choose(l.list()?),
+			"ubi" => LoadLocation(LSlot::listen(l)?),
+			_ => return Err(l.unknown("a location", w)),
+		})
+	}
+}
+
+impl Vocalize for Condition {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::Condition::*;
+		match *self {
+			Nand(ref x) => { v.utter("nec"); v.list(x) },
+			And(ref x) => { v.utter("et"); v.list(x) },
+			Or(ref x) => { v.utter("aut"); v.list(x) },
+			Top => v.utter("ver"),
+			Bottom => v.utter("fals"),
+			Equals(ref a, ref b) => { v.utter("par"); a.vocalize(v); b.vocalize(v) },
+			LessThan(ref a, ref b) => { v.utter("minor"); a.vocalize(v); b.vocalize(v) },
+			MoreThan(ref a, ref b) => { v.utter("maior"); a.vocalize(v); b.vocalize(v) },
+			EntitySetCmp(ref c) => { v.utter("conf"); c.vocalize(v) },
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::Condition::*;
+		let w = l.hear("a condition")?;
+		Ok(match w {
+			"nec" => Nand(l.list()?),
+			"et" => And(l.list()?),
+			"aut" => Or(l.list()?),
+			"ver" => Top,
+			"fals" => Bottom,
+			"par" => Equals(Discrete::listen(l)?, Discrete::listen(l)?),
+			"minor" => LessThan(Discrete::listen(l)?, Discrete::listen(l)?),
+			"maior" => MoreThan(Discrete::listen(l)?, Discrete::listen(l)?),
+			"conf" => EntitySetCmp(::magic::EntitySetCmp::listen(l)?),
+			_ => return Err(l.unknown("a condition", w)),
+		})
+	}
+}
+
+impl Vocalize for EntitySetCmp {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::EntitySetCmp::*;
+		match *self {
+			Nand(ref x) => { v.utter("nec"); v.list(x) },
+			And(ref x) => { v.utter("et"); v.list(x) },
+			Or(ref x) => { v.utter("aut"); v.list(x) },
+			Subset(ref a, ref b) => { v.utter("sub"); a.vocalize(v); b.vocalize(v) },
+			Superset(ref a, ref b) => { v.utter("super"); a.vocalize(v); b.vocalize(v) },
+			Equal(ref a, ref b) => { v.utter("idem"); a.vocalize(v); b.vocalize(v) },
+			Contains(ref a, ref b) => { v.utter("tenet"); a.vocalize(v); b.vocalize(v) },
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::EntitySetCmp::*;
+		let w = l.hear("a comparison")?;
+		Ok(match w {
+			"nec" => Nand(l.list()?),
+			"et" => And(l.list()?),
+			"aut" => Or(l.list()?),
+			"sub" => Subset(EntitySet::listen(l)?, EntitySet::listen(l)?),
+			"super" => Superset(EntitySet::listen(l)?, EntitySet::listen(l)?),
+			"idem" => Equal(EntitySet::listen(l)?, EntitySet::listen(l)?),
+			"tenet" => Contains(EntitySet::listen(l)?, Entity::listen(l)?),
+			_ => return Err(l.unknown("a comparison", w)),
+		})
+	}
+}
+
+impl Vocalize for EntitySet {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::EntitySet::*;
+		match *self {
+			None(ref x) => { v.utter("nul"); v.list(x) },
+			And(ref x) => { v.utter("et"); v.list(x) },
+			Or(ref x) => { v.utter("aut"); v.list(x) },
+			Only(ref e) => { v.utter("sol"); e.vocalize(v) },
+			IsInSlot(s) => { v.utter("grex"); s.vocalize(v) },
+			WithinRangeOf(ref e, ref d) => { v.utter("prope"); e.vocalize(v); d.vocalize(v) },
+			HasMinResource(ref r) => { v.utter("habet"); r.vocalize(v) },
+			EnemiesOf(ref e) => { v.utter("hostes"); e.vocalize(v) },
+			AllBut(ref e) => { v.utter("praeter"); e.vocalize(v) },
+			IsHuman => v.utter("homo"),
+			IsProjectile => v.utter("telum"),
+			Empty => v.utter("vacu"),
+			Universe => v.utter("omnes"),
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::EntitySet::*;
+		let w = l.hear("a set of entities")?;
+		Ok(match w {
+			"nul" => None(l.list()?),
+			"et" => And(l.list()?),
+			"aut" => Or(l.list()?),
+			"sol" => Only(Entity::listen(l)?),
+			"grex" => IsInSlot(ESetSlot::listen(l)?),
+			"prope" => WithinRangeOf(Entity::listen(l)?, Discrete::listen(l)?),
+			"habet" => HasMinResource(Resource::listen(l)?),
+			"hostes" => EnemiesOf(Entity::listen(l)?),
+			"praeter" => AllBut(Entity::listen(l)?),
+			"homo" => IsHuman,
+			"telum" => IsProjectile,
+			"vacu" => Empty,
+			"omnes" => Universe,
+			_ => return Err(l.unknown("a set of entities", w)),
+		})
+	}
+}
+
+impl Vocalize for Discrete {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::Discrete::*;
+		match *self {
+			Const(c) => { v.utter("num"); c.vocalize(v) },
+			Range(a, b) => { v.utter("inter"); a.vocalize(v); b.vocalize(v) },
+			WithinPercent(a, f) => { v.utter("circa"); a.vocalize(v); f.vocalize(v) },
+			Div(ref a, ref b) => { v.utter("divi"); a.vocalize(v); b.vocalize(v) },
+			Sum(ref x) => { v.utter("summa"); v.list(x) },
+			Neg(ref a) => { v.utter("contra"); a.vocalize(v) },
+			Mult(ref x) => { v.utter("multi"); v.list(x) },
+			Max(ref x) => { v.utter("maxi"); v.list(x) },
+			Min(ref x) => { v.utter("mini"); v.list(x) },
+			CountStacks(b, ref t, ref e) => { v.utter("cumul"); b.vocalize(v); t.vocalize(v); e.vocalize(v) },
+			CountDur(b, ref t, ref e) => { v.utter("dura"); b.vocalize(v); t.vocalize(v); e.vocalize(v) },
+			Choose(ref x) => { v.utter("fors"); v.list(x) },
+			Cardinality(ref s) => { v.utter("numer"); s.vocalize(v) },
+			LoadFrom(s) => { v.utter("quot"); s.vocalize(v) },
+			CooldownLeft(i, ref e) => { v.utter("mora"); i.vocalize(v); e.vocalize(v) },
+			Charges(i, ref e) => { v.utter("onus"); i.vocalize(v); e.vocalize(v) },
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::Discrete::*;
+		let w = l.hear("a number")?;
+		Ok(match w {
+			"num" => Const(i32::listen(l)?),
+			"inter" => Range(i32::listen(l)?, i32::listen(l)?),
+			"circa" => WithinPercent(i32::listen(l)?, F32::listen(l)?),
+			"divi" => Div(l.boxed()?, l.boxed()?),
+			"summa" => Sum(l.list()?),
+			"contra" => Neg(l.boxed()?),
+			"multi" => Mult(l.list()?),
+			"maxi" => Max(l.list()?),
+			"mini" => Min(l.list()?),
+			"cumul" => CountStacks(Buff::listen(l)?, BuffTier::listen(l)?, Entity::listen(l)?),
+			"dura" => CountDur(Buff::listen(l)?, BuffTier::listen(l)?, Entity::listen(l)?),
+			"fors" => Choose(l.list()?),
+			"numer" => Cardinality(l.boxed()?),
+			"quot" => LoadFrom(DSlot::listen(l)?),
+			"mora" => CooldownLeft(u8::listen(l)?, Entity::listen(l)?),
+			"onus" => Charges(u8::listen(l)?, Entity::listen(l)?),
+			_ => return Err(l.unknown("a number", w)),
+		})
+	}
+}
+
+impl Vocalize for Resource {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::Resource::*;
+		match *self {
+			Mana(ref d) => { v.utter("mana"); d.vocalize(v) },
+			Health(ref d) => { v.utter("vita"); d.vocalize(v) },
+			BuffStacks(b, ref t, ref d) => { v.utter("affec"); b.vocalize(v); t.vocalize(v); d.vocalize(v) },
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::Resource::*;
+		let w = l.hear("a resource")?;
+		Ok(match w {
+			"mana" => Mana(Discrete::listen(l)?),
+			"vita" => Health(Discrete::listen(l)?),
+			"affec" => BuffStacks(Buff::listen(l)?, BuffTier::listen(l)?, Discrete::listen(l)?),
+			_ => return Err(l.unknown("a resource", w)),
+		})
+	}
+}
+
+impl Vocalize for BuffTier {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		match *self {
+			BuffTier::Tier(ref d) => { v.utter("gradus"); d.vocalize(v) },
+			BuffTier::AnyTier => v.utter("quivis"),
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		let w = l.hear("a tier")?;
+		Ok(match w {
+			"gradus" => BuffTier::Tier(l.boxed()?),
+			"quivis" => BuffTier::AnyTier,
+			_ => return Err(l.unknown("a tier", w)),
+		})
+	}
+}
+
+impl Vocalize for Entity {
+	fn vocalize(&self, v: &mut Vocalizer) {
+		use magic::Entity::*;
+		match *self {
+			LoadEntity(s) => { v.utter("ille"); s.vocalize(v) },
+			FirstOf(ref s) => { v.utter("prim"); s.vocalize(v) },
+			Choose(ref s) => { v.utter("fors"); s.vocalize(v) },
+			ClosestFrom(ref s, ref loc) => { v.utter("proxi"); s.vocalize(v); loc.vocalize(v) },
+		}
+	}
+	fn listen(l: &mut Listener) -> Result<Self, IncantationError> {
+		use magic::Entity::*;
+		let w = l.hear("an entity")?;
+		Ok(match w {
+			"ille" => LoadEntity(ESlot::listen(l)?),
+			"prim" => FirstOf(l.boxed()?),
+			"fors" => Choose(l.boxed()?),
+			"proxi" => ClosestFrom(l.boxed()?, l.boxed()?),
+			_ => return Err(l.unknown("an entity", w)),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::{Rng, Isaac64Rng, SeedableRng};
+	use generate;
+	use super::*;
+
+	#[test]
+	fn codec() {
+		generate::check_codec(5, |s| incantation(s).serialize().into_bytes(), |b| parse_incantation(&String::from_utf8_lossy(b)));
+	}
+
+	fn spoken<T: Vocalize>(x: &T) -> String {
+		let mut v = Vocalizer { sentences: vec![], words: vec![] };
+		x.vocalize(&mut v);
+		v.words.iter().flat_map(|w| w.0.iter()).map(|p| &p.0[..]).collect()
+	}
+
+	fn heard<T: Vocalize>(word: &str) -> Result<T, IncantationError> {
+		let mut l = Listener { words: vec![(0, 0, word)], next: 0, depth: 0 };
+		T::listen(&mut l)
+	}
+
+	// a number is one word of two letter phonemes, which split only one way
+	#[test]
+	fn numbers_split_one_way() {
+		for &x in [0, 7, -10, 255, i32::MIN, i32::MAX].iter() {
+			assert_eq!(heard::<i32>(&spoken(&x)), Ok(x));
+		}
+		for &x in [0.5, -0.0, 1e-40, 3.4e38, f32::INFINITY, f32::NEG_INFINITY, f32::NAN].iter() {
+			assert_eq!(heard::<F32>(&spoken(&F32(x))).map(|y| y.0.to_bits()), Ok(x.to_bits()));
+		}
+		assert_eq!(spoken(&-105), "nezezasa");
+		// half a phoneme, one cut in two, or phonemes in an order no number has
+		for w in ["z", "zaz", "zé", "zane", "nene", "kaze", "zakeze", "ru", "ra", "dona"].iter() {
+			assert!(heard::<i32>(w).is_err(), "{}", w);
+		}
+		for w in ["zaz", "zé", "zane", "kaka", "rara", "ruza", "zakeke"].iter() {
+			assert!(heard::<F32>(w).is_err(), "{}", w);
+		}
+		// 256
+		assert!(heard::<u8>("zisase").is_err());
+	}
+
+	// words that are real, in an order that isn't
+	#[test]
+	fn random() {
+		let mut rng = Isaac64Rng::from_seed(&[7]);
+		let mut vocabulary: Vec<String> = generate::samples(10).iter()
+			.flat_map(|s| incantation(s).serialize().split_whitespace().map(|w| w.to_string()).collect::<Vec<_>>())
+			.collect();
+		vocabulary.sort();
+		vocabulary.dedup();
+		for _ in 0..500 {
+			let len = rng.gen_range(0, 100);
+			let src: Vec<&str> = (0..len).map(|_| &rng.choose(&vocabulary).unwrap()[..]).collect();
+			let src = src.join(" ");
+			assert!(parse_incantation(&src).is_err(), "{}", src);
+		}
+		// a cast_time of contra contra contra ...
+		assert!(parse_incantation(&format!(". ver. . . {}. . .", "contra ".repeat(100000))).is_err());
+	}
+}
@@ -1,5 +1,6 @@
 
 
+#[allow(dead_code)]
 #[derive(Copy, Clone, Debug)]
 pub struct WasdSet {
     w: Setting,
@@ -67,7 +68,6 @@ impl WasdSet {
     }
 
     pub fn direction(&self) -> WasdDirection {
-        use self::Setting::*;
         use self::WasdDirection::*;
         if self.is_pressed_w() {
             //W..
@@ -99,4 +99,28 @@ pub enum WasdDirection {
     Nothing,
     W, A, S, D,
     WA, WD, SA, SD,
+}
+
+impl WasdDirection {
+    pub const ALL: [WasdDirection; 9] = {
+        use self::WasdDirection::*;
+        [Nothing, W, A, S, D, WA, WD, SA, SD]
+    };
+
+    // radians, with y pointing down the screen. None if not moving
+    pub fn angle(&self) -> Option<f32> {
+        use self::WasdDirection::*;
+        use ::std::f32::consts::PI;
+        Some(match *self {
+            Nothing => return None,
+            W => PI*1.5,
+            A => PI*1.0,
+            S => PI*0.5,
+            D => PI*0.0,
+            WA => PI*1.25,
+            WD => PI*1.75,
+            SA => PI*0.75,
+            SD => PI*0.25,
+        })
+    }
 }
\ No newline at end of file